@@ -1,7 +1,258 @@
-struct APU {}
+const DMC_FLAGS: u16 = 0x4010;
+const DMC_ADDRESS: u16 = 0x4012;
+const DMC_LENGTH: u16 = 0x4013;
+const STATUS: u16 = 0x4015;
+const FRAME_COUNTER: u16 = 0x4017;
+
+const FRAME_IRQ_INHIBIT: u8 = 0x40;
+const DMC_IRQ_ENABLE: u8 = 0x80;
+const DMC_LOOP: u8 = 0x40;
+const DMC_ENABLE: u8 = 0x10;
+
+// CPU cycles between DMC output bits, NTSC
+const DMC_RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+/// The memory reader and output timer of the delta modulation channel,
+/// enough to know when it steals the bus for a sample fetch.
+struct Dmc {
+    address: u16,         // next sample byte
+    bytes_remaining: u16, // zero once the sample has been read out
+    buffer: Option<u8>,   // an empty buffer asks for a DMA fetch
+    timer: u16,
+    bits_remaining: u8,
+}
+
+/// The CPU facing side of the 2A03 APU.
+///
+/// Sound generation is not emulated yet. Registers are stored as written and
+/// the status register reports a channel as active while it is enabled. The
+/// DMC sample reader is emulated so its DMA fetches happen on time.
+pub struct APU {
+    pub registers: [u8; 0x14], // $4000-$4013
+    channels_enabled: u8,
+    frame_counter: u8,
+    frame_irq: bool,
+    dmc_irq: bool,
+    dmc: Dmc,
+}
+
+impl APU {
+    pub fn new() -> Self {
+        APU {
+            registers: [0; 0x14],
+            channels_enabled: 0,
+            frame_counter: 0,
+            frame_irq: false,
+            dmc_irq: false,
+            dmc: Dmc {
+                address: 0xC000,
+                bytes_remaining: 0,
+                buffer: None,
+                timer: DMC_RATES[0],
+                bits_remaining: 8,
+            },
+        }
+    }
+
+    /// Advances the channel timers by `cycles` CPU cycles.
+    pub fn clock(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.dmc.timer -= 1;
+            if self.dmc.timer == 0 {
+                self.dmc.timer = DMC_RATES[(self.register(DMC_FLAGS) & 0x0F) as usize];
+                self.clock_dmc_output();
+            }
+        }
+    }
+
+    /// Shifts out one bit. Every 8 bits the output unit empties the sample
+    /// buffer, which makes the reader request the next byte.
+    fn clock_dmc_output(&mut self) {
+        self.dmc.bits_remaining -= 1;
+        if self.dmc.bits_remaining == 0 {
+            self.dmc.bits_remaining = 8;
+            self.dmc.buffer = None;
+        }
+    }
+
+    /// Address of the sample byte the DMC waits for, while its buffer is empty
+    /// and the sample is not over.
+    pub fn dmc_request(&self) -> Option<u16> {
+        (self.dmc.buffer.is_none() && self.dmc.bytes_remaining > 0).then_some(self.dmc.address)
+    }
+
+    /// Completes the fetch asked for by `dmc_request`.
+    pub fn dmc_sample(&mut self, value: u8) {
+        self.dmc.buffer = Some(value);
+        // The address wraps to $8000, not $0000
+        self.dmc.address = self.dmc.address.checked_add(1).unwrap_or(0x8000);
+        self.dmc.bytes_remaining -= 1;
+        if self.dmc.bytes_remaining == 0 {
+            let flags = self.register(DMC_FLAGS);
+            if flags & DMC_LOOP != 0 {
+                self.restart_dmc();
+            } else if flags & DMC_IRQ_ENABLE != 0 {
+                self.dmc_irq = true;
+            }
+        }
+    }
+
+    fn register(&self, address: u16) -> u8 {
+        self.registers[(address - 0x4000) as usize]
+    }
+
+    fn restart_dmc(&mut self) {
+        self.dmc.address = 0xC000 | (self.register(DMC_ADDRESS) as u16) << 6;
+        self.dmc.bytes_remaining = ((self.register(DMC_LENGTH) as u16) << 4) + 1;
+    }
+
+    pub fn get_frame_counter(&self) -> u8 {
+        self.frame_counter
+    }
+
+    /// Raised by the frame counter in 4-step mode unless inhibited.
+    pub fn set_frame_irq(&mut self) {
+        if self.frame_counter & FRAME_IRQ_INHIBIT == 0 {
+            self.frame_irq = true;
+        }
+    }
+
+    pub fn irq_line(&self) -> bool {
+        self.frame_irq || self.dmc_irq
+    }
+
+    /// CPU read of $4015. Acknowledges the frame IRQ.
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.frame_irq = false;
+        status
+    }
+
+    /// $4015 without acknowledging anything. Bit 5 is not driven.
+    pub fn peek_status(&self) -> u8 {
+        (self.channels_enabled & 0x0F)
+            | if self.dmc.bytes_remaining > 0 {
+                DMC_ENABLE
+            } else {
+                0
+            }
+            | if self.frame_irq { 0x40 } else { 0 }
+            | if self.dmc_irq { 0x80 } else { 0 }
+    }
+
+    /// CPU write of $4000-$4013, $4015 or $4017.
+    pub fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x4000..=0x4013 => {
+                self.registers[(address - 0x4000) as usize] = value;
+                if address == DMC_FLAGS && value & DMC_IRQ_ENABLE == 0 {
+                    self.dmc_irq = false;
+                }
+            }
+            STATUS => {
+                self.channels_enabled = value & 0x1F;
+                self.dmc_irq = false;
+                if value & DMC_ENABLE == 0 {
+                    self.dmc.bytes_remaining = 0;
+                } else if self.dmc.bytes_remaining == 0 {
+                    self.restart_dmc();
+                }
+            }
+            FRAME_COUNTER => {
+                self.frame_counter = value;
+                if value & FRAME_IRQ_INHIBIT != 0 {
+                    self.frame_irq = false;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    #[test]
+    fn test_construct_apu() {
+        let apu = APU::new();
+        assert!(!apu.irq_line());
+    }
+
+    #[test]
+    fn status_read_acknowledges_frame_irq() {
+        let mut apu = APU::new();
+        apu.write_register(STATUS, 0x05);
+        apu.set_frame_irq();
+        assert_eq!(apu.peek_status(), 0x45);
+        assert_eq!(apu.read_status(), 0x45);
+        assert_eq!(apu.read_status(), 0x05);
+        assert!(!apu.irq_line());
+    }
+
+    #[test]
+    fn inhibit_blocks_and_clears_frame_irq() {
+        let mut apu = APU::new();
+        apu.set_frame_irq();
+        apu.write_register(FRAME_COUNTER, FRAME_IRQ_INHIBIT);
+        assert!(!apu.irq_line());
+        apu.set_frame_irq();
+        assert!(!apu.irq_line());
+    }
+
+    #[test]
+    fn dmc_requests_sample_bytes_until_the_end() {
+        let mut apu = APU::new();
+        apu.write_register(DMC_FLAGS, DMC_IRQ_ENABLE | 0x0F);
+        apu.write_register(DMC_ADDRESS, 0xFF);
+        apu.write_register(DMC_LENGTH, 0x00);
+        assert_eq!(apu.dmc_request(), None);
+        apu.write_register(STATUS, DMC_ENABLE);
+        assert_eq!(apu.peek_status(), DMC_ENABLE);
+        assert_eq!(apu.dmc_request(), Some(0xFFC0));
+        apu.dmc_sample(0x55);
+        // A single byte sample is over as soon as it is fetched
+        assert_eq!(apu.dmc_request(), None);
+        assert!(apu.irq_line());
+        assert_eq!(apu.peek_status(), 0x80);
+        apu.write_register(DMC_FLAGS, 0x0F);
+        assert!(!apu.irq_line());
+    }
+
+    #[test]
+    fn dmc_output_empties_the_buffer_every_8_bits() {
+        let mut apu = APU::new();
+        apu.write_register(DMC_FLAGS, 0x0F);
+        apu.write_register(DMC_LENGTH, 0x01);
+        apu.write_register(STATUS, DMC_ENABLE);
+        apu.dmc_sample(0x55);
+        // The timer was loaded with the power on rate, then runs at rate $F
+        apu.clock(428 + 54 * 6);
+        assert_eq!(apu.dmc_request(), None);
+        apu.clock(54);
+        assert_eq!(apu.dmc_request(), Some(0xC001));
+    }
+
     #[test]
-    fn test_construct_apu() {}
+    fn dmc_address_wraps_to_8000() {
+        let mut apu = APU::new();
+        apu.write_register(DMC_FLAGS, DMC_LOOP);
+        apu.write_register(DMC_ADDRESS, 0xFF);
+        apu.write_register(DMC_LENGTH, 0x04);
+        apu.write_register(STATUS, DMC_ENABLE);
+        for _ in 0..0x40 {
+            apu.dmc_request().unwrap();
+            apu.dmc_sample(0);
+            apu.clock(428 * 8);
+        }
+        assert_eq!(apu.dmc_request(), Some(0x8000));
+    }
 }
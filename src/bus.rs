@@ -1,48 +1,815 @@
+mod watch;
+
+use std::ops::RangeInclusive;
+
+use crate::apu::APU;
+use crate::cartridge::Mapper;
+use crate::error::EmuError;
+use crate::input::Input;
+use crate::ppu::PPU;
+use watch::Watchpoints;
+
+pub use watch::{BusAccess, Watch, WatchpointId};
+
 pub trait Memory {
-    fn read(&self, address: u16) -> u8;
+    /// Reads as the CPU does, with whatever side effects the access has on
+    /// the device behind `address`.
+    fn read(&mut self, address: u16) -> u8;
+
+    /// Returns what `read` would without side effects, for debuggers,
+    /// disassemblers and tracers.
+    fn peek(&self, address: u16) -> u8;
+
     fn write(&mut self, address: u16, value: u8);
+
+    /// Returns and clears the first fault since the last call, such as an
+    /// access to an unmapped address. Buses that cannot fault keep the default.
+    fn take_fault(&mut self) -> Option<EmuError> {
+        None
+    }
+
+    /// Returns a DMA transfer waiting for the CPU to halt. The CPU performs
+    /// it before its next read, see `Dma`.
+    fn take_dma(&mut self) -> Option<Dma> {
+        None
+    }
+
+    /// Hands the byte fetched by a `Dma::Dmc` transfer to the APU.
+    fn dmc_sample(&mut self, _value: u8) {}
+
+    /// Called by the CPU before it fetches the opcode at `pc`, on `cycle`.
+    fn begin_instruction(&mut self, _pc: u16, _cycle: u64) {}
+
+    /// Called by the CPU as it starts the NMI/IRQ sequence on `cycle`, in
+    /// place of the opcode fetch at `pc`, the address it will return to.
+    fn begin_interrupt(&mut self, _pc: u16, _cycle: u64) {}
+
+    /// Called by the CPU for cycles it spends without an access, the dummy
+    /// accesses `step` leaves out and DMA waiting for its turn.
+    fn skip_cycles(&mut self, _cycles: u64) {}
 }
 
+/// A transfer that halts the CPU to use the bus for itself.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Dma {
+    /// Copy $XX00-$XXFF to OAM through $2004, requested by a write to $4014.
+    Oam(u8),
+    /// Fetch the next DMC sample byte from this address.
+    Dmc(u16),
+}
+
+/// How long the open bus value survives once nothing drives the data bus.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum OpenBusDecay {
+    /// Keep the last value forever. The CPU drives the bus on every cycle, so
+    /// in practice this is what games see.
+    #[default]
+    Never,
+    /// Read back zero once the bus has not been driven for this many CPU cycles.
+    After(u64),
+}
+
+/// The CPU address space of the NES.
+///
+/// | Range         | Device                                    |
+/// |---------------|-------------------------------------------|
+/// | $0000-$1FFF   | 2KB internal RAM, mirrored every $0800    |
+/// | $2000-$3FFF   | PPU registers, mirrored every 8 bytes     |
+/// | $4000-$4017   | APU and I/O registers                     |
+/// | $4018-$401F   | APU test mode, disabled on retail units   |
+/// | $4020-$FFFF   | Cartridge, handled by the mapper          |
+///
+/// Nothing drives the data bus on reads from unmapped or write-only locations,
+/// so they return the last value on it (open bus). Registers that drive only
+/// some data lines fill the others from open bus as well.
+///
+/// Games read open bus legally, from $4018-$401F or disabled PRG-RAM say, so
+/// such accesses only raise faults once `set_strict_access` asks for them.
 pub struct Bus {
     pub ram: [u8; 0x800],
-    pub ppu: [u8; 0x7],
+    pub ppu: PPU,
+    pub apu: APU,
+    pub input: Input,
+    pub mapper: Option<Box<dyn Mapper>>,
+    fault: Option<EmuError>,
+    oam_dma: Option<u8>, // page written to $4014
+    watchpoints: Watchpoints,
+    data_bus: u8,
+    driven_at: u64, // cycle the data bus was last driven
+    cycles: u64,
+    open_bus_decay: OpenBusDecay,
+    strict_access: bool,
+    access_cycle: u64, // CPU cycle of the next access, for watchpoints
 }
 
 impl Bus {
     pub fn new() -> Self {
         Bus {
             ram: [0u8; 0x800],
-            ppu: [0u8; 7],
+            ppu: PPU::new(),
+            apu: APU::new(),
+            input: Input::new(),
+            mapper: None,
+            fault: None,
+            oam_dma: None,
+            watchpoints: Watchpoints::default(),
+            data_bus: 0,
+            driven_at: 0,
+            cycles: 0,
+            open_bus_decay: OpenBusDecay::default(),
+            strict_access: false,
+            access_cycle: 0,
+        }
+    }
+
+    pub fn with_mapper(mapper: Box<dyn Mapper>) -> Self {
+        let mut bus = Bus::new();
+        bus.ppu.mirroring = mapper.mirroring();
+        bus.mapper = Some(mapper);
+        bus
+    }
+
+    pub fn get_open_bus_decay(&self) -> OpenBusDecay {
+        self.open_bus_decay
+    }
+
+    pub fn set_open_bus_decay(&mut self, decay: OpenBusDecay) {
+        self.open_bus_decay = decay;
+    }
+
+    pub fn get_strict_access(&self) -> bool {
+        self.strict_access
+    }
+
+    /// Reports reads that nothing answers and writes that nothing takes as
+    /// `EmuError::UnmappedRead` and `UnmappedWrite` faults, see
+    /// `Memory::take_fault`. Off by default.
+    pub fn set_strict_access(&mut self, strict: bool) {
+        self.strict_access = strict;
+    }
+
+    /// Calls `callback` on every access of one of `kinds` to `range`, see
+    /// `BusAccess`.
+    pub fn add_watchpoint(
+        &mut self,
+        kinds: Watch,
+        range: RangeInclusive<u16>,
+        callback: impl FnMut(&BusAccess) + 'static,
+    ) -> WatchpointId {
+        self.watchpoints.add(kinds, range, Box::new(callback))
+    }
+
+    /// Returns false if there is no such watchpoint.
+    pub fn remove_watchpoint(&mut self, id: WatchpointId) -> bool {
+        self.watchpoints.remove(id)
+    }
+
+    /// Advances the bus clock by the CPU cycles the CPU just spent. The PPU
+    /// runs three dots per CPU cycle.
+    pub fn clock(&mut self, cycles: u64) {
+        self.cycles += cycles;
+        self.apu.clock(cycles);
+        for _ in 0..cycles {
+            for _ in 0..3 {
+                self.ppu.tick(self.mapper.as_deref_mut());
+            }
+            if let Some(mapper) = self.mapper.as_mut() {
+                mapper.cpu_cycle();
+            }
+        }
+    }
+
+    /// The shared /IRQ line, driven by the APU and the cartridge.
+    pub fn irq_line(&self) -> bool {
+        self.apu.irq_line() || self.mapper.as_ref().is_some_and(|m| m.irq())
+    }
+
+    /// The cartridge's expansion audio, see `Mapper::audio`.
+    pub fn expansion_audio(&self) -> f32 {
+        self.mapper.as_ref().map_or(0.0, |m| m.audio())
+    }
+
+    /// The value left on the data bus by the last access.
+    pub fn open_bus(&self) -> u8 {
+        match self.open_bus_decay {
+            OpenBusDecay::After(cycles) if self.cycles - self.driven_at >= cycles => 0,
+            _ => self.data_bus,
+        }
+    }
+
+    fn drive(&mut self, value: u8) {
+        self.data_bus = value;
+        self.driven_at = self.cycles;
+    }
+
+    fn read_device(&mut self, address: u16) -> u8 {
+        let open_bus = self.open_bus();
+        let value = match address {
+            0x0000..=0x1FFF => self.ram[(address & 0x07FF) as usize],
+            0x2000..=0x3FFF => self.ppu.read_register(address, self.mapper.as_deref_mut()),
+            // Write only
+            0x4000..=0x4014 => return open_bus,
+            // Bit 5 of the status is not driven
+            0x4015 => (self.apu.read_status() & !0x20) | (open_bus & 0x20),
+            // Controllers drive D0-D4 only
+            0x4016 | 0x4017 => {
+                (self.input.read((address & 0x0001) as usize) & 0x1F) | (open_bus & 0xE0)
+            }
+            0x4018..=0x401F => return self.unmapped_read(address),
+            0x4020..=0xFFFF => match self.mapper.as_mut().and_then(|m| m.cpu_read(address)) {
+                Some(value) => value,
+                None => return self.unmapped_read(address),
+            },
+        };
+        self.drive(value);
+        value
+    }
+
+    fn unmapped_read(&mut self, address: u16) -> u8 {
+        self.record_fault(EmuError::UnmappedRead { address });
+        self.open_bus()
+    }
+
+    /// The CPU makes one access per cycle, so count them, and the cycles it
+    /// skips, from the last `begin_instruction` or `begin_interrupt`.
+    fn next_access_cycle(&mut self) -> u64 {
+        let cycle = self.access_cycle;
+        self.access_cycle += 1;
+        cycle
+    }
+
+    /// Keeps the first fault, it usually explains the ones that follow.
+    fn record_fault(&mut self, error: EmuError) {
+        if self.strict_access {
+            self.fault.get_or_insert(error);
         }
     }
 }
 
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Memory for Bus {
-    fn read(&self, address: u16) -> u8 {
+    fn read(&mut self, address: u16) -> u8 {
+        let cycle = self.next_access_cycle();
+        let value = self.read_device(address);
+        self.watchpoints.notify(Watch::READ, address, value, cycle);
+        value
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        let open_bus = self.open_bus();
         match address {
-            0x0000..=0x07ff => self.ram[(address & 0x07FF) as usize],
-            0x2000..=0x2007 => self.ppu[(address & 0x0001) as usize],
-            _ => panic!("Not implemented yet."),
+            0x0000..=0x1FFF => self.ram[(address & 0x07FF) as usize],
+            0x2000..=0x3FFF => self.ppu.peek_register(address),
+            0x4000..=0x4014 | 0x4018..=0x401F => open_bus,
+            0x4015 => (self.apu.peek_status() & !0x20) | (open_bus & 0x20),
+            0x4016 | 0x4017 => {
+                (self.input.peek((address & 0x0001) as usize) & 0x1F) | (open_bus & 0xE0)
+            }
+            0x4020..=0xFFFF => self
+                .mapper
+                .as_ref()
+                .and_then(|m| m.cpu_peek(address))
+                .unwrap_or(open_bus),
         }
     }
 
     fn write(&mut self, address: u16, value: u8) {
+        self.drive(value);
         match address {
-            0x0000..=0x07ff => self.ram[(address & 0x07FF) as usize] = value,
-            0x2000..=0x2007 => self.ppu[(address & 0x000F) as usize] = value,
-            _ => panic!("Not implemented yet."),
+            0x0000..=0x1FFF => self.ram[(address & 0x07FF) as usize] = value,
+            0x2000..=0x3FFF => {
+                if let Some(mapper) = self.mapper.as_mut() {
+                    mapper.ppu_register_write(address, value);
+                }
+                self.ppu
+                    .write_register(address, value, self.mapper.as_deref_mut());
+            }
+            0x4014 => self.oam_dma = Some(value),
+            0x4016 => self.input.write_strobe(value),
+            0x4000..=0x4017 => self.apu.write_register(address, value),
+            0x4018..=0x401F => self.record_fault(EmuError::UnmappedWrite { address, value }),
+            0x4020..=0xFFFF => match self.mapper.as_mut() {
+                Some(mapper) => {
+                    mapper.cpu_write(address, value);
+                    self.ppu.mirroring = mapper.mirroring();
+                }
+                None => self.record_fault(EmuError::UnmappedWrite { address, value }),
+            },
         }
+        let cycle = self.next_access_cycle();
+        self.watchpoints.notify(Watch::WRITE, address, value, cycle);
+    }
+
+    fn take_fault(&mut self) -> Option<EmuError> {
+        self.fault.take()
+    }
+
+    /// The DMC goes first, a late sample fetch is audible while a late OAM
+    /// copy is not.
+    fn take_dma(&mut self) -> Option<Dma> {
+        match self.apu.dmc_request() {
+            Some(address) => Some(Dma::Dmc(address)),
+            None => self.oam_dma.take().map(Dma::Oam),
+        }
+    }
+
+    fn dmc_sample(&mut self, value: u8) {
+        self.apu.dmc_sample(value);
+    }
+
+    fn begin_instruction(&mut self, pc: u16, cycle: u64) {
+        let opcode = self.peek(pc);
+        self.access_cycle = cycle;
+        self.watchpoints.begin_instruction(pc, cycle, opcode);
+    }
+
+    fn begin_interrupt(&mut self, pc: u16, cycle: u64) {
+        self.access_cycle = cycle;
+        self.watchpoints.begin_interrupt(pc);
+    }
+
+    fn skip_cycles(&mut self, cycles: u64) {
+        self.access_cycle += cycles;
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
     use super::*;
+    use crate::cartridge::Mirroring;
+    use crate::cpu::CPU;
+    use crate::input::Buttons;
+
+    /// 8KB of PRG-RAM at $6000, nothing else.
+    struct WorkRam {
+        ram: [u8; 0x2000],
+    }
+
+    impl Mapper for WorkRam {
+        fn cpu_peek(&self, address: u16) -> Option<u8> {
+            (0x6000..=0x7FFF)
+                .contains(&address)
+                .then(|| self.ram[(address - 0x6000) as usize])
+        }
+
+        fn cpu_write(&mut self, address: u16, value: u8) {
+            if (0x6000..=0x7FFF).contains(&address) {
+                self.ram[(address - 0x6000) as usize] = value;
+            }
+        }
+
+        fn ppu_peek(&self, _address: u16) -> u8 {
+            0
+        }
+
+        fn ppu_write(&mut self, _address: u16, _value: u8) {}
+
+        fn mirroring(&self) -> Mirroring {
+            Mirroring::Horizontal
+        }
+    }
+
+    /// CHR-RAM, mirroring selected by writes to $5000, and a log of the PPU
+    /// addresses and CPU cycles it is told about. Raises IRQ at cycle 10.
+    #[derive(Default)]
+    struct Probe {
+        chr: Vec<u8>,
+        mirroring: Mirroring,
+        ppu_addresses: Rc<RefCell<Vec<u16>>>,
+        cycles: u64,
+    }
+
+    impl Mapper for Probe {
+        fn cpu_peek(&self, _address: u16) -> Option<u8> {
+            None
+        }
+
+        fn cpu_write(&mut self, address: u16, value: u8) {
+            if address == 0x5000 {
+                self.mirroring = if value & 1 != 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+        }
+
+        fn ppu_peek(&self, address: u16) -> u8 {
+            self.chr[address as usize]
+        }
+
+        fn ppu_write(&mut self, address: u16, value: u8) {
+            self.chr[address as usize] = value;
+        }
+
+        fn mirroring(&self) -> Mirroring {
+            self.mirroring
+        }
+
+        fn irq(&self) -> bool {
+            self.cycles >= 10
+        }
+
+        fn cpu_cycle(&mut self) {
+            self.cycles += 1;
+        }
+
+        fn ppu_address(&mut self, address: u16) {
+            self.ppu_addresses.borrow_mut().push(address);
+        }
+    }
 
     #[test]
     fn test_construct_bus() {
         let bus = Bus::new();
         assert_eq!(bus.ram.len(), 2048);
-        assert_eq!(bus.ppu.len(), 7);
+        assert!(bus.mapper.is_none());
+    }
+
+    #[test]
+    fn ram_is_mirrored_every_2kb() {
+        let mut bus = Bus::new();
+        bus.write(0x0801, 0x42);
+        for mirror in [0x0001, 0x0801, 0x1001, 0x1801] {
+            assert_eq!(bus.read(mirror), 0x42);
+        }
+        bus.write(0x1FFF, 0x17);
+        assert_eq!(bus.ram[0x07FF], 0x17);
+    }
+
+    #[test]
+    fn ppu_registers_are_mirrored_every_8_bytes() {
+        let mut bus = Bus::new();
+        // PPUADDR, PPUADDR, PPUDATA through mirrors
+        bus.write(0x200E, 0x21);
+        bus.write(0x3FF6, 0x08);
+        bus.write(0x3FFF, 0x99);
+        assert_eq!(bus.ppu.peek_memory(0x2108, None), 0x99);
+        bus.ppu.set_vblank(true);
+        assert_eq!(bus.read(0x300A) & 0x80, 0x80);
+        assert_eq!(bus.read(0x2002) & 0x80, 0x00);
+    }
+
+    #[test]
+    fn apu_and_io_registers_are_not_mirrored() {
+        let mut bus = Bus::new();
+        bus.write(0x4000, 0x30);
+        bus.write(0x4013, 0x40);
+        assert_eq!(
+            (bus.apu.registers[0x00], bus.apu.registers[0x13]),
+            (0x30, 0x40)
+        );
+        bus.write(0x4017, 0x40);
+        assert_eq!(bus.apu.get_frame_counter(), 0x40);
+        bus.write(0x4015, 0x1F);
+        assert_eq!(bus.read(0x4015), 0x1F);
+        bus.read(0x4018);
+        assert_eq!(bus.take_fault(), None, "open bus is legal by default");
+        bus.set_strict_access(true);
+        assert_eq!(bus.read(0x4015), 0x1F);
+        assert_eq!(bus.take_fault(), None);
+        bus.read(0x4018);
+        assert_eq!(
+            bus.take_fault(),
+            Some(EmuError::UnmappedRead { address: 0x4018 })
+        );
+    }
+
+    #[test]
+    fn cartridge_space_goes_to_the_mapper() {
+        let mut bus = Bus::with_mapper(Box::new(WorkRam { ram: [0; 0x2000] }));
+        bus.set_strict_access(true);
+        bus.write(0x6000, 0x5A);
+        assert_eq!(bus.read(0x6000), 0x5A);
+        assert_eq!(bus.take_fault(), None);
+        bus.read(0x8000);
+        assert_eq!(
+            bus.take_fault(),
+            Some(EmuError::UnmappedRead { address: 0x8000 })
+        );
+    }
+
+    #[test]
+    fn unmapped_reads_return_the_last_bus_value() {
+        let mut bus = Bus::new();
+        bus.ram[0x10] = 0x6A;
+        assert_eq!(bus.read(0x0010), 0x6A);
+        assert_eq!(bus.read(0x5000), 0x6A);
+        bus.write(0x0011, 0x21);
+        assert_eq!(bus.read(0x4018), 0x21);
+        // Write only registers are not driven either
+        assert_eq!(bus.read(0x4014), 0x21);
+    }
+
+    #[test]
+    fn partially_driven_reads_mix_in_open_bus() {
+        let mut bus = Bus::new();
+        bus.input.set_buttons(0, Buttons::A);
+        bus.write(0x4016, 0x01);
+        bus.apu.set_frame_irq();
+        bus.ram[0] = 0xE0;
+        bus.read(0x0000);
+        assert_eq!(bus.read(0x4016), 0xE1);
+        assert_eq!(bus.read(0x4015), 0x60);
+        bus.ram[0] = 0x00;
+        bus.read(0x0000);
+        assert_eq!(bus.read(0x4016), 0x01);
+    }
+
+    #[test]
+    fn reads_have_side_effects_and_peeks_do_not() {
+        let mut bus = Bus::new();
+        bus.input.set_buttons(0, Buttons::B);
+        bus.write(0x4016, 0x01);
+        bus.write(0x4016, 0x00);
+        bus.apu.set_frame_irq();
+        bus.ppu.set_vblank(true);
+
+        assert_eq!(bus.peek(0x4016) & 0x01, 0);
+        assert_eq!(bus.peek(0x4016) & 0x01, 0);
+        assert_eq!(bus.read(0x4016) & 0x01, 0);
+        assert_eq!(bus.read(0x4016) & 0x01, 1);
+
+        assert_eq!(bus.peek(0x4015) & 0x40, 0x40);
+        assert_eq!(bus.read(0x4015) & 0x40, 0x40);
+        assert_eq!(bus.peek(0x4015) & 0x40, 0x00);
+
+        assert_eq!(bus.peek(0x2002) & 0x80, 0x80);
+        assert_eq!(bus.read(0x2002) & 0x80, 0x80);
+        assert_eq!(bus.peek(0x2002) & 0x80, 0x00);
+    }
+
+    #[test]
+    fn open_bus_decays_when_configured() {
+        let mut bus = Bus::new();
+        bus.set_open_bus_decay(OpenBusDecay::After(100));
+        bus.write(0x0000, 0xFF);
+        bus.clock(99);
+        assert_eq!(bus.read(0x5000), 0xFF);
+        bus.clock(1);
+        assert_eq!(bus.read(0x5000), 0x00);
+
+        bus.set_open_bus_decay(OpenBusDecay::Never);
+        bus.write(0x0000, 0xFF);
+        bus.clock(1_000_000);
+        assert_eq!(bus.read(0x5000), 0xFF);
+    }
+
+    #[test]
+    fn unmapped_access_records_first_fault() {
+        let mut bus = Bus::new();
+        bus.write(0x4018, 0x42);
+        assert_eq!(bus.read(0x6000), 0x42);
+        assert_eq!(bus.take_fault(), None);
+        bus.set_strict_access(true);
+        assert_eq!(bus.read(0x6000), 0x42);
+        bus.write(0x6001, 0x42);
+        assert_eq!(
+            bus.take_fault(),
+            Some(EmuError::UnmappedRead { address: 0x6000 })
+        );
+        assert_eq!(bus.take_fault(), None);
+    }
+
+    #[test]
+    fn watchpoints_report_accesses_with_pc_and_cycle() {
+        let accesses = Rc::new(RefCell::new(Vec::new()));
+        let mut cpu = CPU::new(Bus::new());
+        let log = accesses.clone();
+        cpu.bus.add_watchpoint(
+            Watch::WRITE | Watch::EXECUTE,
+            0x0010..=0x0020,
+            move |access| log.borrow_mut().push(*access),
+        );
+        let program = asm!("LDA #$42", "STA $10", "STA $30", "LDA $10");
+        cpu.bus.ram[0x10..0x10 + program.len()].copy_from_slice(&program);
+        cpu.set_pc(0x0010);
+        for _ in 0..4 {
+            cpu.step();
+        }
+        let access = |kind, address, value, pc, cycle| BusAccess {
+            kind,
+            address,
+            value,
+            pc,
+            cycle,
+        };
+        assert_eq!(
+            *accesses.borrow(),
+            [
+                access(Watch::EXECUTE, 0x10, 0xA9, 0x10, 0),
+                access(Watch::EXECUTE, 0x12, 0x85, 0x12, 2),
+                // The first STA overwrites the program it is part of, on its
+                // third cycle
+                access(Watch::WRITE, 0x10, 0x42, 0x12, 4),
+                access(Watch::EXECUTE, 0x14, 0x85, 0x14, 5),
+                access(Watch::EXECUTE, 0x16, 0xA5, 0x16, 8),
+            ]
+        );
+    }
+
+    #[test]
+    fn ticked_watchpoints_see_the_exact_cycle() {
+        let cycles = Rc::new(RefCell::new(Vec::new()));
+        let mut cpu = CPU::new(Bus::new());
+        let log = cycles.clone();
+        cpu.bus
+            .add_watchpoint(Watch::READ, 0x0010..=0x0020, move |access| {
+                log.borrow_mut().push((access.address, access.cycle))
+            });
+        cpu.bus.ram[..2].copy_from_slice(&asm!("LDA $10,X"));
+        cpu.set_pc(0x0000);
+        cpu.set_x(0x05);
+        while {
+            cpu.tick();
+            !cpu.at_instruction_boundary()
+        } {}
+        // A dummy read of the base address comes before the indexed one
+        assert_eq!(*cycles.borrow(), [(0x10, 2), (0x15, 3)]);
+    }
+
+    /// Every read and write seen while running the program at $0200 to its
+    /// `done` label, cycle by cycle or an instruction at a time.
+    fn watched_accesses(ticked: bool) -> Vec<BusAccess> {
+        let program = asm!(
+            "        .org $0200",
+            "        LDX #$01",
+            "        LDY #$FF",
+            "        LDA $02FF,X",
+            "        STA $0300,X",
+            "        LDA ($10),Y",
+            "        STA ($20,X)",
+            "        INC $30",
+            "        INC $30,X",
+            "        ASL A",
+            "        PHA",
+            "        PLA",
+            "        JSR sub",
+            "        BNE done",
+            "sub:    RTS",
+            "done:   NOP",
+        );
+        let accesses = Rc::new(RefCell::new(Vec::new()));
+        let mut cpu = CPU::new(Bus::new());
+        let log = accesses.clone();
+        cpu.bus
+            .add_watchpoint(Watch::READ | Watch::WRITE, 0x0000..=0xFFFF, move |access| {
+                log.borrow_mut().push(*access)
+            });
+        cpu.bus.ram[0x200..0x200 + program.len()].copy_from_slice(&program);
+        cpu.bus.ram[0x10..0x12].copy_from_slice(&[0x01, 0x03]);
+        cpu.bus.ram[0x21..0x23].copy_from_slice(&[0x40, 0x03]);
+        cpu.bus.ram[0x0400] = 0x21; // BNE is taken
+        cpu.set_pc(0x0200);
+        let done = 0x0200 + program.len() as u16 - 1;
+        while cpu.get_pc() != done || !cpu.at_instruction_boundary() {
+            if ticked {
+                cpu.tick();
+            } else {
+                cpu.step();
+            }
+        }
+        accesses.take()
+    }
+
+    #[test]
+    fn stepped_watchpoints_see_the_ticked_cycles() {
+        let ticked = watched_accesses(true);
+        let stepped = watched_accesses(false);
+        // Ticking adds the dummy accesses, the real ones land on the same cycles
+        let mut remaining = ticked.iter();
+        for access in &stepped {
+            assert!(remaining.any(|ticked| ticked == access), "{access:?}");
+        }
+    }
+
+    #[test]
+    fn interrupt_accesses_belong_to_the_interrupt() {
+        for ticked in [false, true] {
+            let accesses = Rc::new(RefCell::new(Vec::new()));
+            let mut cpu = CPU::new(Bus::new());
+            let log = accesses.clone();
+            cpu.bus
+                .add_watchpoint(Watch::WRITE, 0x0100..=0x01FF, move |access| {
+                    log.borrow_mut().push((access.pc, access.cycle))
+                });
+            cpu.bus.ram[..1].copy_from_slice(&asm!("NOP"));
+            cpu.set_pc(0x0000);
+            cpu.step();
+            cpu.nmi(true);
+            if ticked {
+                for _ in 0..7 {
+                    cpu.tick();
+                }
+            } else {
+                cpu.step();
+            }
+            // Pushed on cycles 2-4 of the sequence, which starts on cycle 2
+            assert_eq!(*accesses.borrow(), [(0x0001, 4), (0x0001, 5), (0x0001, 6)]);
+        }
+    }
+
+    #[test]
+    fn watchpoints_count_the_cycles_of_dma() {
+        let cycles = Rc::new(RefCell::new(Vec::new()));
+        let mut cpu = CPU::new(Bus::new());
+        let log = cycles.clone();
+        cpu.bus
+            .add_watchpoint(Watch::READ, 0x0003..=0x0003, move |access| {
+                log.borrow_mut().push(access.cycle)
+            });
+        cpu.bus.ram[..4].copy_from_slice(&asm!("STA $4014", "NOP"));
+        cpu.set_pc(0x0000);
+        cpu.step();
+        cpu.step();
+        // Halted on the opcode fetch at cycle 4 and aligned, the DMA copies
+        // page zero from cycle 6, two cycles a byte
+        assert_eq!(*cycles.borrow(), [4, 12, 518]);
+    }
+
+    #[test]
+    fn removed_watchpoints_stop_firing() {
+        let hits = Rc::new(RefCell::new(0));
+        let mut bus = Bus::new();
+        let counter = hits.clone();
+        let id = bus.add_watchpoint(Watch::READ, 0x0000..=0x07FF, move |_| {
+            *counter.borrow_mut() += 1
+        });
+        bus.read(0x0800); // mirrors are matched by the address used
+        bus.read(0x0001);
+        bus.peek(0x0001);
+        assert!(bus.remove_watchpoint(id));
+        assert!(!bus.remove_watchpoint(id));
+        bus.read(0x0001);
+        assert_eq!(*hits.borrow(), 1);
+    }
+
+    #[test]
+    fn ppu_reaches_pattern_tables_through_the_mapper() {
+        let probe = Probe {
+            chr: vec![0; 0x2000],
+            ..Probe::default()
+        };
+        let mut bus = Bus::with_mapper(Box::new(probe));
+        bus.write(0x2006, 0x10);
+        bus.write(0x2006, 0x00);
+        bus.write(0x2007, 0xAB);
+        bus.write(0x2006, 0x10);
+        bus.write(0x2006, 0x00);
+        bus.read(0x2007);
+        assert_eq!(bus.read(0x2007), 0xAB);
+
+        let mapper = bus.mapper.as_ref().unwrap();
+        assert_eq!(mapper.ppu_peek(0x1000), 0xAB);
+        assert_eq!(bus.ppu.peek_memory(0x1000, Some(mapper.as_ref())), 0xAB);
+        assert_eq!(bus.ppu.peek_memory(0x1000, None), 0x00);
+    }
+
+    #[test]
+    fn mapper_sees_v_mirroring_is_synced_and_irq_is_shared() {
+        let ppu_addresses = Rc::new(RefCell::new(Vec::new()));
+        let probe = Probe {
+            chr: vec![0; 0x2000],
+            ppu_addresses: ppu_addresses.clone(),
+            ..Probe::default()
+        };
+        let mut bus = Bus::with_mapper(Box::new(probe));
+        assert_eq!(bus.ppu.mirroring, Mirroring::Horizontal);
+        bus.write(0x5000, 1);
+        assert_eq!(bus.ppu.mirroring, Mirroring::Vertical);
+
+        // A12 rises as the increment after the PPUDATA write crosses $1000
+        bus.write(0x2006, 0x0F);
+        bus.write(0x2006, 0xFF);
+        bus.write(0x2007, 0x00);
+        assert_eq!(*ppu_addresses.borrow(), [0x0FFF, 0x1000]);
+
+        bus.clock(9);
+        assert!(!bus.irq_line());
+        bus.clock(1);
+        assert!(bus.irq_line());
+    }
+
+    #[test]
+    fn expansion_audio_comes_from_the_mapper() {
+        assert_eq!(Bus::new().expansion_audio(), 0.0);
+        let cartridge = crate::cartridge::Cartridge::numbered(69, 0x8000, 0x2000);
+        let mut bus = Bus::with_mapper(cartridge.into_mapper().unwrap());
+        // 5B channel A: tone only, fixed volume 15
+        for (register, value) in [(0x07, 0x3E), (0x08, 0x0F)] {
+            bus.write(0xC000, register);
+            bus.write(0xE000, value);
+        }
+        assert_eq!(bus.expansion_audio(), 0.0);
+        bus.clock(16);
+        assert!(bus.expansion_audio() > 0.0);
     }
 }
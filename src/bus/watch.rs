@@ -0,0 +1,106 @@
+//! Watchpoints on CPU bus accesses.
+//!
+//! A watchpoint calls back on every read, write or opcode fetch in its address
+//! range, with the value on the bus and the instruction that made the access.
+//! Peeks are invisible to watchpoints.
+
+use std::ops::RangeInclusive;
+
+use bitflags::bitflags;
+
+bitflags! {
+    /// The kinds of access a watchpoint fires on.
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub struct Watch: u8 {
+        const READ = 0x01;
+        const WRITE = 0x02;
+        /// An opcode fetch, reported before the instruction runs.
+        const EXECUTE = 0x04;
+    }
+}
+
+/// One access seen by a watchpoint.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct BusAccess {
+    /// A single kind, never a combination.
+    pub kind: Watch,
+    pub address: u16,
+    /// The value read, written or, for `EXECUTE`, the opcode.
+    pub value: u8,
+    /// Address of the instruction making the access. During an NMI or IRQ
+    /// sequence, the address the interrupt returns to.
+    pub pc: u16,
+    /// CPU cycle of the access, under both `CPU::step` and `CPU::tick`.
+    pub cycle: u64,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct WatchpointId(u32);
+
+struct Watchpoint {
+    id: WatchpointId,
+    kinds: Watch,
+    range: RangeInclusive<u16>,
+    callback: Box<dyn FnMut(&BusAccess)>,
+}
+
+#[derive(Default)]
+pub(super) struct Watchpoints {
+    list: Vec<Watchpoint>,
+    next_id: u32,
+    pc: u16,
+}
+
+impl Watchpoints {
+    pub(super) fn add(
+        &mut self,
+        kinds: Watch,
+        range: RangeInclusive<u16>,
+        callback: Box<dyn FnMut(&BusAccess)>,
+    ) -> WatchpointId {
+        let id = WatchpointId(self.next_id);
+        self.next_id += 1;
+        self.list.push(Watchpoint {
+            id,
+            kinds,
+            range,
+            callback,
+        });
+        id
+    }
+
+    pub(super) fn remove(&mut self, id: WatchpointId) -> bool {
+        let len = self.list.len();
+        self.list.retain(|watchpoint| watchpoint.id != id);
+        self.list.len() != len
+    }
+
+    /// Accesses from now on are attributed to the instruction at `pc`.
+    pub(super) fn begin_instruction(&mut self, pc: u16, cycle: u64, opcode: u8) {
+        self.pc = pc;
+        self.notify(Watch::EXECUTE, pc, opcode, cycle);
+    }
+
+    /// Accesses from now on are attributed to the interrupt returning to `pc`.
+    pub(super) fn begin_interrupt(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub(super) fn notify(&mut self, kind: Watch, address: u16, value: u8, cycle: u64) {
+        if self.list.is_empty() {
+            return;
+        }
+        let access = BusAccess {
+            kind,
+            address,
+            value,
+            pc: self.pc,
+            cycle,
+        };
+        for watchpoint in &mut self.list {
+            if watchpoint.kinds.contains(kind) && watchpoint.range.contains(&address) {
+                (watchpoint.callback)(&access);
+            }
+        }
+    }
+}
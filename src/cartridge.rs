@@ -1,7 +1,259 @@
-struct Cartridge {}
+mod discrete;
+mod fme7;
+mod ines;
+mod mmc1;
+mod mmc2;
+mod mmc3;
+mod mmc5;
+mod namco163;
+mod nrom;
+mod vrc;
+mod vrc4;
+mod vrc6;
+mod vrc7;
+
+pub use discrete::{Board, Discrete};
+pub use fme7::Fme7;
+pub use ines::RomError;
+pub use mmc1::Mmc1;
+pub use mmc2::Mmc2;
+pub use mmc3::{Mmc3, Mmc3Revision};
+pub use mmc5::Mmc5;
+pub use namco163::Namco163;
+pub use nrom::Nrom;
+pub use vrc4::Vrc4;
+pub use vrc6::Vrc6;
+pub use vrc7::Vrc7;
+
+/// A game as loaded from a ROM image.
+///
+/// NES 2.0 headers describe the board fully. For iNES 1.0 images the fields
+/// NES 2.0 added hold what the board almost always has.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Cartridge {
+    /// Loaded from a NES 2.0 header.
+    pub nes2: bool,
+    /// The iNES mapper number identifying the board, up to 12 bits.
+    pub mapper: u16,
+    /// Board variant within the mapper, NES 2.0 only.
+    pub submapper: u8,
+    /// Nametable layout hardwired by the board. Mappers that switch it at run
+    /// time start from here.
+    pub mirroring: Mirroring,
+    /// PRG-RAM is battery backed and should be saved.
+    pub battery: bool,
+    /// 512 bytes meant to be loaded at $7000, from some copier dumps.
+    pub trainer: Option<Vec<u8>>,
+    pub prg_rom: Vec<u8>,
+    /// CHR-ROM, or zeroed CHR-RAM for boards without CHR-ROM.
+    pub chr: Vec<u8>,
+    pub chr_is_ram: bool,
+    /// Sizes in bytes of the RAM on the board. The NVRAM is battery backed.
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub timing: Timing,
+    pub console: Console,
+    /// NES 2.0 default expansion device, e.g. 1 for standard controllers or
+    /// 8 for the Zapper. 0 when unspecified.
+    pub expansion_device: u8,
+}
+
+/// The CPU/PPU timing the game was made for.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Timing {
+    #[default]
+    Ntsc,
+    Pal,
+    /// Runs on either.
+    MultiRegion,
+    /// The Dendy famiclone, PAL frame rate with NTSC-like CPU timing.
+    Dendy,
+}
+
+/// The system the game runs on.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Console {
+    #[default]
+    Nes,
+    /// Vs. System arcade board, with NES 2.0 PPU and hardware type codes.
+    VsSystem {
+        ppu: u8,
+        hardware: u8,
+    },
+    Playchoice10,
+    /// An NES 2.0 extended console type code, such as a Famiclone with
+    /// decimal mode.
+    Extended(u8),
+}
+
+/// How the four logical nametables map onto the console's 2KB of VRAM.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Mirroring {
+    /// $2000 = $2400 and $2800 = $2C00, for vertical scrolling.
+    #[default]
+    Horizontal,
+    /// $2000 = $2800 and $2400 = $2C00, for horizontal scrolling.
+    Vertical,
+    SingleScreenLower,
+    SingleScreenUpper,
+    /// The cartridge provides another 2KB for four unique nametables.
+    FourScreen,
+    /// Each nametable picks its own 1KB page of VRAM, as MMC5 and Namco 163
+    /// allow.
+    Custom([u8; 4]),
+}
+
+/// The board inside a cartridge: how its ROM and RAM appear to the CPU at
+/// $4020-$FFFF and to the PPU at $0000-$1FFF, and the signals it drives.
+pub trait Mapper {
+    /// Returns `None` when the board does not drive the data bus at `address`.
+    fn cpu_peek(&self, address: u16) -> Option<u8>;
+    fn cpu_write(&mut self, address: u16, value: u8);
+
+    /// Like `cpu_peek`, for boards with registers that react to reads.
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        self.cpu_peek(address)
+    }
+
+    /// Pattern table read, $0000-$1FFF.
+    fn ppu_peek(&self, address: u16) -> u8;
+    /// Pattern table write, which only lands on boards with CHR-RAM.
+    fn ppu_write(&mut self, address: u16, value: u8);
+
+    /// Like `ppu_peek`, for boards that latch on pattern fetches.
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.ppu_peek(address)
+    }
+
+    /// The current nametable layout. The bus reads it back after every write
+    /// to the cartridge.
+    fn mirroring(&self) -> Mirroring;
+
+    /// Nametable read, $2000-$2FFF, for boards that can answer nametable
+    /// accesses themselves. `None` leaves it to the console's VRAM.
+    fn nametable_peek(&self, _address: u16) -> Option<u8> {
+        None
+    }
+
+    /// Like `nametable_peek`, for boards that watch nametable fetches.
+    fn nametable_read(&mut self, address: u16) -> Option<u8> {
+        self.nametable_peek(address)
+    }
+
+    /// Returns false to leave the write to the console's VRAM.
+    fn nametable_write(&mut self, _address: u16, _value: u8) -> bool {
+        false
+    }
+
+    /// Called with CPU writes to the PPU registers, for boards that snoop
+    /// PPUCTRL and PPUMASK.
+    fn ppu_register_write(&mut self, _address: u16, _value: u8) {}
+
+    /// The board's own audio, to be mixed with the APU's output. Silent
+    /// boards return 0.0.
+    fn audio(&self) -> f32 {
+        0.0
+    }
+
+    /// The /IRQ output, asserted while true.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Called once per CPU cycle, for boards with cycle counters.
+    fn cpu_cycle(&mut self) {}
+
+    /// Called with each address the PPU puts on its bus, for boards that count
+    /// A12 edges.
+    fn ppu_address(&mut self, _address: u16) {}
+}
+
+impl Cartridge {
+    /// Builds the board the header asks for.
+    pub fn into_mapper(self) -> Result<Box<dyn Mapper>, RomError> {
+        match self.mapper {
+            0 => Ok(Box::new(Nrom::new(self))),
+            1 => Ok(Box::new(Mmc1::new(self))),
+            2 => Ok(Box::new(Discrete::new(Board::UxRom, self))),
+            3 => Ok(Box::new(Discrete::new(Board::CnRom, self))),
+            4 => Ok(Box::new(Mmc3::new(self))),
+            5 => Ok(Box::new(Mmc5::new(self))),
+            7 => Ok(Box::new(Discrete::new(Board::AxRom, self))),
+            9 => Ok(Box::new(Mmc2::new(self, false))),
+            10 => Ok(Box::new(Mmc2::new(self, true))),
+            11 => Ok(Box::new(Discrete::new(Board::ColorDreams, self))),
+            19 => Ok(Box::new(Namco163::new(self))),
+            21 | 22 | 23 | 25 => Ok(Box::new(Vrc4::new(self))),
+            24 | 26 => Ok(Box::new(Vrc6::new(self))),
+            66 => Ok(Box::new(Discrete::new(Board::GxRom, self))),
+            69 => Ok(Box::new(Fme7::new(self))),
+            85 => Ok(Box::new(Vrc7::new(self))),
+            mapper => Err(RomError::UnsupportedMapper(mapper)),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Cartridge {
+    /// A board for mapper tests. Every byte of PRG-ROM holds the number of its
+    /// 8KB bank, every byte of CHR-ROM the number of its 1KB bank. Without
+    /// CHR-ROM it gets 8KB of CHR-RAM. 8KB of PRG-RAM either way.
+    pub(crate) fn numbered(mapper: u16, prg_rom_size: usize, chr_rom_size: usize) -> Self {
+        let chr_is_ram = chr_rom_size == 0;
+        Cartridge {
+            nes2: false,
+            mapper,
+            submapper: 0,
+            mirroring: Mirroring::Horizontal,
+            battery: false,
+            trainer: None,
+            prg_rom: (0..prg_rom_size).map(|i| (i >> 13) as u8).collect(),
+            chr: if chr_is_ram {
+                vec![0; 0x2000]
+            } else {
+                (0..chr_rom_size).map(|i| (i >> 10) as u8).collect()
+            },
+            chr_is_ram,
+            prg_ram_size: 0x2000,
+            prg_nvram_size: 0,
+            chr_ram_size: if chr_is_ram { 0x2000 } else { 0 },
+            chr_nvram_size: 0,
+            timing: Timing::Ntsc,
+            console: Console::Nes,
+            expansion_device: 0,
+        }
+    }
+}
+
+/// The 8KB PRG banks at $8000, $A000, $C000 and $E000 of a `numbered` board.
+#[cfg(test)]
+pub(crate) fn prg_banks(mapper: &impl Mapper) -> [u8; 4] {
+    [0x8000, 0xA000, 0xC000, 0xE000].map(|a| mapper.cpu_peek(a).unwrap())
+}
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_cartridge() {
+        let mut image = b"NES\x1A\x01\x00\x01\x00".to_vec();
+        image.resize(16 + 0x4000, 0);
+        let cartridge = Cartridge::from_ines(&image).unwrap();
+        assert_eq!(cartridge.mirroring, Mirroring::Vertical);
+        assert!(cartridge.chr_is_ram);
+    }
+
     #[test]
-    fn test_create_cartridge() {}
+    fn unsupported_mappers_are_reported() {
+        let mut image = b"NES\x1A\x01\x00\xF0".to_vec();
+        image.resize(16 + 0x4000, 0);
+        let cartridge = Cartridge::from_ines(&image).unwrap();
+        assert!(matches!(
+            cartridge.into_mapper(),
+            Err(RomError::UnsupportedMapper(15))
+        ));
+    }
 }
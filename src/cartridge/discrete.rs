@@ -0,0 +1,209 @@
+//! The discrete logic boards: one latch written anywhere in $8000-$FFFF,
+//! wired to bank select lines.
+//!
+//! | Mapper | Board        | Latch                                            |
+//! |--------|--------------|--------------------------------------------------|
+//! | 2      | UxROM        | 16KB PRG bank at $8000, last bank fixed at $C000 |
+//! | 3      | CNROM        | 8KB CHR bank                                     |
+//! | 7      | AxROM        | ...M.PPP: single screen page, 32KB PRG bank      |
+//! | 11     | Color Dreams | CCCC..PP: 8KB CHR bank, 32KB PRG bank            |
+//! | 66     | GxROM        | ..PP..CC: 32KB PRG bank, 8KB CHR bank            |
+//!
+//! The latch is written while the PRG-ROM drives the same data bus, so on
+//! boards without a chip to prevent it the latch gets the written value ANDed
+//! with the ROM byte (a bus conflict). Games write to a ROM byte that holds
+//! the same value. NES 2.0 submappers 1 and 2 tell UxROM, CNROM and AxROM
+//! boards without and with conflicts; Color Dreams and GxROM always have them.
+
+use super::{Cartridge, Mapper, Mirroring};
+
+const CHR_BANK_SIZE: usize = 0x2000;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Board {
+    UxRom,
+    CnRom,
+    AxRom,
+    ColorDreams,
+    GxRom,
+}
+
+pub struct Discrete {
+    board: Board,
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    latch: u8,
+}
+
+impl Discrete {
+    pub fn new(board: Board, cartridge: Cartridge) -> Self {
+        let bus_conflicts = match board {
+            Board::UxRom | Board::CnRom | Board::AxRom => cartridge.submapper == 2,
+            Board::ColorDreams | Board::GxRom => true,
+        };
+        Discrete {
+            board,
+            prg_rom: cartridge.prg_rom,
+            prg_ram: vec![0; cartridge.prg_ram_size + cartridge.prg_nvram_size],
+            chr: cartridge.chr,
+            chr_is_ram: cartridge.chr_is_ram,
+            mirroring: cartridge.mirroring,
+            bus_conflicts,
+            latch: 0,
+        }
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> usize {
+        let latch = self.latch as usize;
+        let (size, bank) = match self.board {
+            Board::UxRom if address < 0xC000 => (0x4000, Some(latch)),
+            Board::UxRom => (0x4000, None), // the last bank
+            Board::CnRom => (0x8000, Some(0)),
+            Board::AxRom => (0x8000, Some(latch & 0x0F)),
+            Board::ColorDreams => (0x8000, Some(latch & 0x03)),
+            Board::GxRom => (0x8000, Some((latch >> 4) & 0x03)),
+        };
+        let banks = (self.prg_rom.len() / size).max(1);
+        let bank = bank.map_or(banks - 1, |bank| bank % banks);
+        // 16KB CNROM mirrors, like NROM-128
+        (bank * size + (address as usize & (size - 1))) % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let bank = match self.board {
+            Board::CnRom => self.latch,
+            Board::ColorDreams => self.latch >> 4,
+            Board::GxRom => self.latch & 0x03,
+            Board::UxRom | Board::AxRom => 0,
+        };
+        let banks = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        (bank as usize % banks) * CHR_BANK_SIZE + (address as usize & 0x1FFF)
+    }
+}
+
+impl Mapper for Discrete {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(address as usize - 0x6000) % self.prg_ram.len()])
+            }
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_rom_offset(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(address as usize - 0x6000) % len] = value;
+            }
+            0x8000..=0xFFFF => {
+                self.latch = if self.bus_conflicts {
+                    value & self.prg_rom[self.prg_rom_offset(address)]
+                } else {
+                    value
+                };
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let i = self.chr_offset(address);
+            self.chr[i] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.board {
+            Board::AxRom if self.latch & 0x10 != 0 => Mirroring::SingleScreenUpper,
+            Board::AxRom => Mirroring::SingleScreenLower,
+            _ => self.mirroring,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn discrete(board: Board, prg_rom_size: usize, chr_rom_size: usize) -> Discrete {
+        let mut cartridge = Cartridge::numbered(0, prg_rom_size, chr_rom_size);
+        cartridge.submapper = 1; // no bus conflicts where the board allows it
+        Discrete::new(board, cartridge)
+    }
+
+    #[test]
+    fn uxrom_switches_8000_and_fixes_the_last_bank() {
+        let mut uxrom = discrete(Board::UxRom, 0x20000, 0);
+        assert_eq!(uxrom.cpu_peek(0x8000), Some(0));
+        assert_eq!(uxrom.cpu_peek(0xC000), Some(14));
+        uxrom.cpu_write(0x8000, 5);
+        assert_eq!(uxrom.cpu_peek(0x8000), Some(10));
+        assert_eq!(uxrom.cpu_peek(0xA000), Some(11));
+        assert_eq!(uxrom.cpu_peek(0xE000), Some(15));
+    }
+
+    #[test]
+    fn cnrom_switches_chr() {
+        let mut cnrom = discrete(Board::CnRom, 0x4000, 0x8000);
+        cnrom.cpu_write(0xFFFF, 3);
+        assert_eq!(cnrom.ppu_peek(0x0000), 24);
+        assert_eq!(cnrom.ppu_peek(0x1C00), 31);
+        // 16KB of PRG-ROM is mirrored
+        assert_eq!(cnrom.cpu_peek(0xC000), Some(0));
+    }
+
+    #[test]
+    fn axrom_selects_a_single_screen() {
+        let mut axrom = discrete(Board::AxRom, 0x40000, 0);
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenLower);
+        axrom.cpu_write(0x8000, 0x13);
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenUpper);
+        assert_eq!(axrom.cpu_peek(0x8000), Some(12));
+        assert_eq!(axrom.cpu_peek(0xE000), Some(15));
+    }
+
+    #[test]
+    fn color_dreams_and_gxrom_split_the_latch() {
+        let mut color_dreams = discrete(Board::ColorDreams, 0x20000, 0x20000);
+        let mut gxrom = discrete(Board::GxRom, 0x20000, 0x8000);
+        // Avoid the conflicts by writing where the ROM reads $FF
+        color_dreams.prg_rom[0x7FFF] = 0xFF;
+        gxrom.prg_rom[0x7FFF] = 0xFF;
+        color_dreams.cpu_write(0xFFFF, 0x52);
+        gxrom.cpu_write(0xFFFF, 0x21);
+        assert_eq!(color_dreams.cpu_peek(0x8000), Some(8));
+        assert_eq!(color_dreams.ppu_peek(0x0000), 40);
+        assert_eq!(gxrom.cpu_peek(0x8000), Some(8));
+        assert_eq!(gxrom.ppu_peek(0x0000), 8);
+    }
+
+    #[test]
+    fn bus_conflicts_and_the_written_value_with_rom() {
+        let mut cartridge = Cartridge::numbered(2, 0x20000, 0);
+        cartridge.submapper = 2;
+        let mut uxrom = Discrete::new(Board::UxRom, cartridge);
+        uxrom.prg_rom[0x1FFFF] = 0x03;
+        uxrom.cpu_write(0xFFFF, 0x06);
+        assert_eq!(uxrom.cpu_peek(0x8000), Some(4)); // bank 2
+    }
+
+    #[test]
+    fn mappers_build_their_boards() {
+        for mapper in [2, 3, 7, 11, 66] {
+            let cartridge = Cartridge::numbered(mapper, 0x8000, 0x2000);
+            assert!(cartridge.into_mapper().is_ok(), "mapper {mapper}");
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! Sunsoft FME-7 and 5B, iNES mapper 69.
+//!
+//! | Address     | Register                                              |
+//! |-------------|-------------------------------------------------------|
+//! | $8000-$9FFF | Command, 4 bits                                       |
+//! | $A000-$BFFF | Parameter for the last command                        |
+//! | $C000-$DFFF | 5B audio register select, see `audio`                 |
+//! | $E000-$FFFF | 5B audio register write                               |
+//!
+//! | Command | Function                                                  |
+//! |---------|-----------------------------------------------------------|
+//! | $0-$7   | 1KB CHR bank at $0000, $0400, ... $1C00                   |
+//! | $8      | ER-B BBBB: 8KB bank at $6000, PRG-RAM enable, RAM or ROM  |
+//! | $9-$B   | 8KB PRG bank at $8000, $A000 and $C000                    |
+//! | $C      | Mirroring: vertical, horizontal, one screen lower, upper  |
+//! | $D      | C--- ---T: IRQ counter enable, IRQ enable; acknowledges   |
+//! | $E-$F   | IRQ counter low and high byte                             |
+//!
+//! $E000-$FFFF is fixed to the last 8KB bank. The IRQ counter counts down
+//! every CPU cycle and fires when it wraps from $0000 to $FFFF. The FME-7
+//! has no audio, but nothing else answers at $C000-$FFFF so every board gets
+//! the 5B's.
+
+mod audio;
+
+use super::{Cartridge, Mapper, Mirroring};
+use audio::Audio;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+const PRG_RAM_SELECT: u8 = 0x40;
+const PRG_RAM_ENABLE: u8 = 0x80;
+const IRQ_ENABLE: u8 = 0x01;
+const COUNTER_ENABLE: u8 = 0x80;
+
+pub struct Fme7 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    command: u8,
+    chr_banks: [u8; 8],
+    prg_banks: [u8; 4], // $6000, $8000, $A000 and $C000
+    mirroring: u8,
+    irq_control: u8,
+    irq_counter: u16,
+    irq_pending: bool,
+    audio: Audio,
+}
+
+impl Fme7 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Fme7 {
+            prg_rom: cartridge.prg_rom,
+            prg_ram: vec![0; cartridge.prg_ram_size + cartridge.prg_nvram_size],
+            chr: cartridge.chr,
+            chr_is_ram: cartridge.chr_is_ram,
+            command: 0,
+            chr_banks: [0; 8],
+            prg_banks: [0; 4],
+            mirroring: 0,
+            irq_control: 0,
+            irq_counter: 0,
+            irq_pending: false,
+            audio: Audio::default(),
+        }
+    }
+
+    fn write_parameter(&mut self, value: u8) {
+        match self.command {
+            0x0..=0x7 => self.chr_banks[self.command as usize] = value,
+            0x8..=0xB => self.prg_banks[self.command as usize - 0x8] = value,
+            0xC => self.mirroring = value & 0x03,
+            0xD => {
+                self.irq_control = value;
+                self.irq_pending = false;
+            }
+            0xE => self.irq_counter = (self.irq_counter & 0xFF00) | value as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00FF) | (value as u16) << 8,
+        }
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> usize {
+        let banks = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+        let bank = match (address >> 13) - 3 {
+            4 => banks - 1,
+            slot => self.prg_banks[slot as usize] as usize & 0x3F,
+        };
+        // Less than a bank mirrors within it
+        ((bank % banks) * PRG_BANK_SIZE + (address as usize & 0x1FFF)) % self.prg_rom.len()
+    }
+
+    /// Where $6000-$7FFF reads and writes land in PRG-RAM, when it's mapped.
+    fn prg_ram_offset(&self, address: u16) -> Option<usize> {
+        let bank = self.prg_banks[0];
+        if self.prg_ram.is_empty() || bank & PRG_RAM_SELECT == 0 || bank & PRG_RAM_ENABLE == 0 {
+            return None;
+        }
+        let offset = (bank as usize & 0x3F) * PRG_BANK_SIZE + (address as usize & 0x1FFF);
+        Some(offset % self.prg_ram.len())
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let bank = self.chr_banks[(address >> 10) as usize & 0x07] as usize;
+        let banks = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        (bank % banks) * CHR_BANK_SIZE + (address as usize & 0x03FF)
+    }
+}
+
+impl Mapper for Fme7 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if self.prg_banks[0] & PRG_RAM_SELECT != 0 => {
+                self.prg_ram_offset(address).map(|i| self.prg_ram[i])
+            }
+            0x6000..=0xFFFF => Some(self.prg_rom[self.prg_rom_offset(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF => {
+                if let Some(i) = self.prg_ram_offset(address) {
+                    self.prg_ram[i] = value;
+                }
+            }
+            0x8000..=0x9FFF => self.command = value & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(value),
+            0xC000..=0xDFFF => self.audio.select(value),
+            0xE000..=0xFFFF => self.audio.write(value),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let i = self.chr_offset(address);
+            self.chr[i] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn audio(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.audio.clock();
+        if self.irq_control & COUNTER_ENABLE == 0 {
+            return;
+        }
+        self.irq_counter = self.irq_counter.wrapping_sub(1);
+        if self.irq_counter == 0xFFFF && self.irq_control & IRQ_ENABLE != 0 {
+            self.irq_pending = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::prg_banks;
+
+    fn fme7() -> Fme7 {
+        Fme7::new(Cartridge::numbered(69, 0x40000, 0x40000))
+    }
+
+    fn command(fme7: &mut Fme7, command: u8, parameter: u8) {
+        fme7.cpu_write(0x8000, command);
+        fme7.cpu_write(0xA000, parameter);
+    }
+
+    #[test]
+    fn prg_banks_and_ram() {
+        let mut fme7 = fme7();
+        for (register, bank) in [(0x8, 2), (0x9, 3), (0xA, 4), (0xB, 5)] {
+            command(&mut fme7, register, bank);
+        }
+        assert_eq!(fme7.cpu_peek(0x6000), Some(2));
+        assert_eq!(prg_banks(&fme7), [3, 4, 5, 31]);
+        // RAM selected but disabled is open bus
+        command(&mut fme7, 0x8, PRG_RAM_SELECT);
+        assert_eq!(fme7.cpu_peek(0x6000), None);
+        fme7.cpu_write(0x6000, 0x55);
+        command(&mut fme7, 0x8, PRG_RAM_SELECT | PRG_RAM_ENABLE);
+        assert_eq!(fme7.cpu_peek(0x6000), Some(0x00));
+        fme7.cpu_write(0x6000, 0x55);
+        assert_eq!(fme7.cpu_peek(0x6000), Some(0x55));
+    }
+
+    #[test]
+    fn small_prg_stays_in_range() {
+        for size in [0x2000, 0x1000] {
+            let fme7 = Fme7::new(Cartridge::numbered(69, size, 0x2000));
+            assert_eq!(prg_banks(&fme7), [0, 0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn chr_banks_and_mirroring() {
+        let mut fme7 = fme7();
+        command(&mut fme7, 0x6, 0x42);
+        assert_eq!(fme7.ppu_peek(0x1800), 0x42);
+        command(&mut fme7, 0xC, 0x01);
+        assert_eq!(fme7.mirroring(), Mirroring::Horizontal);
+        command(&mut fme7, 0xC, 0x03);
+        assert_eq!(fme7.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn irq_fires_when_the_counter_wraps() {
+        let mut fme7 = fme7();
+        command(&mut fme7, 0xE, 0x02);
+        command(&mut fme7, 0xF, 0x00);
+        command(&mut fme7, 0xD, COUNTER_ENABLE | IRQ_ENABLE);
+        for _ in 0..3 {
+            assert!(!fme7.irq());
+            fme7.cpu_cycle();
+        }
+        assert!(fme7.irq());
+        command(&mut fme7, 0xD, COUNTER_ENABLE);
+        assert!(!fme7.irq());
+        // The counter keeps going without the IRQ
+        for _ in 0..0x10000 {
+            fme7.cpu_cycle();
+        }
+        assert!(!fme7.irq());
+        assert_eq!(fme7.irq_counter, 0xFFFF);
+    }
+
+    #[test]
+    fn audio_registers() {
+        let mut fme7 = fme7();
+        for (register, value) in [(0x00, 0x01), (0x07, 0x3E), (0x08, 0x0F)] {
+            fme7.cpu_write(0xC000, register);
+            fme7.cpu_write(0xE000, value);
+        }
+        for _ in 0..16 {
+            fme7.cpu_cycle();
+        }
+        assert!(fme7.audio() > 0.0);
+    }
+}
@@ -0,0 +1,283 @@
+//! Sunsoft 5B expansion audio: the tone, noise and envelope generators of a
+//! YM2149, with the CPU writing a register number to $C000 and then a value
+//! to $E000.
+//!
+//! | Register | Function                                                  |
+//! |----------|-----------------------------------------------------------|
+//! | $00-$05  | Tone period of channels A, B and C, low 8 and high 4 bits |
+//! | $06      | Noise period, 5 bits                                      |
+//! | $07      | --CB AcbA: noise and tone disable for each channel        |
+//! | $08-$0A  | ---E VVVV: envelope or fixed volume of channels A-C       |
+//! | $0B-$0C  | Envelope period, low and high 8 bits                      |
+//! | $0D      | Envelope shape: continue, attack, alternate, hold         |
+//!
+//! Every generator runs off a 16 CPU cycle prescaler. Volume steps are 3dB,
+//! envelope steps half that.
+
+const PRESCALER: u8 = 16;
+
+const ENVELOPE_MODE: u8 = 0x10;
+const SHAPE_CONTINUE: u8 = 0x08;
+const SHAPE_ATTACK: u8 = 0x04;
+const SHAPE_ALTERNATE: u8 = 0x02;
+const SHAPE_HOLD: u8 = 0x01;
+
+/// The level of one channel at full volume, about that of an APU pulse.
+const CHANNEL_LEVEL: f32 = 0.15;
+
+#[derive(Default)]
+struct Tone {
+    period: u16,
+    counter: u16,
+    high: bool,
+}
+
+impl Tone {
+    fn clock(&mut self) {
+        self.counter += 1;
+        if self.counter >= self.period {
+            self.counter = 0;
+            self.high = !self.high;
+        }
+    }
+}
+
+#[derive(Default)]
+struct Envelope {
+    period: u16,
+    counter: u16,
+    shape: u8,
+    step: u8, // 0 to 31 through the current ramp
+    attack: bool,
+    holding: bool,
+}
+
+impl Envelope {
+    fn restart(&mut self, shape: u8) {
+        self.shape = shape;
+        self.counter = 0;
+        self.step = 0;
+        self.attack = shape & SHAPE_ATTACK != 0;
+        self.holding = false;
+    }
+
+    fn clock(&mut self) {
+        if self.holding {
+            return;
+        }
+        self.counter += 1;
+        if self.counter < self.period {
+            return;
+        }
+        self.counter = 0;
+        if self.step < 31 {
+            self.step += 1;
+            return;
+        }
+        // The end of a ramp
+        if self.shape & SHAPE_CONTINUE == 0 {
+            self.attack = false;
+            self.holding = true;
+        } else if self.shape & SHAPE_HOLD != 0 {
+            self.attack ^= self.shape & SHAPE_ALTERNATE != 0;
+            self.holding = true;
+        } else {
+            self.attack ^= self.shape & SHAPE_ALTERNATE != 0;
+            self.step = 0;
+        }
+    }
+
+    /// From 0, silence, to 31.
+    fn level(&self) -> u8 {
+        match (self.holding, self.attack) {
+            (true, true) => 31,
+            (true, false) => 0,
+            (false, true) => self.step,
+            (false, false) => 31 - self.step,
+        }
+    }
+}
+
+pub(super) struct Audio {
+    address: u8,
+    tones: [Tone; 3],
+    noise_period: u8,
+    noise_counter: u8,
+    noise: u32, // 17-bit LFSR
+    mixer: u8,
+    volumes: [u8; 3],
+    envelope: Envelope,
+    prescaler: u8,
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Audio {
+            address: 0,
+            tones: Default::default(),
+            noise_period: 0,
+            noise_counter: 0,
+            noise: 1,
+            mixer: 0,
+            volumes: [0; 3],
+            envelope: Envelope::default(),
+            prescaler: 0,
+        }
+    }
+}
+
+impl Audio {
+    pub(super) fn select(&mut self, address: u8) {
+        self.address = address;
+    }
+
+    pub(super) fn write(&mut self, value: u8) {
+        match self.address {
+            0x00..=0x05 => {
+                let tone = &mut self.tones[self.address as usize >> 1];
+                tone.period = if self.address & 1 == 0 {
+                    (tone.period & 0x0F00) | value as u16
+                } else {
+                    (tone.period & 0x00FF) | ((value as u16 & 0x0F) << 8)
+                };
+            }
+            0x06 => self.noise_period = value & 0x1F,
+            0x07 => self.mixer = value,
+            0x08..=0x0A => self.volumes[self.address as usize - 0x08] = value & 0x1F,
+            0x0B => self.envelope.period = (self.envelope.period & 0xFF00) | value as u16,
+            0x0C => self.envelope.period = (self.envelope.period & 0x00FF) | (value as u16) << 8,
+            0x0D => self.envelope.restart(value & 0x0F),
+            _ => {}
+        }
+    }
+
+    pub(super) fn clock(&mut self) {
+        self.prescaler += 1;
+        if self.prescaler < PRESCALER {
+            return;
+        }
+        self.prescaler = 0;
+        for tone in &mut self.tones {
+            tone.clock();
+        }
+        self.noise_counter += 1;
+        if self.noise_counter >= self.noise_period {
+            self.noise_counter = 0;
+            let feedback = (self.noise ^ (self.noise >> 3)) & 1;
+            self.noise = (self.noise >> 1) | (feedback << 16);
+        }
+        self.envelope.clock();
+    }
+
+    pub(super) fn output(&self) -> f32 {
+        let noise = self.noise & 1 != 0;
+        (0..3)
+            .map(|channel| {
+                let tone_off = self.mixer & (0x01 << channel) != 0;
+                let noise_off = self.mixer & (0x08 << channel) != 0;
+                if !(self.tones[channel].high || tone_off) || !(noise || noise_off) {
+                    return 0.0;
+                }
+                let volume = self.volumes[channel];
+                let level = if volume & ENVELOPE_MODE != 0 {
+                    self.envelope.level()
+                } else if volume == 0 {
+                    0
+                } else {
+                    volume * 2 + 1
+                };
+                amplitude(level)
+            })
+            .sum::<f32>()
+            * CHANNEL_LEVEL
+    }
+}
+
+/// Level 31 is full volume, each step down is 1.5dB quieter and 0 is silent.
+fn amplitude(level: u8) -> f32 {
+    if level == 0 {
+        0.0
+    } else {
+        10f32.powf(-1.5 * (31 - level) as f32 / 20.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(audio: &mut Audio, address: u8, value: u8) {
+        audio.select(address);
+        audio.write(value);
+    }
+
+    #[test]
+    fn tone_toggles_every_period() {
+        let mut audio = Audio::default();
+        write(&mut audio, 0x00, 0x02);
+        write(&mut audio, 0x07, 0x3E); // only tone A
+        write(&mut audio, 0x08, 0x0F);
+        let mut levels = Vec::new();
+        for _ in 0..6 {
+            for _ in 0..PRESCALER {
+                audio.clock();
+            }
+            levels.push(audio.output() > 0.0);
+        }
+        assert_eq!(levels, [false, true, true, false, false, true]);
+        assert_eq!(audio.output(), CHANNEL_LEVEL);
+    }
+
+    #[test]
+    fn volume_steps_are_3db() {
+        assert!((amplitude(2 * 14 + 1) / amplitude(31) - 0.708).abs() < 0.001);
+        assert_eq!(amplitude(0), 0.0);
+    }
+
+    #[test]
+    fn envelope_shapes() {
+        let levels = |shape: u8| {
+            let mut envelope = Envelope {
+                period: 1,
+                ..Default::default()
+            };
+            envelope.restart(shape);
+            let mut levels = Vec::new();
+            for clock in 0..96 {
+                envelope.clock();
+                if clock % 16 == 0 {
+                    levels.push(envelope.level());
+                }
+            }
+            levels
+        };
+        // Decay once, then silence
+        assert_eq!(levels(0x00), [30, 14, 0, 0, 0, 0]);
+        // Saw up, repeating
+        assert_eq!(levels(SHAPE_CONTINUE | SHAPE_ATTACK), [1, 17, 1, 17, 1, 17]);
+        // Triangle
+        assert_eq!(
+            levels(SHAPE_CONTINUE | SHAPE_ALTERNATE),
+            [30, 14, 1, 17, 30, 14]
+        );
+        // Attack and hold at the top
+        assert_eq!(
+            levels(SHAPE_CONTINUE | SHAPE_ATTACK | SHAPE_HOLD),
+            [1, 17, 31, 31, 31, 31]
+        );
+    }
+
+    #[test]
+    fn noise_needs_its_period() {
+        let mut audio = Audio::default();
+        write(&mut audio, 0x06, 0x01);
+        write(&mut audio, 0x07, 0x37); // only noise A
+        write(&mut audio, 0x08, 0x0F);
+        let mut outputs = std::collections::HashSet::new();
+        for _ in 0..64 * PRESCALER as u32 {
+            audio.clock();
+            outputs.insert(audio.output() > 0.0);
+        }
+        assert_eq!(outputs.len(), 2);
+    }
+}
@@ -0,0 +1,510 @@
+//! iNES and NES 2.0 ROM images.
+//!
+//! A 16 byte header followed by an optional 512 byte trainer, the PRG-ROM in
+//! 16KB banks and the CHR-ROM in 8KB banks:
+//!
+//! | Byte | iNES 1.0                            | NES 2.0                            |
+//! |------|-------------------------------------|------------------------------------|
+//! | 0-3  | "NES" followed by $1A               |                                    |
+//! | 4    | PRG-ROM banks                       | PRG-ROM size LSB                   |
+//! | 5    | CHR-ROM banks, 0 for CHR-RAM        | CHR-ROM size LSB                   |
+//! | 6    | NNNN FTBM: mapper low nibble, four  |                                    |
+//! |      | screen, trainer, battery, vertical  |                                    |
+//! | 7    | NNNN xxPV: mapper high nibble,      | NNNN 10TT: NES 2.0 marker and      |
+//! |      | Playchoice-10, Vs. System           | console type                       |
+//! | 8    | PRG-RAM in 8KB units                | SSSS NNNN: submapper, mapper bits  |
+//! |      |                                     | 8-11                               |
+//! | 9    | xxxx xxxP: PAL                      | CCCC PPPP: CHR/PRG-ROM size MSB    |
+//! | 10   | Unused, must be zero                | PRG-NVRAM and PRG-RAM shift counts |
+//! | 11   |                                     | CHR-NVRAM and CHR-RAM shift counts |
+//! | 12   |                                     | xxxx xxTT: CPU/PPU timing          |
+//! | 13   |                                     | Vs. System type or extended        |
+//! |      |                                     | console type                       |
+//! | 14   |                                     | Miscellaneous ROM count            |
+//! | 15   |                                     | xxDD DDDD: default expansion device|
+//!
+//! NES 2.0 ROM sizes with an MSB nibble of $F are in exponent-multiplier
+//! form, EEEE EEMM for 2^E * (MM * 2 + 1) bytes. RAM sizes are 64 << shift
+//! bytes, or none for a shift count of 0. A NES 2.0 header without any CHR
+//! gets 8KB of CHR-RAM, as an iNES 1.0 one does.
+
+use std::fmt;
+
+use super::{Cartridge, Console, Mirroring, Timing};
+
+const MAGIC: &[u8; 4] = b"NES\x1A";
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x2000;
+const PRG_RAM_UNIT: usize = 0x2000;
+/// The smallest PRG bank any mapper switches.
+const MIN_PRG_ROM_SIZE: usize = 0x2000;
+
+// Flags 6
+const VERTICAL: u8 = 0x01;
+const BATTERY: u8 = 0x02;
+const TRAINER: u8 = 0x04;
+const FOUR_SCREEN: u8 = 0x08;
+
+// Flags 7
+const VS_SYSTEM: u8 = 0x01;
+const PLAYCHOICE_10: u8 = 0x02;
+const FORMAT_MASK: u8 = 0x0C;
+const NES2_FORMAT: u8 = 0x08;
+
+/// Why a ROM image could not be loaded.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RomError {
+    /// The file does not start with "NES\x1A".
+    BadMagic,
+    /// The file ends before `section` does.
+    Truncated {
+        section: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    /// The header declares no PRG-ROM, leaving nothing to run.
+    NoPrgRom,
+    /// The header declares less PRG-ROM than one 8KB bank.
+    PrgRomTooSmall(usize),
+    /// No board is emulated for this iNES mapper number.
+    UnsupportedMapper(u16),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::BadMagic => write!(f, "Not an iNES image, missing the NES<EOF> magic"),
+            RomError::Truncated {
+                section,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Truncated {section}: expected {expected} bytes, found {found}"
+            ),
+            RomError::NoPrgRom => write!(f, "The header declares no PRG-ROM"),
+            RomError::PrgRomTooSmall(size) => {
+                write!(f, "{size} bytes of PRG-ROM is less than one 8KB bank")
+            }
+            RomError::UnsupportedMapper(mapper) => write!(f, "Mapper {mapper} is not supported"),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+/// Splits the image into its sections, front to back.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, section: &'static str, len: usize) -> Result<&'a [u8], RomError> {
+        if self.bytes.len() < len {
+            return Err(RomError::Truncated {
+                section,
+                expected: len,
+                found: self.bytes.len(),
+            });
+        }
+        let (section, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(section)
+    }
+}
+
+/// What the header says about the board, past the flags both formats share.
+struct Board {
+    mapper: u16,
+    submapper: u8,
+    prg_rom_size: usize,
+    chr_rom_size: usize,
+    prg_ram_size: usize,
+    prg_nvram_size: usize,
+    chr_ram_size: usize,
+    chr_nvram_size: usize,
+    timing: Timing,
+    console: Console,
+    expansion_device: u8,
+}
+
+impl Board {
+    fn ines(header: &[u8]) -> Self {
+        let battery = header[6] & BATTERY != 0;
+        // Old dumping tools left their name in bytes 7-15, in which case
+        // none of them mean anything
+        let clean = header[12..16].iter().all(|&b| b == 0);
+        let [flags7, prg_ram_units, flags9] = if clean {
+            [header[7], header[8], header[9]]
+        } else {
+            [0; 3]
+        };
+
+        // Zero means 8KB, for compatibility with headers predating byte 8
+        let prg_ram_size = prg_ram_units.max(1) as usize * PRG_RAM_UNIT;
+        let console = if flags7 & VS_SYSTEM != 0 {
+            Console::VsSystem {
+                ppu: 0,
+                hardware: 0,
+            }
+        } else if flags7 & PLAYCHOICE_10 != 0 {
+            Console::Playchoice10
+        } else {
+            Console::Nes
+        };
+        Board {
+            mapper: (flags7 & 0xF0 | header[6] >> 4) as u16,
+            submapper: 0,
+            prg_rom_size: header[4] as usize * PRG_BANK_SIZE,
+            chr_rom_size: header[5] as usize * CHR_BANK_SIZE,
+            prg_ram_size: if battery { 0 } else { prg_ram_size },
+            prg_nvram_size: if battery { prg_ram_size } else { 0 },
+            chr_ram_size: if header[5] == 0 { CHR_BANK_SIZE } else { 0 },
+            chr_nvram_size: 0,
+            timing: if flags9 & 0x01 != 0 {
+                Timing::Pal
+            } else {
+                Timing::Ntsc
+            },
+            console,
+            expansion_device: 0,
+        }
+    }
+
+    fn nes2(header: &[u8]) -> Self {
+        let console = match header[7] & 0x03 {
+            0 => Console::Nes,
+            1 => Console::VsSystem {
+                ppu: header[13] & 0x0F,
+                hardware: header[13] >> 4,
+            },
+            2 => Console::Playchoice10,
+            _ => Console::Extended(header[13] & 0x0F),
+        };
+        let chr_rom_size = rom_size(header[5], header[9] >> 4, CHR_BANK_SIZE);
+        // A board needs CHR of some kind, assume the usual 8KB of CHR-RAM
+        let chr_ram_size = if chr_rom_size == 0 && header[11] == 0 {
+            CHR_BANK_SIZE
+        } else {
+            ram_size(header[11] & 0x0F)
+        };
+        Board {
+            mapper: (header[8] as u16 & 0x0F) << 8 | (header[7] & 0xF0 | header[6] >> 4) as u16,
+            submapper: header[8] >> 4,
+            prg_rom_size: rom_size(header[4], header[9] & 0x0F, PRG_BANK_SIZE),
+            chr_rom_size,
+            prg_ram_size: ram_size(header[10] & 0x0F),
+            prg_nvram_size: ram_size(header[10] >> 4),
+            chr_ram_size,
+            chr_nvram_size: ram_size(header[11] >> 4),
+            timing: match header[12] & 0x03 {
+                0 => Timing::Ntsc,
+                1 => Timing::Pal,
+                2 => Timing::MultiRegion,
+                _ => Timing::Dendy,
+            },
+            console,
+            expansion_device: header[15] & 0x3F,
+        }
+    }
+}
+
+/// NES 2.0 ROM size in bytes from its LSB and MSB nibble.
+fn rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+    if msb == 0x0F {
+        let multiplier = (lsb & 0x03) as usize * 2 + 1;
+        // Sizes that do not fit cannot be in the file either
+        1usize
+            .checked_shl((lsb >> 2) as u32)
+            .and_then(|size| size.checked_mul(multiplier))
+            .unwrap_or(usize::MAX)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * unit
+    }
+}
+
+/// NES 2.0 RAM size in bytes from its shift count.
+fn ram_size(shift: u8) -> usize {
+    if shift == 0 { 0 } else { 64 << shift }
+}
+
+impl Cartridge {
+    /// Loads an iNES or NES 2.0 image. Anything after the CHR-ROM, such as
+    /// NES 2.0 miscellaneous ROMs, is ignored.
+    pub fn from_ines(bytes: &[u8]) -> Result<Self, RomError> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(RomError::BadMagic);
+        }
+        let mut reader = Reader { bytes };
+        let header = reader.take("header", HEADER_SIZE)?;
+        let flags6 = header[6];
+        let nes2 = header[7] & FORMAT_MASK == NES2_FORMAT;
+        let board = if nes2 {
+            Board::nes2(header)
+        } else {
+            Board::ines(header)
+        };
+
+        let mirroring = if flags6 & FOUR_SCREEN != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & VERTICAL != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+
+        let trainer = if flags6 & TRAINER != 0 {
+            Some(reader.take("trainer", TRAINER_SIZE)?.to_vec())
+        } else {
+            None
+        };
+
+        match board.prg_rom_size {
+            0 => return Err(RomError::NoPrgRom),
+            size if size < MIN_PRG_ROM_SIZE => return Err(RomError::PrgRomTooSmall(size)),
+            _ => {}
+        }
+        let prg_rom = reader.take("PRG-ROM", board.prg_rom_size)?.to_vec();
+
+        let chr_is_ram = board.chr_rom_size == 0;
+        let chr = if chr_is_ram {
+            vec![0; board.chr_ram_size + board.chr_nvram_size]
+        } else {
+            reader.take("CHR-ROM", board.chr_rom_size)?.to_vec()
+        };
+
+        Ok(Cartridge {
+            nes2,
+            mapper: board.mapper,
+            submapper: board.submapper,
+            mirroring,
+            battery: flags6 & BATTERY != 0,
+            trainer,
+            prg_rom,
+            chr,
+            chr_is_ram,
+            prg_ram_size: board.prg_ram_size,
+            prg_nvram_size: board.prg_nvram_size,
+            chr_ram_size: board.chr_ram_size,
+            chr_nvram_size: board.chr_nvram_size,
+            timing: board.timing,
+            console: board.console,
+            expansion_device: board.expansion_device,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(prg_banks: u8, chr_banks: u8, flags6: u8, flags7: u8) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend([prg_banks, chr_banks, flags6, flags7]);
+        bytes.resize(HEADER_SIZE, 0);
+        if flags6 & TRAINER != 0 {
+            bytes.extend([0x77; TRAINER_SIZE]);
+        }
+        bytes.extend(vec![0xAA; prg_banks as usize * PRG_BANK_SIZE]);
+        bytes.extend(vec![0xCC; chr_banks as usize * CHR_BANK_SIZE]);
+        bytes
+    }
+
+    #[test]
+    fn splits_prg_and_chr_rom() {
+        let cartridge = Cartridge::from_ines(&image(2, 1, 0x10, 0x40)).unwrap();
+        assert_eq!(cartridge.mapper, 0x41);
+        assert_eq!(cartridge.mirroring, Mirroring::Horizontal);
+        assert!(!cartridge.battery);
+        assert_eq!(cartridge.trainer, None);
+        assert_eq!(cartridge.prg_rom, vec![0xAA; 0x8000]);
+        assert_eq!(cartridge.chr, vec![0xCC; 0x2000]);
+        assert!(!cartridge.chr_is_ram);
+    }
+
+    #[test]
+    fn reads_flags() {
+        let cartridge = Cartridge::from_ines(&image(1, 1, TRAINER | BATTERY, 0)).unwrap();
+        assert!(cartridge.battery);
+        assert_eq!(cartridge.trainer, Some(vec![0x77; TRAINER_SIZE]));
+        assert_eq!(cartridge.prg_rom[0], 0xAA);
+
+        let cartridge = Cartridge::from_ines(&image(1, 1, FOUR_SCREEN | VERTICAL, 0)).unwrap();
+        assert_eq!(cartridge.mirroring, Mirroring::FourScreen);
+    }
+
+    #[test]
+    fn allocates_chr_ram_without_chr_rom() {
+        let cartridge = Cartridge::from_ines(&image(1, 0, 0, 0)).unwrap();
+        assert!(cartridge.chr_is_ram);
+        assert_eq!(cartridge.chr, vec![0; 0x2000]);
+    }
+
+    #[test]
+    fn ignores_mapper_high_nibble_from_dirty_headers() {
+        let mut bytes = image(1, 1, 0x40, 0x44);
+        bytes[7..16].copy_from_slice(b"DiskDude!");
+        assert_eq!(Cartridge::from_ines(&bytes).unwrap().mapper, 4);
+    }
+
+    #[test]
+    fn rejects_malformed_images() {
+        assert_eq!(
+            Cartridge::from_ines(b"UNIF\x00\x00\x00\x00"),
+            Err(RomError::BadMagic)
+        );
+        assert_eq!(
+            Cartridge::from_ines(b"NES\x1A\x01\x01"),
+            Err(RomError::Truncated {
+                section: "header",
+                expected: 16,
+                found: 6
+            })
+        );
+        assert_eq!(
+            Cartridge::from_ines(&image(0, 1, 0, 0)),
+            Err(RomError::NoPrgRom)
+        );
+
+        let mut bytes = image(2, 1, 0, 0);
+        bytes.truncate(HEADER_SIZE + 0x8000 + 100);
+        let error = Cartridge::from_ines(&bytes).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Truncated CHR-ROM: expected 8192 bytes, found 100"
+        );
+    }
+
+    /// Bytes 4-15 of the header, followed by zeroed ROM of the given sizes.
+    fn nes2_image(header: [u8; 12], prg_rom_size: usize, chr_rom_size: usize) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(header);
+        bytes.resize(HEADER_SIZE + prg_rom_size + chr_rom_size, 0);
+        bytes
+    }
+
+    #[test]
+    fn ines_images_get_usual_board_defaults() {
+        let cartridge = Cartridge::from_ines(&image(1, 0, BATTERY, 0)).unwrap();
+        assert!(!cartridge.nes2);
+        assert_eq!(
+            (cartridge.prg_ram_size, cartridge.prg_nvram_size),
+            (0, 0x2000)
+        );
+        assert_eq!(
+            (cartridge.chr_ram_size, cartridge.chr_nvram_size),
+            (0x2000, 0)
+        );
+        assert_eq!(cartridge.timing, Timing::Ntsc);
+        assert_eq!(cartridge.console, Console::Nes);
+
+        let cartridge = Cartridge::from_ines(&image(1, 1, 0, VS_SYSTEM)).unwrap();
+        assert_eq!(cartridge.prg_ram_size, 0x2000);
+        assert_eq!(cartridge.chr_ram_size, 0);
+        assert_eq!(
+            cartridge.console,
+            Console::VsSystem {
+                ppu: 0,
+                hardware: 0
+            }
+        );
+    }
+
+    #[test]
+    fn reads_nes2_mapper_and_submapper() {
+        let bytes = nes2_image(
+            [0x01, 0x00, 0x10, 0x28, 0x51, 0, 0, 0x07, 0, 0, 0, 0],
+            0x4000,
+            0,
+        );
+        let cartridge = Cartridge::from_ines(&bytes).unwrap();
+        assert!(cartridge.nes2);
+        assert_eq!(cartridge.mapper, 0x121);
+        assert_eq!(cartridge.submapper, 5);
+        assert!(cartridge.chr_is_ram);
+        assert_eq!(cartridge.chr.len(), 0x2000);
+    }
+
+    #[test]
+    fn reads_nes2_rom_and_ram_sizes() {
+        // 0x101 PRG banks, CHR-ROM as 2^13 * 1, 8KB of PRG-NVRAM, 32KB of
+        // CHR-RAM next to the CHR-ROM
+        let bytes = nes2_image(
+            [0x01, 0x34, 0x02, 0x08, 0x00, 0xF1, 0x70, 0x09, 0, 0, 0, 0],
+            0x101 * 0x4000,
+            0x2000,
+        );
+        let cartridge = Cartridge::from_ines(&bytes).unwrap();
+        assert_eq!(cartridge.prg_rom.len(), 0x101 * 0x4000);
+        assert_eq!(cartridge.chr.len(), 0x2000);
+        assert!(!cartridge.chr_is_ram);
+        assert!(cartridge.battery);
+        assert_eq!(
+            (cartridge.prg_ram_size, cartridge.prg_nvram_size),
+            (0, 0x2000)
+        );
+        assert_eq!(
+            (cartridge.chr_ram_size, cartridge.chr_nvram_size),
+            (0x8000, 0)
+        );
+
+        // 2^2 * 1 bytes of PRG-ROM, not even a bank
+        let bytes = nes2_image([0x08, 0, 0, 0x08, 0, 0x0F, 0, 0, 0, 0, 0, 0], 4, 0);
+        assert_eq!(
+            Cartridge::from_ines(&bytes),
+            Err(RomError::PrgRomTooSmall(4))
+        );
+
+        // 2^63 * 3 bytes of PRG-ROM
+        let bytes = nes2_image([0xFD, 0, 0, 0x08, 0, 0x0F, 0, 0, 0, 0, 0, 0], 0, 0);
+        assert_eq!(
+            Cartridge::from_ines(&bytes),
+            Err(RomError::Truncated {
+                section: "PRG-ROM",
+                expected: usize::MAX,
+                found: 0
+            })
+        );
+    }
+
+    #[test]
+    fn nes2_without_chr_gets_chr_ram() {
+        let bytes = nes2_image([0x01, 0x00, 0x00, 0x08, 0, 0, 0, 0, 0, 0, 0, 0], 0x4000, 0);
+        let cartridge = Cartridge::from_ines(&bytes).unwrap();
+        assert!(cartridge.chr_is_ram);
+        assert_eq!(cartridge.chr_ram_size, 0x2000);
+        let mut mapper = cartridge.into_mapper().unwrap();
+        mapper.ppu_write(0x1FFF, 0x42);
+        assert_eq!(mapper.ppu_peek(0x1FFF), 0x42);
+    }
+
+    #[test]
+    fn reads_nes2_system_fields() {
+        let bytes = nes2_image(
+            [0x01, 0x01, 0, 0x09, 0, 0, 0, 0, 0x03, 0x21, 0, 0x08],
+            0x4000,
+            0x2000,
+        );
+        let cartridge = Cartridge::from_ines(&bytes).unwrap();
+        assert_eq!(cartridge.timing, Timing::Dendy);
+        assert_eq!(
+            cartridge.console,
+            Console::VsSystem {
+                ppu: 1,
+                hardware: 2
+            }
+        );
+        assert_eq!(cartridge.expansion_device, 8);
+
+        let bytes = nes2_image(
+            [0x01, 0x01, 0, 0x0B, 0, 0, 0, 0, 0x02, 0x03, 0, 0],
+            0x4000,
+            0x2000,
+        );
+        let cartridge = Cartridge::from_ines(&bytes).unwrap();
+        assert_eq!(cartridge.timing, Timing::MultiRegion);
+        assert_eq!(cartridge.console, Console::Extended(3));
+    }
+}
@@ -0,0 +1,362 @@
+//! MMC1, iNES mapper 1, the SxROM boards.
+//!
+//! The registers are loaded serially: five writes to $8000-$FFFF shift bit 0
+//! in, LSB first, and the fifth picks the register from address bits 13-14:
+//!
+//! | Address     | Register                                                 |
+//! |-------------|----------------------------------------------------------|
+//! | $8000-$9FFF | Control: CPPMM, CHR mode, PRG mode, mirroring            |
+//! | $A000-$BFFF | CHR bank 0, 4KB at $0000 or 8KB at $0000 (bit 0 ignored) |
+//! | $C000-$DFFF | CHR bank 1, 4KB at $1000                                 |
+//! | $E000-$FFFF | PRG bank, RPPPP: PRG-RAM disable and 16KB bank           |
+//!
+//! A write with bit 7 set resets the shift register and locks the last PRG
+//! bank at $C000. Writes on consecutive CPU cycles, as a read-modify-write
+//! instruction makes, only count once.
+//!
+//! Boards with 8KB of CHR have spare CHR bank bits, which SNROM, SOROM, SUROM
+//! and SXROM use for PRG-RAM disable, PRG-RAM banks and a 256KB PRG outer bank.
+
+use super::{Cartridge, Mapper, Mirroring};
+
+const SHIFT_RESET: u8 = 0x10; // the marker bit reaches bit 0 on the fifth write
+const PRG_MODE_FIX_LAST: u8 = 0x0C;
+const CHR_MODE_4KB: u8 = 0x10;
+const PRG_RAM_DISABLE: u8 = 0x10;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+const PRG_RAM_BANK_SIZE: usize = 0x2000;
+
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    shift: u8,
+    control: u8,
+    chr_banks: [u8; 2],
+    prg_bank: u8,
+    cycle: u64,
+    last_write: Option<u64>,
+    ppu_a12: bool, // picks the CHR bank register that drives the spare bits
+}
+
+impl Mmc1 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Mmc1 {
+            prg_rom: cartridge.prg_rom,
+            prg_ram: vec![0; cartridge.prg_ram_size + cartridge.prg_nvram_size],
+            chr: cartridge.chr,
+            chr_is_ram: cartridge.chr_is_ram,
+            shift: SHIFT_RESET,
+            control: PRG_MODE_FIX_LAST,
+            chr_banks: [0; 2],
+            prg_bank: 0,
+            cycle: 0,
+            last_write: None,
+            ppu_a12: false,
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        if value & 0x80 != 0 {
+            self.shift = SHIFT_RESET;
+            self.control |= PRG_MODE_FIX_LAST;
+            return;
+        }
+        let done = self.shift & 0x01 != 0;
+        self.shift = (self.shift >> 1) | ((value & 0x01) << 4);
+        if done {
+            match address & 0x6000 {
+                0x0000 => self.control = self.shift,
+                0x2000 => self.chr_banks[0] = self.shift,
+                0x4000 => self.chr_banks[1] = self.shift,
+                _ => self.prg_bank = self.shift,
+            }
+            self.shift = SHIFT_RESET;
+        }
+    }
+
+    /// The CHR bank register whose spare bits are on the board's lines now.
+    fn board_bits(&self) -> u8 {
+        if self.control & CHR_MODE_4KB != 0 && self.ppu_a12 {
+            self.chr_banks[1]
+        } else {
+            self.chr_banks[0]
+        }
+    }
+
+    fn small_chr(&self) -> bool {
+        self.chr.len() <= 0x2000
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> usize {
+        // SUROM and SXROM select the 256KB half with CHR bank bit 4
+        let outer = if self.small_chr() && self.prg_rom.len() > 0x40000 {
+            (self.board_bits() & 0x10) as usize
+        } else {
+            0
+        };
+        let bank = self.prg_bank as usize & 0x0F;
+        let bank = match (self.control >> 2) & 0x03 {
+            0 | 1 => (bank & !1) | (address as usize >> 14 & 1),
+            2 if address < 0xC000 => 0,
+            2 => bank,
+            _ if address < 0xC000 => bank,
+            _ => 0x0F,
+        };
+        let banks = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+        let offset = ((outer | bank) % banks) * PRG_BANK_SIZE + (address as usize & 0x3FFF);
+        // 8KB of PRG mirrors within the bank
+        offset % self.prg_rom.len()
+    }
+
+    fn prg_ram_offset(&self, address: u16) -> Option<usize> {
+        if self.prg_ram.is_empty() || self.prg_bank & PRG_RAM_DISABLE != 0 {
+            return None;
+        }
+        let bits = self.board_bits();
+        let bank = if !self.small_chr() {
+            0
+        } else {
+            match self.prg_ram.len() / PRG_RAM_BANK_SIZE {
+                // SNROM disables its RAM with CHR bank bit 4
+                1 if self.chr_is_ram && self.prg_rom.len() <= 0x40000 && bits & 0x10 != 0 => {
+                    return None;
+                }
+                // SOROM
+                2 => (bits >> 3) & 0x01,
+                // SXROM
+                4 => (bits >> 2) & 0x03,
+                _ => 0,
+            }
+        };
+        Some(bank as usize * PRG_RAM_BANK_SIZE + (address as usize & 0x1FFF))
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let bank = if self.control & CHR_MODE_4KB != 0 {
+            self.chr_banks[(address >> 12) as usize & 1] as usize
+        } else {
+            (self.chr_banks[0] as usize & !1) | (address >> 12) as usize & 1
+        };
+        let banks = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        (bank % banks) * CHR_BANK_SIZE + (address as usize & 0x0FFF)
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF => self.prg_ram_offset(address).map(|i| self.prg_ram[i]),
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_rom_offset(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF => {
+                if let Some(i) = self.prg_ram_offset(address) {
+                    self.prg_ram[i] = value;
+                }
+            }
+            0x8000..=0xFFFF => {
+                let consecutive = self.last_write == Some(self.cycle);
+                self.last_write = Some(self.cycle);
+                if !consecutive {
+                    self.write_register(address, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let i = self.chr_offset(address);
+            self.chr[i] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    /// Consecutive writes are told apart by the cycles in between. The bus
+    /// clocks the mapper after each instruction, so both writes of a
+    /// read-modify-write land on the same count.
+    fn cpu_cycle(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn ppu_address(&mut self, address: u16) {
+        self.ppu_a12 = address & 0x1000 != 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::prg_banks;
+
+    /// Serially loads `value` into the register at `address`.
+    fn load(mmc1: &mut Mmc1, address: u16, value: u8) {
+        for bit in 0..5 {
+            mmc1.cpu_write(address, value >> bit);
+            mmc1.cpu_cycle();
+        }
+    }
+
+    fn mmc1(prg_rom_size: usize, chr_rom_size: usize) -> Mmc1 {
+        Mmc1::new(Cartridge::numbered(1, prg_rom_size, chr_rom_size))
+    }
+
+    /// 16KB PRG bank mapped at `address`, from the numbered 8KB banks.
+    fn prg_bank_at(mmc1: &Mmc1, address: u16) -> u8 {
+        mmc1.cpu_peek(address).unwrap() / 2
+    }
+
+    #[test]
+    fn powers_on_with_the_last_bank_fixed() {
+        let mmc1 = mmc1(0x40000, 0x20000);
+        assert_eq!(prg_bank_at(&mmc1, 0x8000), 0);
+        assert_eq!(prg_bank_at(&mmc1, 0xC000), 15);
+    }
+
+    #[test]
+    fn small_prg_mirrors() {
+        let mut mmc1 = mmc1(0x2000, 0x2000);
+        load(&mut mmc1, 0xE000, 0x05);
+        assert_eq!(prg_banks(&mmc1), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn fifth_write_selects_the_register() {
+        let mut mmc1 = mmc1(0x40000, 0x20000);
+        load(&mut mmc1, 0xE000, 0x05);
+        assert_eq!(prg_bank_at(&mmc1, 0x8000), 5);
+        load(&mut mmc1, 0x9FFF, 0x02);
+        assert_eq!(mmc1.mirroring(), Mirroring::Vertical);
+        load(&mut mmc1, 0x8000, 0x03);
+        assert_eq!(mmc1.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn bit_7_resets_the_shift_register() {
+        let mut mmc1 = mmc1(0x40000, 0x20000);
+        // Mode 2 fixes the first bank at $8000
+        load(&mut mmc1, 0x8000, 0x08);
+        load(&mut mmc1, 0xE000, 0x03);
+        mmc1.cpu_write(0xE000, 0x01);
+        mmc1.cpu_cycle();
+        mmc1.cpu_write(0x8000, 0x80);
+        mmc1.cpu_cycle();
+        assert_eq!(prg_bank_at(&mmc1, 0x8000), 3);
+        assert_eq!(prg_bank_at(&mmc1, 0xC000), 15);
+        load(&mut mmc1, 0xE000, 0x06);
+        assert_eq!(prg_bank_at(&mmc1, 0x8000), 6);
+    }
+
+    #[test]
+    fn consecutive_writes_count_once() {
+        let mut mmc1 = mmc1(0x40000, 0x20000);
+        for bit in 0..5 {
+            // Both writes of a read-modify-write
+            mmc1.cpu_write(0xE000, 0x01);
+            mmc1.cpu_write(0xE000, 0x01);
+            if bit < 4 {
+                mmc1.cpu_cycle();
+            }
+        }
+        assert_eq!(prg_bank_at(&mmc1, 0x8000), 0x0F);
+    }
+
+    #[test]
+    fn prg_modes() {
+        let mut mmc1 = mmc1(0x40000, 0x20000);
+        load(&mut mmc1, 0xE000, 0x05);
+        load(&mut mmc1, 0x8000, 0x00);
+        assert_eq!(prg_bank_at(&mmc1, 0x8000), 4);
+        assert_eq!(prg_bank_at(&mmc1, 0xC000), 5);
+        load(&mut mmc1, 0x8000, 0x08);
+        assert_eq!(prg_bank_at(&mmc1, 0x8000), 0);
+        assert_eq!(prg_bank_at(&mmc1, 0xC000), 5);
+        load(&mut mmc1, 0x8000, 0x0C);
+        assert_eq!(prg_bank_at(&mmc1, 0x8000), 5);
+        assert_eq!(prg_bank_at(&mmc1, 0xC000), 15);
+    }
+
+    #[test]
+    fn chr_modes() {
+        let mut mmc1 = mmc1(0x40000, 0x20000);
+        load(&mut mmc1, 0xA000, 0x05);
+        load(&mut mmc1, 0xC000, 0x09);
+        // 8KB mode ignores bit 0 and bank 1
+        assert_eq!(mmc1.ppu_peek(0x0000), 4 * 4);
+        assert_eq!(mmc1.ppu_peek(0x1000), 5 * 4);
+        load(&mut mmc1, 0x8000, 0x1C);
+        assert_eq!(mmc1.ppu_peek(0x0000), 5 * 4);
+        assert_eq!(mmc1.ppu_peek(0x1FFF), 9 * 4 + 3);
+    }
+
+    #[test]
+    fn prg_ram_can_be_disabled() {
+        let mut mmc1 = mmc1(0x40000, 0x20000);
+        mmc1.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc1.cpu_peek(0x6000), Some(0x42));
+        load(&mut mmc1, 0xE000, PRG_RAM_DISABLE);
+        assert_eq!(mmc1.cpu_peek(0x6000), None);
+        mmc1.cpu_write(0x6000, 0x00);
+        load(&mut mmc1, 0xE000, 0x00);
+        assert_eq!(mmc1.cpu_peek(0x6000), Some(0x42));
+    }
+
+    #[test]
+    fn snrom_disables_prg_ram_with_chr_bit_4() {
+        let mut mmc1 = mmc1(0x40000, 0);
+        mmc1.cpu_write(0x6000, 0x42);
+        load(&mut mmc1, 0xA000, 0x10);
+        assert_eq!(mmc1.cpu_peek(0x6000), None);
+        load(&mut mmc1, 0xA000, 0x00);
+        assert_eq!(mmc1.cpu_peek(0x6000), Some(0x42));
+        mmc1.ppu_write(0x1234, 0x77);
+        assert_eq!(mmc1.ppu_peek(0x1234), 0x77);
+    }
+
+    #[test]
+    fn sorom_banks_prg_ram_with_chr_bit_3() {
+        let mut cartridge = Cartridge::numbered(1, 0x40000, 0);
+        cartridge.prg_nvram_size = 0x2000;
+        let mut mmc1 = Mmc1::new(cartridge);
+        mmc1.cpu_write(0x6000, 0x11);
+        load(&mut mmc1, 0xA000, 0x08);
+        assert_eq!(mmc1.cpu_peek(0x6000), Some(0x00));
+        mmc1.cpu_write(0x6000, 0x22);
+        load(&mut mmc1, 0xA000, 0x00);
+        assert_eq!(mmc1.cpu_peek(0x6000), Some(0x11));
+    }
+
+    #[test]
+    fn surom_selects_the_256kb_half_with_chr_bit_4() {
+        let mut mmc1 = mmc1(0x80000, 0);
+        assert_eq!(prg_bank_at(&mmc1, 0xC000), 15);
+        load(&mut mmc1, 0xA000, 0x10);
+        load(&mut mmc1, 0xE000, 0x02);
+        assert_eq!(prg_bank_at(&mmc1, 0x8000), 0x12);
+        assert_eq!(prg_bank_at(&mmc1, 0xC000), 0x1F);
+        // The RAM stays enabled, bit 4 is the outer bank here
+        mmc1.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc1.cpu_peek(0x6000), Some(0x42));
+    }
+}
@@ -0,0 +1,206 @@
+//! MMC2 (iNES mapper 9, PxROM) and MMC4 (iNES mapper 10, FxROM).
+//!
+//! | Address     | Register                                        |
+//! |-------------|-------------------------------------------------|
+//! | $A000-$AFFF | PRG bank at $8000: 8KB on MMC2, 16KB on MMC4    |
+//! | $B000-$BFFF | 4KB CHR bank at $0000 while latch 0 holds $FD   |
+//! | $C000-$CFFF | 4KB CHR bank at $0000 while latch 0 holds $FE   |
+//! | $D000-$DFFF | 4KB CHR bank at $1000 while latch 1 holds $FD   |
+//! | $E000-$EFFF | 4KB CHR bank at $1000 while latch 1 holds $FE   |
+//! | $F000-$FFFF | Mirroring: 0 vertical, 1 horizontal             |
+//!
+//! The rest of $8000-$FFFF is fixed to the last banks. Each pattern table has
+//! a latch that the PPU sets by fetching tile $FD or $FE from it: reading
+//! $xFD8-$xFDF or $xFE8-$xFEF switches the bank after the read. On MMC2 the
+//! latch for $0000 only reacts to $0FD8 and $0FE8.
+
+use super::{Cartridge, Mapper, Mirroring};
+
+const CHR_BANK_SIZE: usize = 0x1000;
+
+pub struct Mmc2 {
+    mmc4: bool,
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    prg_bank: u8,
+    chr_banks: [[u8; 2]; 2], // per pattern table, for $FD and $FE
+    latches: [bool; 2],      // true for $FE
+    mirroring: Mirroring,
+}
+
+impl Mmc2 {
+    pub fn new(cartridge: Cartridge, mmc4: bool) -> Self {
+        Mmc2 {
+            mmc4,
+            prg_rom: cartridge.prg_rom,
+            prg_ram: vec![0; cartridge.prg_ram_size + cartridge.prg_nvram_size],
+            chr: cartridge.chr,
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            latches: [true; 2],
+            mirroring: cartridge.mirroring,
+        }
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> usize {
+        let (size, switchable) = if self.mmc4 {
+            (0x4000, 0xC000)
+        } else {
+            (0x2000, 0xA000)
+        };
+        let banks = (self.prg_rom.len() / size).max(1);
+        let bank = if address < switchable {
+            self.prg_bank as usize % banks
+        } else {
+            // The fixed banks are the last ones, mirrored when there are fewer
+            let from_end = (0x10000 - (address as usize & !(size - 1))) / size;
+            (banks * 4 - from_end) % banks
+        };
+        // Less than a bank mirrors within it
+        (bank * size + (address as usize & (size - 1))) % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let table = (address >> 12) as usize & 1;
+        let bank = self.chr_banks[table][self.latches[table] as usize] as usize;
+        let banks = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        (bank % banks) * CHR_BANK_SIZE + (address as usize & 0x0FFF)
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(address as usize - 0x6000) % self.prg_ram.len()])
+            }
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_rom_offset(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(address as usize - 0x6000) % len] = value;
+            }
+            0xA000..=0xAFFF => self.prg_bank = value & 0x0F,
+            0xB000..=0xEFFF => {
+                let register = (address as usize - 0xB000) >> 12;
+                self.chr_banks[register >> 1][register & 1] = value & 0x1F;
+            }
+            0xF000..=0xFFFF => {
+                self.mirroring = if value & 0x01 != 0 {
+                    Mirroring::Horizontal
+                } else {
+                    Mirroring::Vertical
+                };
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn ppu_write(&mut self, _address: u16, _value: u8) {}
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        let value = self.ppu_peek(address);
+        let table = (address >> 12) as usize & 1;
+        let exact = table == 0 && !self.mmc4;
+        match address & 0x0FFF {
+            0x0FD8 => self.latches[table] = false,
+            0x0FE8 => self.latches[table] = true,
+            0x0FD9..=0x0FDF if !exact => self.latches[table] = false,
+            0x0FE9..=0x0FEF if !exact => self.latches[table] = true,
+            _ => {}
+        }
+        value
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::prg_banks;
+
+    fn mmc2(mmc4: bool) -> Mmc2 {
+        Mmc2::new(Cartridge::numbered(9, 0x20000, 0x20000), mmc4)
+    }
+
+    /// The CHR bank numbers switched in at $0000 and $1000, in 4KB units.
+    fn chr_banks(mmc2: &Mmc2) -> [u8; 2] {
+        [mmc2.ppu_peek(0x0000) / 4, mmc2.ppu_peek(0x1000) / 4]
+    }
+
+    #[test]
+    fn prg_bank_sizes() {
+        let mut mmc2 = mmc2(false);
+        mmc2.cpu_write(0xA000, 3);
+        assert_eq!(prg_banks(&mmc2), [3, 13, 14, 15]);
+
+        let mut mmc4 = self::mmc2(true);
+        mmc4.cpu_write(0xA000, 3);
+        assert_eq!(prg_banks(&mmc4), [6, 7, 14, 15]);
+    }
+
+    #[test]
+    fn fixed_banks_mirror_on_small_prg() {
+        let mut mmc2 = Mmc2::new(Cartridge::numbered(9, 0x4000, 0x20000), false);
+        mmc2.cpu_write(0xA000, 3);
+        assert_eq!(prg_banks(&mmc2), [1, 1, 0, 1]);
+        let mmc4 = Mmc2::new(Cartridge::numbered(10, 0x4000, 0x20000), true);
+        assert_eq!(prg_banks(&mmc4), [0, 1, 0, 1]);
+        let mmc4 = Mmc2::new(Cartridge::numbered(10, 0x2000, 0x20000), true);
+        assert_eq!(prg_banks(&mmc4), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn tile_fetches_flip_the_latches() {
+        let mut mmc2 = mmc2(false);
+        for (address, bank) in [(0xB000, 1), (0xC000, 2), (0xD000, 3), (0xE000, 4)] {
+            mmc2.cpu_write(address, bank);
+        }
+        assert_eq!(chr_banks(&mmc2), [2, 4]);
+        // The fetch itself still sees the old bank
+        assert_eq!(mmc2.ppu_read(0x1FD8) / 4, 4);
+        assert_eq!(chr_banks(&mmc2), [2, 3]);
+        mmc2.ppu_read(0x0FD8);
+        assert_eq!(chr_banks(&mmc2), [1, 3]);
+        mmc2.ppu_read(0x1FEF);
+        assert_eq!(chr_banks(&mmc2), [1, 4]);
+        // Only $0FE8 itself on MMC2
+        mmc2.ppu_read(0x0FEA);
+        assert_eq!(chr_banks(&mmc2), [1, 4]);
+        mmc2.ppu_read(0x0FE8);
+        assert_eq!(chr_banks(&mmc2), [2, 4]);
+    }
+
+    #[test]
+    fn mmc4_latch_0_takes_the_whole_row() {
+        let mut mmc4 = mmc2(true);
+        mmc4.cpu_write(0xB000, 5);
+        mmc4.ppu_read(0x0FDC);
+        assert_eq!(chr_banks(&mmc4)[0], 5);
+        // Peeks leave the latches alone
+        mmc4.ppu_peek(0x0FE8);
+        assert_eq!(chr_banks(&mmc4)[0], 5);
+    }
+
+    #[test]
+    fn mirroring_register() {
+        let mut mmc2 = mmc2(false);
+        mmc2.cpu_write(0xF000, 1);
+        assert_eq!(mmc2.mirroring(), Mirroring::Horizontal);
+        mmc2.cpu_write(0xFFFF, 0);
+        assert_eq!(mmc2.mirroring(), Mirroring::Vertical);
+    }
+}
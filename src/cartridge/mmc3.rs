@@ -0,0 +1,400 @@
+//! MMC3, iNES mapper 4, the TxROM boards.
+//!
+//! Registers are decoded from address bit 0 and bits 13-14:
+//!
+//! | Address     | Even                        | Odd                         |
+//! |-------------|-----------------------------|-----------------------------|
+//! | $8000-$9FFF | Bank select: CP...RRR       | Bank data for register RRR  |
+//! | $A000-$BFFF | Mirroring: 0 vertical       | PRG-RAM: enable, deny write |
+//! | $C000-$DFFF | IRQ latch                   | IRQ reload                  |
+//! | $E000-$FFFF | IRQ disable and acknowledge | IRQ enable                  |
+//!
+//! R0 and R1 are 2KB CHR banks, R2-R5 1KB CHR banks; C swaps them between the
+//! pattern tables. R6 and R7 are 8KB PRG banks; P swaps R6 with the fixed
+//! second-to-last bank.
+//!
+//! The scanline counter is clocked by rising edges of PPU A12, which rises
+//! once per line when the background and the sprites use different pattern
+//! tables. Edges after A12 was low for only a few dots, as between the sprite
+//! fetches, are filtered out.
+
+use super::{Cartridge, Mapper, Mirroring};
+
+const PRG_MODE: u8 = 0x40;
+const CHR_INVERSION: u8 = 0x80;
+const PRG_RAM_ENABLE: u8 = 0x80;
+const PRG_RAM_DENY_WRITES: u8 = 0x40;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// CPU cycles A12 must stay low before a rising edge clocks the counter.
+const A12_FILTER: u64 = 3;
+
+/// The MMC3 revisions differ in when the counter raises an IRQ.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Mmc3Revision {
+    /// MMC3A and NEC parts: only when the counter is decremented to 0 or
+    /// reloaded with 0 after a write to $C001.
+    Old,
+    /// MMC3B and MMC3C: whenever the counter is 0 after a clock, so a latch of
+    /// 0 raises an IRQ on every line.
+    New,
+}
+
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    four_screen: bool,
+    revision: Mmc3Revision,
+    bank_select: u8,
+    banks: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_protect: u8,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    cycle: u64,
+    a12: bool,
+    a12_fell: u64,
+}
+
+impl Mmc3 {
+    /// NES 2.0 submapper 4 marks the old IRQ behaviour.
+    pub fn new(cartridge: Cartridge) -> Self {
+        let revision = if cartridge.submapper == 4 {
+            Mmc3Revision::Old
+        } else {
+            Mmc3Revision::New
+        };
+        Mmc3 {
+            prg_rom: cartridge.prg_rom,
+            prg_ram: vec![0; cartridge.prg_ram_size + cartridge.prg_nvram_size],
+            chr: cartridge.chr,
+            chr_is_ram: cartridge.chr_is_ram,
+            four_screen: cartridge.mirroring == Mirroring::FourScreen,
+            revision,
+            bank_select: 0,
+            banks: [0; 8],
+            mirroring: cartridge.mirroring,
+            // The power-on state is undefined, and some games never write it
+            prg_ram_protect: PRG_RAM_ENABLE,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            cycle: 0,
+            a12: false,
+            a12_fell: 0,
+        }
+    }
+
+    pub fn revision(&self) -> Mmc3Revision {
+        self.revision
+    }
+
+    pub fn set_revision(&mut self, revision: Mmc3Revision) {
+        self.revision = revision;
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match (address & 0xE000, address & 0x0001 != 0) {
+            (0x8000, false) => self.bank_select = value,
+            (0x8000, true) => self.banks[self.bank_select as usize & 0x07] = value,
+            (0xA000, false) => {
+                if !self.four_screen {
+                    self.mirroring = if value & 0x01 != 0 {
+                        Mirroring::Horizontal
+                    } else {
+                        Mirroring::Vertical
+                    };
+                }
+            }
+            (0xA000, true) => self.prg_ram_protect = value,
+            (0xC000, false) => self.irq_latch = value,
+            (0xC000, true) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (0xE000, false) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            _ => self.irq_enabled = true,
+        }
+    }
+
+    fn clock_irq(&mut self) {
+        let counted_down = self.irq_counter != 0 || self.irq_reload;
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+        } else {
+            self.irq_counter -= 1;
+        }
+        self.irq_reload = false;
+        let fire = match self.revision {
+            Mmc3Revision::Old => self.irq_counter == 0 && counted_down,
+            Mmc3Revision::New => self.irq_counter == 0,
+        };
+        if fire && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> usize {
+        let banks = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+        let second_last = banks.saturating_sub(2);
+        let swap = self.bank_select & PRG_MODE != 0;
+        let bank = match (address >> 13) & 0x03 {
+            0 if swap => second_last,
+            0 => self.banks[6] as usize & 0x3F,
+            1 => self.banks[7] as usize & 0x3F,
+            2 if swap => self.banks[6] as usize & 0x3F,
+            2 => second_last,
+            _ => banks - 1,
+        };
+        // Less than a bank mirrors within it
+        ((bank % banks) * PRG_BANK_SIZE + (address as usize & 0x1FFF)) % self.prg_rom.len()
+    }
+
+    fn prg_ram_offset(&self, address: u16) -> Option<usize> {
+        if self.prg_ram.is_empty() || self.prg_ram_protect & PRG_RAM_ENABLE == 0 {
+            return None;
+        }
+        Some((address as usize - 0x6000) % self.prg_ram.len())
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let address = if self.bank_select & CHR_INVERSION != 0 {
+            address ^ 0x1000
+        } else {
+            address
+        };
+        let slot = (address >> 10) as usize & 0x07;
+        let bank = match slot {
+            0..=3 => (self.banks[slot >> 1] as usize & !1) | (slot & 1),
+            _ => self.banks[slot - 2] as usize,
+        };
+        let banks = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        (bank % banks) * CHR_BANK_SIZE + (address as usize & 0x03FF)
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF => self.prg_ram_offset(address).map(|i| self.prg_ram[i]),
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_rom_offset(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF => {
+                if self.prg_ram_protect & PRG_RAM_DENY_WRITES == 0
+                    && let Some(i) = self.prg_ram_offset(address)
+                {
+                    self.prg_ram[i] = value;
+                }
+            }
+            0x8000..=0xFFFF => self.write_register(address, value),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let i = self.chr_offset(address);
+            self.chr[i] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    /// The A12 filter runs off M2, so it counts CPU cycles.
+    fn cpu_cycle(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn ppu_address(&mut self, address: u16) {
+        let a12 = address & 0x1000 != 0;
+        if a12 && !self.a12 && self.cycle - self.a12_fell >= A12_FILTER {
+            self.clock_irq();
+        } else if !a12 && self.a12 {
+            self.a12_fell = self.cycle;
+        }
+        self.a12 = a12;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::prg_banks;
+    use crate::ppu::PPU;
+
+    fn mmc3(prg_rom_size: usize, chr_rom_size: usize) -> Mmc3 {
+        Mmc3::new(Cartridge::numbered(4, prg_rom_size, chr_rom_size))
+    }
+
+    /// A rising edge of A12 after it was low long enough.
+    fn a12_edge(mmc3: &mut Mmc3) {
+        mmc3.ppu_address(0x0000);
+        for _ in 0..A12_FILTER {
+            mmc3.cpu_cycle();
+        }
+        mmc3.ppu_address(0x1000);
+    }
+
+    #[test]
+    fn prg_modes_swap_r6_with_the_second_last_bank() {
+        let mut mmc3 = mmc3(0x40000, 0x2000);
+        mmc3.cpu_write(0x8000, 6);
+        mmc3.cpu_write(0x8001, 3);
+        mmc3.cpu_write(0x8000, 7);
+        mmc3.cpu_write(0x8001, 5);
+        assert_eq!(prg_banks(&mmc3), [3, 5, 30, 31]);
+        mmc3.cpu_write(0x8000, PRG_MODE | 6);
+        assert_eq!(prg_banks(&mmc3), [30, 5, 3, 31]);
+    }
+
+    #[test]
+    fn fixed_banks_fit_a_single_bank_of_prg() {
+        for size in [0x2000, 0x1000] {
+            assert_eq!(prg_banks(&mmc3(size, 0x2000)), [0, 0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn chr_inversion_swaps_the_pattern_tables() {
+        let mut mmc3 = mmc3(0x8000, 0x40000);
+        for (register, bank) in [(0, 9), (1, 20), (2, 40), (3, 41), (4, 42), (5, 43)] {
+            mmc3.cpu_write(0x8000, register);
+            mmc3.cpu_write(0x8001, bank);
+        }
+        let slots = |m: &Mmc3| [0u16, 1, 2, 3, 4, 5, 6, 7].map(|s| m.ppu_peek(s << 10));
+        // The 2KB banks ignore their low bit
+        assert_eq!(slots(&mmc3), [8, 9, 20, 21, 40, 41, 42, 43]);
+        mmc3.cpu_write(0x8000, CHR_INVERSION);
+        assert_eq!(slots(&mmc3), [40, 41, 42, 43, 8, 9, 20, 21]);
+    }
+
+    #[test]
+    fn mirroring_and_prg_ram_protect() {
+        let mut mmc3 = mmc3(0x8000, 0x2000);
+        mmc3.cpu_write(0xA000, 0);
+        assert_eq!(mmc3.mirroring(), Mirroring::Vertical);
+        mmc3.cpu_write(0xA000, 1);
+        assert_eq!(mmc3.mirroring(), Mirroring::Horizontal);
+
+        mmc3.cpu_write(0x6000, 0x11);
+        mmc3.cpu_write(0xA001, PRG_RAM_ENABLE | PRG_RAM_DENY_WRITES);
+        mmc3.cpu_write(0x6000, 0x22);
+        assert_eq!(mmc3.cpu_peek(0x6000), Some(0x11));
+        mmc3.cpu_write(0xA001, 0);
+        assert_eq!(mmc3.cpu_peek(0x6000), None);
+    }
+
+    #[test]
+    fn irq_after_latch_plus_one_edges() {
+        let mut mmc3 = mmc3(0x8000, 0x2000);
+        mmc3.cpu_write(0xC000, 2);
+        mmc3.cpu_write(0xC001, 0);
+        mmc3.cpu_write(0xE001, 0);
+        a12_edge(&mut mmc3); // reload to 2
+        a12_edge(&mut mmc3);
+        assert!(!mmc3.irq());
+        a12_edge(&mut mmc3);
+        assert!(mmc3.irq());
+        // Acknowledged by $E000, which also disables it
+        mmc3.cpu_write(0xE000, 0);
+        assert!(!mmc3.irq());
+        for _ in 0..3 {
+            a12_edge(&mut mmc3);
+        }
+        assert!(!mmc3.irq());
+    }
+
+    #[test]
+    fn short_a12_lows_are_filtered() {
+        let mut mmc3 = mmc3(0x8000, 0x2000);
+        mmc3.cpu_write(0xC000, 1);
+        mmc3.cpu_write(0xE001, 0);
+        a12_edge(&mut mmc3); // reload to 1
+        mmc3.ppu_address(0x0000);
+        mmc3.cpu_cycle();
+        mmc3.ppu_address(0x1000);
+        assert!(!mmc3.irq());
+        a12_edge(&mut mmc3);
+        assert!(mmc3.irq());
+    }
+
+    #[test]
+    fn revisions_differ_on_a_zero_latch() {
+        for (revision, irqs) in [(Mmc3Revision::New, 3), (Mmc3Revision::Old, 1)] {
+            let mut mmc3 = mmc3(0x8000, 0x2000);
+            mmc3.set_revision(revision);
+            mmc3.cpu_write(0xC000, 0);
+            mmc3.cpu_write(0xC001, 0);
+            mmc3.cpu_write(0xE001, 0);
+            let mut count = 0;
+            for _ in 0..3 {
+                a12_edge(&mut mmc3);
+                if mmc3.irq() {
+                    count += 1;
+                    mmc3.cpu_write(0xE000, 0);
+                    mmc3.cpu_write(0xE001, 0);
+                }
+            }
+            assert_eq!(count, irqs, "{revision:?}");
+        }
+    }
+
+    #[test]
+    fn submapper_4_is_the_old_revision() {
+        let mut cartridge = Cartridge::numbered(4, 0x8000, 0x2000);
+        assert_eq!(Mmc3::new(cartridge.clone()).revision(), Mmc3Revision::New);
+        cartridge.submapper = 4;
+        assert_eq!(Mmc3::new(cartridge).revision(), Mmc3Revision::Old);
+    }
+
+    #[test]
+    fn counts_scanlines_from_ppu_fetches() {
+        let mut mmc3 = mmc3(0x8000, 0x2000);
+        let mut ppu = PPU::new();
+        // Background from $0000, sprites from $1000
+        ppu.write_register(0x2000, 0x08, None);
+        ppu.write_register(0x2001, 0x18, None);
+        mmc3.cpu_write(0xC000, 9);
+        mmc3.cpu_write(0xC001, 0);
+        mmc3.cpu_write(0xE001, 0);
+        for dot in 1.. {
+            ppu.tick(Some(&mut mmc3));
+            if dot % 3 == 0 {
+                mmc3.cpu_cycle();
+            }
+            if mmc3.irq() {
+                break;
+            }
+        }
+        // Reloaded on line 0, counted down on lines 1-9, at the sprite fetches
+        assert_eq!(ppu.get_scanline(), 9);
+        assert!((257..=320).contains(&ppu.get_dot()));
+    }
+}
@@ -0,0 +1,739 @@
+//! MMC5, iNES mapper 5, the ExROM boards.
+//!
+//! | Address     | Register                                                  |
+//! |-------------|-----------------------------------------------------------|
+//! | $5000-$5015 | Expansion audio, see `audio`                              |
+//! | $5100       | PRG mode: 32KB, 16KB+16KB, 16KB+8KB+8KB or 4x8KB          |
+//! | $5101       | CHR mode: 8KB, 4KB, 2KB or 1KB banks                      |
+//! | $5102/$5103 | PRG-RAM protect: writable while they hold 2 and 1         |
+//! | $5104       | ExRAM mode                                                |
+//! | $5105       | Nametable mapping, two bits per nametable                 |
+//! | $5106/$5107 | Fill mode tile and attribute                              |
+//! | $5113-$5117 | PRG banks for $6000, $8000, $A000, $C000 and $E000        |
+//! | $5120-$5127 | CHR banks A, for sprites                                  |
+//! | $5128-$512B | CHR banks B, for the background in 8x16 sprite mode       |
+//! | $5130       | Upper CHR bank bits, latched by the bank register writes  |
+//! | $5200-$5202 | Vertical split: control, scroll and CHR bank              |
+//! | $5203/$5204 | Scanline IRQ compare value, IRQ enable and status         |
+//! | $5205/$5206 | Unsigned 8x8 multiplier, the product reads back low/high  |
+//! | $5C00-$5FFF | ExRAM                                                     |
+//!
+//! The MMC5 has no view of the PPU's timing, so it works it out from the PPU
+//! bus: three reads of the same nametable address in a row happen only at the
+//! start of a rendered line, and after that the fetches of a line come in a
+//! fixed order. That tells the background fetches from the sprite fetches
+//! and which tile is being fetched, for the CHR sets, extended attributes and
+//! the split. When the PPU stops reading for a few CPU cycles the frame is
+//! over.
+
+mod audio;
+
+use super::{Cartridge, Mapper, Mirroring};
+use audio::Audio;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const BANK_IS_ROM: u8 = 0x80;
+const SPLIT_ENABLE: u8 = 0x80;
+const SPLIT_RIGHT: u8 = 0x40;
+const IRQ_ENABLE: u8 = 0x80;
+const IRQ_PENDING: u8 = 0x80;
+const IN_FRAME: u8 = 0x40;
+
+/// Reads in a line, counted from the read that detected it: the background
+/// tiles 2-33 (four reads each), then eight sprites (four each), then tiles 0
+/// and 1 of the next line.
+const SPRITE_FETCHES: std::ops::Range<u16> = 128..160;
+const NEXT_LINE_FETCHES: std::ops::Range<u16> = 160..168;
+
+/// CPU cycles without PPU reads that end the frame.
+const IDLE_CYCLES: u8 = 3;
+
+/// What ExRAM is used for, selected by $5104.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum ExramMode {
+    Nametable,
+    ExtendedAttributes,
+    Ram,
+    ReadOnlyRam,
+}
+
+enum Prg {
+    Rom(usize),
+    Ram(usize),
+}
+
+pub struct Mmc5 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    exram: [u8; 0x400],
+    exram_mode: ExramMode,
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect: [u8; 2],
+    nametables: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    prg_banks: [u8; 5],
+    chr_banks: [u16; 12],
+    chr_upper: u8,
+    sprite_set_written: bool, // the last CHR bank write was to set A
+    sprites_16: bool,         // snooped from PPUCTRL
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+    split_fine_y: u16,
+    extended_attribute: u8, // the ExRAM byte for the tile being fetched
+    irq_target: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    scanline: u8,
+    last_read: Option<u16>,
+    repeats: u8,
+    fetch: u16,
+    idle: u8,
+    multiplicand: u8,
+    multiplier: u8,
+    audio: Audio,
+}
+
+impl Mmc5 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Mmc5 {
+            prg_rom: cartridge.prg_rom,
+            prg_ram: vec![0; cartridge.prg_ram_size + cartridge.prg_nvram_size],
+            chr: cartridge.chr,
+            chr_is_ram: cartridge.chr_is_ram,
+            exram: [0; 0x400],
+            exram_mode: ExramMode::Nametable,
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            nametables: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_banks: [0, 0, 0, 0, 0xFF],
+            chr_banks: [0; 12],
+            chr_upper: 0,
+            sprite_set_written: true,
+            sprites_16: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            split_fine_y: 0,
+            extended_attribute: 0,
+            irq_target: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline: 0,
+            last_read: None,
+            repeats: 0,
+            fetch: 0,
+            idle: 0,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            audio: Audio::default(),
+        }
+    }
+
+    /// Where $6000-$FFFF goes in the current PRG mode.
+    fn prg_offset(&self, address: u16) -> Option<Prg> {
+        let (register, size) = match (self.prg_mode & 0x03, address) {
+            (_, 0x6000..=0x7FFF) => (0, 0x2000),
+            (0, _) => (4, 0x8000),
+            (1, 0x8000..=0xBFFF) | (2, 0x8000..=0xBFFF) => (2, 0x4000),
+            (1, _) => (4, 0x4000),
+            (2, 0xC000..=0xDFFF) => (3, 0x2000),
+            (2, _) => (4, 0x2000),
+            (_, address) => ((address as usize - 0x6000) / PRG_BANK_SIZE, 0x2000),
+        };
+        let value = self.prg_banks[register];
+        // Bigger banks ignore the low bits of the 8KB bank number
+        let bank = (value & 0x7F) as usize & !(size / PRG_BANK_SIZE - 1);
+        let offset = bank * PRG_BANK_SIZE + (address as usize & (size - 1));
+        let rom = register == 4 || (register > 0 && value & BANK_IS_ROM != 0);
+        if rom {
+            Some(Prg::Rom(offset % self.prg_rom.len()))
+        } else if self.prg_ram.is_empty() {
+            None
+        } else {
+            Some(Prg::Ram(offset % self.prg_ram.len()))
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect[0] & 0x03 == 0x02 && self.prg_ram_protect[1] & 0x03 == 0x01
+    }
+
+    /// Whether a pattern fetch uses set A. With 8x16 sprites set A is for
+    /// sprites and set B for the background, otherwise the set last written
+    /// is used for everything.
+    fn uses_sprite_set(&self) -> bool {
+        if self.sprites_16 && self.in_frame {
+            SPRITE_FETCHES.contains(&self.fetch)
+        } else {
+            self.sprite_set_written
+        }
+    }
+
+    fn chr_offset(&self, address: u16, sprite_set: bool) -> usize {
+        let mode = self.chr_mode & 0x03;
+        let size = 0x2000 >> mode;
+        let bank = if sprite_set {
+            self.chr_banks[(address as usize / size + 1) * (8 >> mode) - 1]
+        } else {
+            // $5128-$512B cover $0000-$0FFF and repeat at $1000
+            let slot = (address as usize & 0x0FFF) / size;
+            let per_slot = (8 >> mode).min(4);
+            self.chr_banks[8 + (slot + 1) * per_slot - 1]
+        };
+        let banks = (self.chr.len() / size).max(1);
+        (bank as usize % banks) * size + (address as usize & (size - 1))
+    }
+
+    /// Follows the PPU through the line with every read it makes.
+    fn count_read(&mut self, address: u16) {
+        self.idle = 0;
+        self.fetch = self.fetch.saturating_add(1);
+        if (0x2000..=0x2FFF).contains(&address) && self.last_read == Some(address) {
+            self.repeats += 1;
+            if self.repeats == 2 {
+                self.start_line();
+            }
+        } else {
+            self.repeats = 0;
+        }
+        self.last_read = Some(address);
+    }
+
+    fn start_line(&mut self) {
+        if self.in_frame {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.scanline == self.irq_target {
+                self.irq_pending = true;
+            }
+        } else {
+            self.in_frame = true;
+            self.scanline = 0;
+        }
+        self.fetch = 0;
+    }
+
+    /// The column and line of the background tile being fetched, if any.
+    fn background_tile(&self) -> Option<(u16, u16)> {
+        if !self.in_frame {
+            return None;
+        }
+        let line = self.scanline as u16;
+        match self.fetch {
+            fetch @ 0..128 => Some((fetch / 4 + 2, line)),
+            fetch if NEXT_LINE_FETCHES.contains(&fetch) => {
+                Some(((fetch - NEXT_LINE_FETCHES.start) / 4, line + 1))
+            }
+            _ => None,
+        }
+    }
+
+    fn in_split(&self, column: u16) -> bool {
+        if self.split_control & SPLIT_ENABLE == 0
+            || !matches!(
+                self.exram_mode,
+                ExramMode::Nametable | ExramMode::ExtendedAttributes
+            )
+        {
+            return false;
+        }
+        let threshold = (self.split_control & 0x1F) as u16;
+        if self.split_control & SPLIT_RIGHT != 0 {
+            column >= threshold
+        } else {
+            column < threshold
+        }
+    }
+
+    /// The nametable or attribute byte the split region shows at `column`.
+    fn split_read(&mut self, address: u16, column: u16, line: u16) -> u8 {
+        let y = (line + self.split_scroll as u16) % 240;
+        let row = y / 8;
+        self.split_fine_y = y & 0x07;
+        if address & 0x03FF < 0x03C0 {
+            self.exram[(row * 32 + column) as usize & 0x03FF]
+        } else {
+            let attribute = self.exram[0x03C0 + (row / 4 * 8 + column / 4) as usize];
+            let shift = ((row & 0x02) << 1) | (column & 0x02);
+            ((attribute >> shift) & 0x03) * 0x55
+        }
+    }
+
+    /// The byte of the nametable mapped at `address` by $5105, `None` for the
+    /// console's VRAM.
+    fn mapped_nametable(&self, address: u16) -> Option<u8> {
+        let table = (address >> 10) & 0x03;
+        match (self.nametables >> (table * 2)) & 0x03 {
+            0 | 1 => None,
+            2 => Some(match self.exram_mode {
+                ExramMode::Nametable | ExramMode::ExtendedAttributes => {
+                    self.exram[address as usize & 0x03FF]
+                }
+                _ => 0,
+            }),
+            _ if address & 0x03FF >= 0x03C0 => Some(self.fill_attribute * 0x55),
+            _ => Some(self.fill_tile),
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x5000..=0x5015 => self.audio.write(address, value),
+            0x5100 => self.prg_mode = value & 0x03,
+            0x5101 => self.chr_mode = value & 0x03,
+            0x5102 | 0x5103 => self.prg_ram_protect[address as usize - 0x5102] = value,
+            0x5104 => {
+                self.exram_mode = match value & 0x03 {
+                    0 => ExramMode::Nametable,
+                    1 => ExramMode::ExtendedAttributes,
+                    2 => ExramMode::Ram,
+                    _ => ExramMode::ReadOnlyRam,
+                }
+            }
+            0x5105 => self.nametables = value,
+            0x5106 => self.fill_tile = value,
+            0x5107 => self.fill_attribute = value & 0x03,
+            0x5113..=0x5117 => self.prg_banks[address as usize - 0x5113] = value,
+            0x5120..=0x512B => {
+                let register = address as usize - 0x5120;
+                self.chr_banks[register] = value as u16 | (self.chr_upper as u16) << 8;
+                self.sprite_set_written = register < 8;
+            }
+            0x5130 => self.chr_upper = value & 0x03,
+            0x5200 => self.split_control = value,
+            0x5201 => self.split_scroll = value,
+            0x5202 => self.split_bank = value,
+            0x5203 => self.irq_target = value,
+            0x5204 => self.irq_enabled = value & IRQ_ENABLE != 0,
+            0x5205 => self.multiplicand = value,
+            0x5206 => self.multiplier = value,
+            0x5C00..=0x5FFF => {
+                let i = address as usize - 0x5C00;
+                match self.exram_mode {
+                    // Only the PPU side works outside rendering
+                    ExramMode::Nametable | ExramMode::ExtendedAttributes => {
+                        self.exram[i] = if self.in_frame { value } else { 0 };
+                    }
+                    ExramMode::Ram => self.exram[i] = value,
+                    ExramMode::ReadOnlyRam => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x5010 | 0x5015 => self.audio.peek(address),
+            0x5204 => Some(
+                if self.irq_pending { IRQ_PENDING } else { 0 }
+                    | if self.in_frame { IN_FRAME } else { 0 },
+            ),
+            0x5205 => Some((self.multiplicand as u16 * self.multiplier as u16) as u8),
+            0x5206 => Some(((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8),
+            0x5C00..=0x5FFF => match self.exram_mode {
+                ExramMode::Ram | ExramMode::ReadOnlyRam => {
+                    Some(self.exram[address as usize - 0x5C00])
+                }
+                _ => None,
+            },
+            0x6000..=0xFFFF => match self.prg_offset(address)? {
+                Prg::Rom(i) => Some(self.prg_rom[i]),
+                Prg::Ram(i) => Some(self.prg_ram[i]),
+            },
+            _ => None,
+        }
+    }
+
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        let value = match address {
+            0x5010 => self.audio.read(address),
+            0x5204 => {
+                let value = self.cpu_peek(address);
+                self.irq_pending = false;
+                value
+            }
+            _ => self.cpu_peek(address),
+        };
+        if let Some(value) = value {
+            self.audio.cpu_read(address, value);
+        }
+        value
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x5000..=0x5FFF => self.write_register(address, value),
+            0x6000..=0xFFFF => {
+                if self.prg_ram_writable()
+                    && let Some(Prg::Ram(i)) = self.prg_offset(address)
+                {
+                    self.prg_ram[i] = value;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address, self.sprite_set_written)]
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let i = self.chr_offset(address, self.sprite_set_written);
+            self.chr[i] = value;
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.count_read(address);
+        if let Some((column, _)) = self.background_tile() {
+            if self.in_split(column) {
+                let address = (address & 0x0FF8) | self.split_fine_y;
+                let i = self.split_bank as usize * 0x1000 + address as usize;
+                return self.chr[i % self.chr.len()];
+            }
+            if self.exram_mode == ExramMode::ExtendedAttributes {
+                let bank =
+                    (self.extended_attribute & 0x3F) as usize | (self.chr_upper as usize) << 6;
+                let i = bank * 0x1000 + (address as usize & 0x0FFF);
+                return self.chr[i % self.chr.len()];
+            }
+        }
+        self.chr[self.chr_offset(address, self.uses_sprite_set())]
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        // ExRAM and fill mode nametables are answered by `nametable_read`
+        Mirroring::Custom([0, 1, 2, 3].map(|table| (self.nametables >> (table * 2)) & 0x01))
+    }
+
+    fn nametable_peek(&self, address: u16) -> Option<u8> {
+        self.mapped_nametable(address)
+    }
+
+    fn nametable_read(&mut self, address: u16) -> Option<u8> {
+        self.count_read(address);
+        let Some((column, line)) = self.background_tile() else {
+            return self.mapped_nametable(address);
+        };
+        if self.in_split(column) {
+            return Some(self.split_read(address, column, line));
+        }
+        let attribute = address & 0x03FF >= 0x03C0;
+        if self.exram_mode == ExramMode::ExtendedAttributes {
+            if attribute {
+                return Some((self.extended_attribute >> 6) * 0x55);
+            }
+            self.extended_attribute = self.exram[address as usize & 0x03FF];
+        }
+        self.mapped_nametable(address)
+    }
+
+    fn nametable_write(&mut self, address: u16, value: u8) -> bool {
+        let table = (address >> 10) & 0x03;
+        match (self.nametables >> (table * 2)) & 0x03 {
+            0 | 1 => false,
+            2 => {
+                if matches!(
+                    self.exram_mode,
+                    ExramMode::Nametable | ExramMode::ExtendedAttributes
+                ) {
+                    self.exram[address as usize & 0x03FF] = value;
+                }
+                true
+            }
+            _ => true,
+        }
+    }
+
+    fn ppu_register_write(&mut self, address: u16, value: u8) {
+        if address & 0x0007 == 0 {
+            self.sprites_16 = value & 0x20 != 0;
+        }
+    }
+
+    fn irq(&self) -> bool {
+        (self.irq_pending && self.irq_enabled) || self.audio.irq()
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.audio.clock();
+        if self.in_frame {
+            self.idle += 1;
+            if self.idle >= IDLE_CYCLES {
+                self.in_frame = false;
+                self.last_read = None;
+            }
+        }
+    }
+
+    fn audio(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::prg_banks;
+    use crate::ppu::PPU;
+
+    fn mmc5() -> Mmc5 {
+        let mut cartridge = Cartridge::numbered(5, 0x80000, 0x100000);
+        cartridge.prg_ram_size = 0x10000;
+        Mmc5::new(cartridge)
+    }
+
+    /// The 1KB CHR banks of the eight pattern table slots, low byte.
+    fn chr_banks(mmc5: &Mmc5) -> [u8; 8] {
+        [0u16, 1, 2, 3, 4, 5, 6, 7].map(|slot| mmc5.ppu_peek(slot << 10))
+    }
+
+    /// Runs a PPU with the MMC5 for `dots`, with a CPU cycle every three.
+    fn run(mmc5: &mut Mmc5, ppu: &mut PPU, dots: u32) {
+        for dot in 1..=dots {
+            ppu.tick(Some(&mut *mmc5));
+            if dot % 3 == 0 {
+                mmc5.cpu_cycle();
+            }
+        }
+    }
+
+    fn rendering_ppu(mmc5: &mut Mmc5, ctrl: u8) -> PPU {
+        let mut ppu = PPU::new();
+        mmc5.ppu_register_write(0x2000, ctrl);
+        ppu.write_register(0x2000, ctrl, None);
+        ppu.write_register(0x2001, 0x18, None);
+        ppu
+    }
+
+    #[test]
+    fn prg_modes() {
+        let mut mmc5 = mmc5();
+        assert_eq!(mmc5.cpu_peek(0xE000), Some(63));
+        for (register, bank) in [
+            (0x5114, 0x85),
+            (0x5115, 0x8A),
+            (0x5116, 0x8D),
+            (0x5117, 0x93),
+        ] {
+            mmc5.cpu_write(register, bank);
+        }
+        assert_eq!(prg_banks(&mmc5), [5, 10, 13, 19]);
+        mmc5.cpu_write(0x5100, 2);
+        assert_eq!(prg_banks(&mmc5), [10, 11, 13, 19]);
+        mmc5.cpu_write(0x5100, 1);
+        assert_eq!(prg_banks(&mmc5), [10, 11, 18, 19]);
+        mmc5.cpu_write(0x5100, 0);
+        assert_eq!(prg_banks(&mmc5), [16, 17, 18, 19]);
+    }
+
+    #[test]
+    fn prg_ram_banks_and_write_protect() {
+        let mut mmc5 = mmc5();
+        mmc5.cpu_write(0x5113, 2);
+        mmc5.cpu_write(0x6000, 0x11);
+        assert_eq!(mmc5.cpu_peek(0x6000), Some(0));
+        mmc5.cpu_write(0x5102, 2);
+        mmc5.cpu_write(0x5103, 1);
+        mmc5.cpu_write(0x6000, 0x11);
+        assert_eq!(mmc5.cpu_peek(0x6000), Some(0x11));
+        // The same RAM bank mapped as RAM at $8000
+        mmc5.cpu_write(0x5114, 0x02);
+        assert_eq!(mmc5.cpu_peek(0x8000), Some(0x11));
+        mmc5.cpu_write(0x8001, 0x22);
+        assert_eq!(mmc5.prg_ram[0x4001], 0x22);
+    }
+
+    #[test]
+    fn chr_modes() {
+        let mut mmc5 = mmc5();
+        for register in 0..8 {
+            mmc5.cpu_write(0x5120 + register, 0x10 + register as u8);
+        }
+        mmc5.cpu_write(0x5101, 3);
+        assert_eq!(chr_banks(&mmc5), [16, 17, 18, 19, 20, 21, 22, 23]);
+        mmc5.cpu_write(0x5101, 2);
+        assert_eq!(chr_banks(&mmc5), [34, 35, 38, 39, 42, 43, 46, 47]);
+        mmc5.cpu_write(0x5101, 1);
+        assert_eq!(chr_banks(&mmc5), [76, 77, 78, 79, 92, 93, 94, 95]);
+        mmc5.cpu_write(0x5101, 0);
+        assert_eq!(chr_banks(&mmc5)[0], 184);
+    }
+
+    #[test]
+    fn upper_chr_bits_are_latched_by_bank_writes() {
+        let mut mmc5 = mmc5();
+        mmc5.cpu_write(0x5101, 3);
+        mmc5.cpu_write(0x5130, 1);
+        mmc5.cpu_write(0x5120, 0x02);
+        mmc5.cpu_write(0x5130, 0);
+        // 1KB bank $102: the numbered CHR byte is its low 8 bits
+        assert_eq!(mmc5.chr_offset(0x0000, true), 0x102 * 0x400);
+    }
+
+    #[test]
+    fn the_last_set_written_is_used_outside_8x16_rendering() {
+        let mut mmc5 = mmc5();
+        mmc5.cpu_write(0x5101, 3);
+        mmc5.cpu_write(0x5120, 1);
+        mmc5.cpu_write(0x5124, 5);
+        mmc5.cpu_write(0x5128, 9);
+        // Set B repeats in the upper pattern table
+        assert_eq!(mmc5.ppu_peek(0x0000), 9);
+        assert_eq!(mmc5.ppu_peek(0x1000), 9);
+        mmc5.cpu_write(0x5120, 1);
+        assert_eq!(mmc5.ppu_peek(0x0000), 1);
+        assert_eq!(mmc5.ppu_peek(0x1000), 5);
+    }
+
+    #[test]
+    fn sprites_and_background_use_their_own_sets_in_8x16_mode() {
+        let mut mmc5 = mmc5();
+        mmc5.cpu_write(0x5101, 0);
+        mmc5.cpu_write(0x5127, 1); // sprites: 8KB bank 1, 1KB banks 8-15
+        mmc5.cpu_write(0x512B, 2); // background: 8KB bank 2, 1KB banks 16-23
+        let mut ppu = rendering_ppu(&mut mmc5, 0x20);
+        // A frame, then the first dot of line 0 where the MMC5 finds the line
+        run(&mut mmc5, &mut ppu, 262 * 341 + 2);
+        assert!(mmc5.in_frame);
+        assert_eq!(mmc5.ppu_read(0x0000), 16);
+        // Up to the sprite fetches at dot 257
+        run(&mut mmc5, &mut ppu, 256);
+        assert!(SPRITE_FETCHES.contains(&mmc5.fetch));
+        assert_eq!(mmc5.ppu_read(0x0000), 8);
+    }
+
+    #[test]
+    fn nametable_mapping_and_fill_mode() {
+        let mut mmc5 = mmc5();
+        // $2000 CIRAM 0, $2400 CIRAM 1, $2800 ExRAM, $2C00 fill
+        mmc5.cpu_write(0x5105, 0b11_10_01_00);
+        assert_eq!(mmc5.mirroring(), Mirroring::Custom([0, 1, 0, 1]));
+        assert_eq!(mmc5.nametable_peek(0x2000), None);
+        assert!(!mmc5.nametable_write(0x2400, 0x33));
+
+        assert!(mmc5.nametable_write(0x2805, 0x44));
+        assert_eq!(mmc5.exram[5], 0x44);
+        assert_eq!(mmc5.nametable_peek(0x2805), Some(0x44));
+
+        mmc5.cpu_write(0x5106, 0x7E);
+        mmc5.cpu_write(0x5107, 0x02);
+        assert_eq!(mmc5.nametable_peek(0x2C00), Some(0x7E));
+        assert_eq!(mmc5.nametable_peek(0x2FC0), Some(0xAA));
+    }
+
+    #[test]
+    fn exram_modes_from_the_cpu() {
+        let mut mmc5 = mmc5();
+        // As a nametable, only writes while rendering land
+        mmc5.cpu_write(0x5C00, 0x12);
+        assert_eq!(mmc5.cpu_peek(0x5C00), None);
+        assert_eq!(mmc5.exram[0], 0);
+        mmc5.cpu_write(0x5104, 2);
+        mmc5.cpu_write(0x5C00, 0x12);
+        assert_eq!(mmc5.cpu_peek(0x5C00), Some(0x12));
+        mmc5.cpu_write(0x5104, 3);
+        mmc5.cpu_write(0x5C00, 0x34);
+        assert_eq!(mmc5.cpu_peek(0x5C00), Some(0x12));
+        // And no longer a nametable
+        mmc5.cpu_write(0x5105, 0x02);
+        assert_eq!(mmc5.nametable_peek(0x2000), Some(0));
+    }
+
+    #[test]
+    fn extended_attributes_pick_the_palette_and_the_tile_bank() {
+        let mut mmc5 = mmc5();
+        mmc5.cpu_write(0x5104, 1);
+        mmc5.cpu_write(0x5130, 1);
+        // Tile 2 of line 0, where the MMC5 has just found the line
+        mmc5.exram[2] = 0xC5;
+        let mut ppu = rendering_ppu(&mut mmc5, 0x00);
+        run(&mut mmc5, &mut ppu, 262 * 341 + 2);
+        assert_eq!(mmc5.fetch, 0);
+        assert_eq!(mmc5.nametable_read(0x23C0), Some(0xFF));
+        // 4KB bank $45, 1KB banks $114-$117
+        assert_eq!(mmc5.ppu_read(0x0000), 0x14);
+    }
+
+    #[test]
+    fn vertical_split() {
+        let mut mmc5 = mmc5();
+        mmc5.cpu_write(0x5200, SPLIT_ENABLE | 3); // tiles 0-2 from the left
+        mmc5.cpu_write(0x5201, 12);
+        mmc5.cpu_write(0x5202, 7);
+        mmc5.exram[32 + 2] = 0x42; // row 1, tile 2
+        mmc5.exram[0x3C0] = 0xC0; // bottom right quadrant: palette 3
+        let mut ppu = rendering_ppu(&mut mmc5, 0x00);
+        run(&mut mmc5, &mut ppu, 262 * 341);
+        // The MMC5 finds line 0 with the next read, which shows split line 12:
+        // row 1, fine Y 4
+        assert_eq!(mmc5.nametable_read(0x2002), Some(0x42));
+        assert_eq!(mmc5.nametable_read(0x23C0), Some(0x00));
+        assert_eq!(mmc5.ppu_read(0x0420), 7 * 4 + 1);
+        // Tile 3 is outside the split
+        mmc5.fetch = 3;
+        assert_eq!(mmc5.nametable_read(0x2003), None);
+
+        mmc5.split_scroll = 28; // line 28 is row 3
+        mmc5.fetch = 2;
+        assert_eq!(mmc5.nametable_read(0x23C0), Some(0xFF));
+    }
+
+    #[test]
+    fn scanline_irq() {
+        let mut mmc5 = mmc5();
+        mmc5.cpu_write(0x5203, 20);
+        let mut ppu = rendering_ppu(&mut mmc5, 0x00);
+        // The first frame starts without a pre-render line
+        run(&mut mmc5, &mut ppu, 262 * 341);
+        mmc5.cpu_read(0x5204);
+        mmc5.cpu_write(0x5204, IRQ_ENABLE);
+        while !mmc5.irq() {
+            run(&mut mmc5, &mut ppu, 1);
+        }
+        assert_eq!((ppu.get_scanline(), ppu.get_dot()), (20, 2));
+        assert_eq!(mmc5.cpu_read(0x5204), Some(IRQ_PENDING | IN_FRAME));
+        assert!(!mmc5.irq());
+        // The frame ends when rendering stops
+        run(&mut mmc5, &mut ppu, (241 - 20) * 341);
+        assert_eq!(mmc5.cpu_peek(0x5204), Some(0));
+    }
+
+    #[test]
+    fn multiplier() {
+        let mut mmc5 = mmc5();
+        assert_eq!(mmc5.cpu_peek(0x5205), Some(0x01));
+        assert_eq!(mmc5.cpu_peek(0x5206), Some(0xFE));
+        mmc5.cpu_write(0x5205, 200);
+        mmc5.cpu_write(0x5206, 3);
+        assert_eq!(mmc5.cpu_peek(0x5205), Some(600u16 as u8));
+        assert_eq!(mmc5.cpu_peek(0x5206), Some(2));
+    }
+
+    #[test]
+    fn pcm_irq_is_shared() {
+        let mut mmc5 = mmc5();
+        mmc5.cpu_write(0x5010, 0x81); // read mode with IRQ
+        mmc5.cpu_write(0x5114, 0x81);
+        mmc5.cpu_write(0x5115, 0x80);
+        mmc5.cpu_read(0x8000);
+        assert!(!mmc5.irq());
+        // PRG bank 0 is all zeros
+        mmc5.cpu_read(0xA000);
+        assert!(mmc5.irq());
+    }
+}
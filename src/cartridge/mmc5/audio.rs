@@ -0,0 +1,256 @@
+//! MMC5 expansion audio: two pulse channels like the APU's, without sweep
+//! units, and an 8-bit PCM channel.
+//!
+//! The pulses have envelopes and length counters clocked at 240Hz by the
+//! MMC5's own divider, not by the APU frame counter.
+
+const LENGTHS: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+const DUTIES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+/// CPU cycles per envelope and length counter clock, 240Hz on NTSC.
+const FRAME_PERIOD: u16 = 7457;
+
+const PCM_READ_MODE: u8 = 0x01;
+const PCM_IRQ_ENABLE: u8 = 0x80;
+
+#[derive(Default)]
+struct Pulse {
+    enabled: bool,
+    control: u8, // DDLC VVVV: duty, length halt / envelope loop, constant volume
+    period: u16,
+    timer: u16,
+    step: usize,
+    length: u8,
+    envelope_start: bool,
+    envelope_divider: u8,
+    decay: u8,
+}
+
+impl Pulse {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => self.control = value,
+            2 => self.period = (self.period & 0x0700) | value as u16,
+            3 => {
+                self.period = (self.period & 0x00FF) | ((value as u16 & 0x07) << 8);
+                if self.enabled {
+                    self.length = LENGTHS[value as usize >> 3];
+                }
+                self.step = 0;
+                self.envelope_start = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length = 0;
+        }
+    }
+
+    /// Clocked every other CPU cycle.
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_frame(&mut self) {
+        let looping = self.control & 0x20 != 0;
+        if self.envelope_start {
+            self.envelope_start = false;
+            self.decay = 15;
+            self.envelope_divider = self.control & 0x0F;
+        } else if self.envelope_divider == 0 {
+            self.envelope_divider = self.control & 0x0F;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if looping {
+                self.decay = 15;
+            }
+        } else {
+            self.envelope_divider -= 1;
+        }
+        if !looping && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || DUTIES[self.control as usize >> 6][self.step] == 0 {
+            return 0;
+        }
+        if self.control & 0x10 != 0 {
+            self.control & 0x0F
+        } else {
+            self.decay
+        }
+    }
+}
+
+#[derive(Default)]
+pub(super) struct Audio {
+    pulses: [Pulse; 2],
+    pcm_control: u8,
+    pcm: u8,
+    pcm_irq: bool,
+    frame_divider: u16,
+    odd_cycle: bool,
+}
+
+impl Audio {
+    pub(super) fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x5000..=0x5007 => {
+                self.pulses[(address as usize >> 2) & 1].write(address & 0x03, value);
+            }
+            0x5010 => {
+                self.pcm_control = value;
+                if value & PCM_IRQ_ENABLE == 0 {
+                    self.pcm_irq = false;
+                }
+            }
+            // Zero can't be written, as in read mode it ends the sample
+            0x5011 if self.pcm_control & PCM_READ_MODE == 0 && value != 0 => self.pcm = value,
+            0x5015 => {
+                self.pulses[0].set_enabled(value & 0x01 != 0);
+                self.pulses[1].set_enabled(value & 0x02 != 0);
+            }
+            _ => {}
+        }
+    }
+
+    /// $5010 and $5015, or `None` for the write only registers.
+    pub(super) fn peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x5010 => Some(((self.pcm_irq as u8) << 7) | (self.pcm_control & PCM_READ_MODE)),
+            0x5015 => {
+                Some((self.pulses[0].length > 0) as u8 | ((self.pulses[1].length > 0) as u8) << 1)
+            }
+            _ => None,
+        }
+    }
+
+    /// Reading $5010 acknowledges the PCM IRQ.
+    pub(super) fn read(&mut self, address: u16) -> Option<u8> {
+        let value = self.peek(address);
+        if address == 0x5010 {
+            self.pcm_irq = false;
+        }
+        value
+    }
+
+    /// In read mode the PCM channel plays the bytes the CPU reads from
+    /// $8000-$BFFF. A zero ends the sample with an IRQ.
+    pub(super) fn cpu_read(&mut self, address: u16, value: u8) {
+        if self.pcm_control & PCM_READ_MODE == 0 || !(0x8000..=0xBFFF).contains(&address) {
+            return;
+        }
+        if value == 0 {
+            self.pcm_irq = self.pcm_control & PCM_IRQ_ENABLE != 0;
+        } else {
+            self.pcm = value;
+        }
+    }
+
+    pub(super) fn irq(&self) -> bool {
+        self.pcm_irq && self.pcm_control & PCM_IRQ_ENABLE != 0
+    }
+
+    pub(super) fn clock(&mut self) {
+        self.odd_cycle = !self.odd_cycle;
+        if self.odd_cycle {
+            for pulse in &mut self.pulses {
+                pulse.clock_timer();
+            }
+        }
+        self.frame_divider += 1;
+        if self.frame_divider == FRAME_PERIOD {
+            self.frame_divider = 0;
+            for pulse in &mut self.pulses {
+                pulse.clock_frame();
+            }
+        }
+    }
+
+    /// Mixed the way the APU mixes its own pulses and DMC.
+    pub(super) fn output(&self) -> f32 {
+        let pulses = (self.pulses[0].output() + self.pulses[1].output()) as f32;
+        let pulses = if pulses == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulses + 100.0)
+        };
+        pulses + 0.00335 * (self.pcm >> 1) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_counters_need_the_channel_enabled() {
+        let mut audio = Audio::default();
+        audio.write(0x5003, 0x08);
+        assert_eq!(audio.peek(0x5015), Some(0x00));
+        audio.write(0x5015, 0x03);
+        audio.write(0x5003, 0x08);
+        audio.write(0x5007, 0x08);
+        assert_eq!(audio.peek(0x5015), Some(0x03));
+        // 254 frames at 240Hz
+        for _ in 0..254 * FRAME_PERIOD as u32 {
+            audio.clock();
+        }
+        assert_eq!(audio.peek(0x5015), Some(0x00));
+    }
+
+    #[test]
+    fn pulse_plays_its_duty_cycle() {
+        let mut audio = Audio::default();
+        audio.write(0x5015, 0x01);
+        audio.write(0x5000, 0xBF); // 50% duty, constant volume 15
+        audio.write(0x5002, 0x00);
+        audio.write(0x5003, 0x08);
+        let mut levels = Vec::new();
+        for _ in 0..8 {
+            levels.push(audio.pulses[0].output());
+            audio.clock();
+            audio.clock();
+        }
+        assert_eq!(levels, [0, 15, 15, 15, 15, 0, 0, 0]);
+        assert_eq!(audio.output(), 0.0);
+        audio.clock();
+        audio.clock();
+        assert!(audio.output() > 0.0);
+    }
+
+    #[test]
+    fn pcm_read_mode_ends_with_an_irq() {
+        let mut audio = Audio::default();
+        audio.write(0x5011, 0x40);
+        assert_eq!(audio.pcm, 0x40);
+        audio.write(0x5010, PCM_IRQ_ENABLE | PCM_READ_MODE);
+        audio.cpu_read(0xC000, 0x22); // outside $8000-$BFFF
+        audio.cpu_read(0x8000, 0x22);
+        assert_eq!(audio.pcm, 0x22);
+        audio.cpu_read(0x8001, 0x00);
+        assert!(audio.irq());
+        assert_eq!(audio.read(0x5010), Some(0x81));
+        assert!(!audio.irq());
+    }
+}
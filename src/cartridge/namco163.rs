@@ -0,0 +1,291 @@
+//! Namco 163, iNES mapper 19.
+//!
+//! | Address     | Register                                                |
+//! |-------------|---------------------------------------------------------|
+//! | $4800-$4FFF | Audio RAM data port, see `audio`                        |
+//! | $5000-$57FF | IRQ counter, low 8 bits                                 |
+//! | $5800-$5FFF | EHHH HHHH: IRQ enable, counter high 7 bits              |
+//! | $8000-$BFFF | 1KB CHR banks at $0000, $0400, ... $1C00, every $800    |
+//! | $C000-$DFFF | Nametables at $2000, $2400, $2800 and $2C00, every $800 |
+//! | $E000-$E7FF | -SPP PPPP: audio disable, 8KB PRG bank at $8000         |
+//! | $E800-$EFFF | 8KB PRG bank at $A000                                   |
+//! | $F000-$F7FF | 8KB PRG bank at $C000                                   |
+//! | $F800-$FFFF | Audio RAM address, and PRG-RAM write protect            |
+//!
+//! $E000-$FFFF is fixed to the last 8KB bank. The counter registers read
+//! back, writing either acknowledges the IRQ. While enabled the counter
+//! counts up every CPU cycle and fires when it reaches $7FFF.
+//!
+//! Nametable banks $E0-$FF pick a page of the console's VRAM by bit 0, lower
+//! ones a 1KB bank of CHR-ROM. CHR banks of $E0 and up can map VRAM into the
+//! pattern tables too, which isn't supported: they read CHR-ROM instead.
+//!
+//! PRG-RAM takes writes only while $F800 holds $4x, with each bit of x
+//! protecting 2KB of it.
+
+mod audio;
+
+use super::{Cartridge, Mapper, Mirroring};
+use audio::Audio;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+const AUDIO_DISABLE: u8 = 0x40;
+const IRQ_ENABLE: u8 = 0x80;
+const IRQ_FIRES_AT: u16 = 0x7FFF;
+
+pub struct Namco163 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    nametables: [u8; 4],
+    write_protect: u8,
+    irq_counter: u16, // 15 bits
+    irq_enabled: bool,
+    audio_disabled: bool,
+    audio: Audio,
+}
+
+impl Namco163 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let nametables = match cartridge.mirroring {
+            Mirroring::Vertical => [0xE0, 0xE1, 0xE0, 0xE1],
+            _ => [0xE0, 0xE0, 0xE1, 0xE1],
+        };
+        Namco163 {
+            prg_rom: cartridge.prg_rom,
+            prg_ram: vec![0; cartridge.prg_ram_size + cartridge.prg_nvram_size],
+            chr: cartridge.chr,
+            chr_is_ram: cartridge.chr_is_ram,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            nametables,
+            write_protect: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            audio_disabled: false,
+            audio: Audio::default(),
+        }
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> usize {
+        let banks = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+        let bank = match (address >> 13) & 0x03 {
+            3 => banks - 1,
+            slot => self.prg_banks[slot as usize] as usize,
+        };
+        // Less than a bank mirrors within it
+        ((bank % banks) * PRG_BANK_SIZE + (address as usize & 0x1FFF)) % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, bank: u8, address: u16) -> usize {
+        let banks = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        (bank as usize % banks) * CHR_BANK_SIZE + (address as usize & 0x03FF)
+    }
+
+    fn prg_ram_writable(&self, address: u16) -> bool {
+        let section = (address as usize - 0x6000) >> 11;
+        self.write_protect & 0xF0 == 0x40 && self.write_protect & (1 << section) == 0
+    }
+
+    /// The CHR-ROM bank mapped at nametable `address`, or `None` for VRAM.
+    fn nametable_bank(&self, address: u16) -> Option<u8> {
+        let bank = self.nametables[(address as usize >> 10) & 0x03];
+        (bank < 0xE0).then_some(bank)
+    }
+}
+
+impl Mapper for Namco163 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x4800..=0x4FFF => Some(self.audio.peek()),
+            0x5000..=0x57FF => Some(self.irq_counter as u8),
+            0x5800..=0x5FFF => {
+                Some((self.irq_counter >> 8) as u8 | if self.irq_enabled { IRQ_ENABLE } else { 0 })
+            }
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(address as usize - 0x6000) % self.prg_ram.len()])
+            }
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_rom_offset(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x4800..=0x4FFF => Some(self.audio.read()),
+            _ => self.cpu_peek(address),
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x4800..=0x4FFF => self.audio.write(value),
+            0x5000..=0x57FF => self.irq_counter = (self.irq_counter & 0x7F00) | value as u16,
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | (value as u16 & 0x7F) << 8;
+                self.irq_enabled = value & IRQ_ENABLE != 0;
+            }
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() && self.prg_ram_writable(address) => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(address as usize - 0x6000) % len] = value;
+            }
+            0x8000..=0xBFFF => self.chr_banks[(address as usize - 0x8000) >> 11] = value,
+            0xC000..=0xDFFF => self.nametables[(address as usize - 0xC000) >> 11] = value,
+            0xE000..=0xE7FF => {
+                self.prg_banks[0] = value & 0x3F;
+                self.audio_disabled = value & AUDIO_DISABLE != 0;
+            }
+            0xE800..=0xEFFF => self.prg_banks[1] = value & 0x3F,
+            0xF000..=0xF7FF => self.prg_banks[2] = value & 0x3F,
+            0xF800..=0xFFFF => {
+                self.write_protect = value;
+                self.audio.select(value);
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        let bank = self.chr_banks[(address >> 10) as usize & 0x07];
+        self.chr[self.chr_offset(bank, address)]
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let bank = self.chr_banks[(address >> 10) as usize & 0x07];
+            let i = self.chr_offset(bank, address);
+            self.chr[i] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        // CHR-ROM nametables are answered by `nametable_peek`
+        Mirroring::Custom(self.nametables.map(|bank| bank & 0x01))
+    }
+
+    fn nametable_peek(&self, address: u16) -> Option<u8> {
+        self.nametable_bank(address)
+            .map(|bank| self.chr[self.chr_offset(bank, address)])
+    }
+
+    fn nametable_write(&mut self, address: u16, _value: u8) -> bool {
+        self.nametable_bank(address).is_some()
+    }
+
+    fn audio(&self) -> f32 {
+        if self.audio_disabled {
+            0.0
+        } else {
+            self.audio.output()
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_enabled && self.irq_counter == IRQ_FIRES_AT
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.audio.clock();
+        if self.irq_enabled && self.irq_counter < IRQ_FIRES_AT {
+            self.irq_counter += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::prg_banks;
+
+    fn namco163() -> Namco163 {
+        Namco163::new(Cartridge::numbered(19, 0x40000, 0x40000))
+    }
+
+    #[test]
+    fn prg_and_chr_banks() {
+        let mut n163 = namco163();
+        n163.cpu_write(0xE000, 3);
+        n163.cpu_write(0xE800, 4);
+        n163.cpu_write(0xF000, 5);
+        assert_eq!(prg_banks(&n163), [3, 4, 5, 31]);
+        n163.cpu_write(0xB800, 0x77);
+        assert_eq!(n163.ppu_peek(0x1C00), 0x77);
+    }
+
+    #[test]
+    fn small_prg_stays_in_range() {
+        for size in [0x2000, 0x1000] {
+            let n163 = Namco163::new(Cartridge::numbered(19, size, 0x2000));
+            assert_eq!(prg_banks(&n163), [0, 0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn nametables_from_vram_or_chr_rom() {
+        let mut n163 = namco163();
+        assert_eq!(n163.mirroring(), Mirroring::Custom([0, 0, 1, 1]));
+        n163.cpu_write(0xC000, 0xE1);
+        n163.cpu_write(0xC800, 0xE0);
+        n163.cpu_write(0xD000, 0x42);
+        n163.cpu_write(0xD800, 0xFF);
+        assert_eq!(n163.mirroring(), Mirroring::Custom([1, 0, 0, 1]));
+        assert_eq!(n163.nametable_peek(0x2000), None);
+        assert!(!n163.nametable_write(0x2400, 0x00));
+        assert_eq!(n163.nametable_peek(0x2805), Some(0x42));
+        assert!(n163.nametable_write(0x2805, 0x00), "CHR-ROM ignores writes");
+        assert_eq!(n163.nametable_peek(0x2C00), None);
+    }
+
+    #[test]
+    fn prg_ram_write_protect() {
+        let mut n163 = namco163();
+        n163.cpu_write(0x6000, 0x11);
+        assert_eq!(n163.cpu_peek(0x6000), Some(0x00));
+        n163.cpu_write(0xF800, 0x41); // protects $6000-$67FF
+        n163.cpu_write(0x6000, 0x11);
+        n163.cpu_write(0x6800, 0x22);
+        assert_eq!(n163.cpu_peek(0x6000), Some(0x00));
+        assert_eq!(n163.cpu_peek(0x6800), Some(0x22));
+    }
+
+    #[test]
+    fn irq_counts_up_to_7fff() {
+        let mut n163 = namco163();
+        n163.cpu_write(0x5000, 0xFD);
+        n163.cpu_write(0x5800, IRQ_ENABLE | 0x7F);
+        assert_eq!(n163.cpu_peek(0x5800), Some(0xFF));
+        n163.cpu_cycle();
+        assert!(!n163.irq());
+        n163.cpu_cycle();
+        assert!(n163.irq());
+        n163.cpu_cycle();
+        assert_eq!(n163.cpu_peek(0x5000), Some(0xFF), "stops at $7FFF");
+        // Writing acknowledges
+        n163.cpu_write(0x5000, 0x00);
+        assert!(!n163.irq());
+    }
+
+    #[test]
+    fn audio_port_and_disable() {
+        let mut n163 = namco163();
+        // Channel 8 alone, at full volume
+        n163.cpu_write(0xF800, 0xF8);
+        for value in [0x00, 0x00, 0x00, 0x00, 0xFC, 0x00, 0x00, 0x0F] {
+            n163.cpu_write(0x4800, value);
+        }
+        n163.cpu_write(0xF800, 0xFF);
+        assert_eq!(n163.cpu_read(0x4800), Some(0x0F));
+        assert_eq!(n163.cpu_peek(0x4800), Some(0x00), "the address moved on");
+        for _ in 0..15 {
+            n163.cpu_cycle();
+        }
+        // A silent wave sits at -8
+        assert!(n163.audio() < 0.0);
+        n163.cpu_write(0xE000, AUDIO_DISABLE);
+        assert_eq!(n163.audio(), 0.0);
+    }
+}
@@ -0,0 +1,184 @@
+//! Namco 163 expansion audio: up to eight wavetable channels playing 4-bit
+//! samples out of 128 bytes of internal RAM, which also holds the channel
+//! registers at $40-$7F.
+//!
+//! | Offset | Function                                         |
+//! |--------|--------------------------------------------------|
+//! | $0     | Frequency, low 8 of 18 bits                      |
+//! | $1     | Phase, low 8 of 24 bits                          |
+//! | $2     | Frequency, middle 8 bits                         |
+//! | $3     | Phase, middle 8 bits                             |
+//! | $4     | LLLL LLFF: 256 - wave length in samples, freq    |
+//! | $5     | Phase, high 8 bits                               |
+//! | $6     | Wave address, in samples                         |
+//! | $7     | Volume; at $7F also -CCC ---- enabled channels-1 |
+//!
+//! Channel 8 is at $78 and channel 1 at $40. Only the last channels, from 8
+//! down, are enabled. The chip updates one channel every 15 CPU cycles and
+//! plays them in turn, so each channel is quieter the more are enabled.
+
+/// CPU cycles per channel update.
+const UPDATE_PERIOD: u8 = 15;
+/// The level of one channel with a full scale wave at full volume.
+const CHANNEL_LEVEL: f32 = 0.0013;
+
+const AUTO_INCREMENT: u8 = 0x80;
+
+pub(super) struct Audio {
+    ram: [u8; 128],
+    address: u8,
+    channel: u8, // the next to update, counting down from 7
+    divider: u8,
+    outputs: [f32; 8],
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Audio {
+            ram: [0; 128],
+            address: 0,
+            channel: 7,
+            divider: 0,
+            outputs: [0.0; 8],
+        }
+    }
+}
+
+impl Audio {
+    /// $F800: the RAM address, with auto-increment in bit 7.
+    pub(super) fn select(&mut self, address: u8) {
+        self.address = address;
+    }
+
+    /// $4800, the byte at the selected address.
+    pub(super) fn peek(&self) -> u8 {
+        self.ram[self.address as usize & 0x7F]
+    }
+
+    pub(super) fn read(&mut self) -> u8 {
+        let value = self.peek();
+        self.increment();
+        value
+    }
+
+    pub(super) fn write(&mut self, value: u8) {
+        self.ram[self.address as usize & 0x7F] = value;
+        self.increment();
+    }
+
+    fn increment(&mut self) {
+        if self.address & AUTO_INCREMENT != 0 {
+            self.address = AUTO_INCREMENT | (self.address.wrapping_add(1) & 0x7F);
+        }
+    }
+
+    fn enabled_channels(&self) -> u8 {
+        ((self.ram[0x7F] >> 4) & 0x07) + 1
+    }
+
+    pub(super) fn clock(&mut self) {
+        self.divider += 1;
+        if self.divider < UPDATE_PERIOD {
+            return;
+        }
+        self.divider = 0;
+        let channel = self.channel as usize;
+        self.update(channel);
+        let first = 8 - self.enabled_channels();
+        self.channel = if self.channel <= first {
+            7
+        } else {
+            self.channel - 1
+        };
+    }
+
+    /// Steps the phase of `channel` and fetches its next sample.
+    fn update(&mut self, channel: usize) {
+        let base = 0x40 + channel * 8;
+        let registers = &self.ram[base..base + 8];
+        let frequency =
+            registers[0] as u32 | (registers[2] as u32) << 8 | (registers[4] as u32 & 0x03) << 16;
+        let phase = registers[1] as u32 | (registers[3] as u32) << 8 | (registers[5] as u32) << 16;
+        let length = (256 - (registers[4] as u32 & 0xFC)) << 16;
+        let phase = (phase + frequency) % length;
+        let sample_address = ((phase >> 16) + registers[6] as u32) as usize & 0xFF;
+        let volume = registers[7] & 0x0F;
+
+        let byte = self.ram[sample_address >> 1];
+        let sample = if sample_address & 1 == 0 {
+            byte & 0x0F
+        } else {
+            byte >> 4
+        };
+        self.outputs[channel] = (sample as f32 - 8.0) * volume as f32;
+
+        self.ram[base + 1] = phase as u8;
+        self.ram[base + 3] = (phase >> 8) as u8;
+        self.ram[base + 5] = (phase >> 16) as u8;
+    }
+
+    /// The average of the enabled channels, as the chip switches between them
+    /// faster than the output can follow.
+    pub(super) fn output(&self) -> f32 {
+        let enabled = self.enabled_channels() as usize;
+        let sum: f32 = self.outputs[8 - enabled..].iter().sum();
+        sum / enabled as f32 * CHANNEL_LEVEL
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(audio: &mut Audio, address: u8, value: u8) {
+        audio.select(address);
+        audio.write(value);
+    }
+
+    #[test]
+    fn ram_port_auto_increments() {
+        let mut audio = Audio::default();
+        audio.select(AUTO_INCREMENT | 0x7F);
+        audio.write(0x11);
+        audio.write(0x22);
+        assert_eq!(audio.ram[0x7F], 0x11);
+        assert_eq!(audio.ram[0x00], 0x22);
+        audio.select(0x7F);
+        assert_eq!(audio.read(), 0x11);
+        assert_eq!(audio.read(), 0x11);
+    }
+
+    #[test]
+    fn channel_plays_its_wave() {
+        let mut audio = Audio::default();
+        // A 4 sample wave 0, 15, 0, 15 at address 0
+        write(&mut audio, 0x00, 0xF0);
+        write(&mut audio, 0x01, 0xF0);
+        // Channel 8: one sample per update, length 4, full volume
+        write(&mut audio, 0x7C, 0xFD);
+        write(&mut audio, 0x7F, 0x0F);
+        let mut outputs = Vec::new();
+        for _ in 0..4 {
+            for _ in 0..UPDATE_PERIOD {
+                audio.clock();
+            }
+            outputs.push(audio.outputs[7]);
+        }
+        assert_eq!(outputs, [105.0, -120.0, 105.0, -120.0]);
+        assert_eq!(audio.ram[0x7D], 0x00, "the phase wraps at the length");
+    }
+
+    #[test]
+    fn enabled_channels_take_turns() {
+        let mut audio = Audio::default();
+        write(&mut audio, 0x7F, 0x10); // channels 7 and 8
+        for _ in 0..UPDATE_PERIOD {
+            audio.clock();
+        }
+        assert_eq!(audio.channel, 6);
+        for _ in 0..UPDATE_PERIOD {
+            audio.clock();
+        }
+        assert_eq!(audio.channel, 7);
+    }
+}
@@ -0,0 +1,110 @@
+//! NROM, iNES mapper 0.
+//!
+//! No bank switching: 16KB (NROM-128) or 32KB (NROM-256) of PRG-ROM at
+//! $8000-$FFFF, with the 16KB version mirrored at $C000, and 8KB of CHR.
+//! Mirroring is soldered. Family Basic adds PRG-RAM at $6000-$7FFF.
+
+use super::{Cartridge, Mapper, Mirroring};
+
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Nrom {
+            prg_rom: cartridge.prg_rom,
+            prg_ram: vec![0; cartridge.prg_ram_size + cartridge.prg_nvram_size],
+            chr: cartridge.chr,
+            chr_is_ram: cartridge.chr_is_ram,
+            mirroring: cartridge.mirroring,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(address as usize - 0x6000) % self.prg_ram.len()])
+            }
+            0x8000..=0xFFFF => Some(self.prg_rom[(address as usize - 0x8000) % self.prg_rom.len()]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if let 0x6000..=0x7FFF = address
+            && !self.prg_ram.is_empty()
+        {
+            let len = self.prg_ram.len();
+            self.prg_ram[(address as usize - 0x6000) % len] = value;
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr[address as usize % self.chr.len()]
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let len = self.chr.len();
+            self.chr[address as usize % len] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nrom(prg_banks: u8, chr_banks: u8) -> Nrom {
+        let mut image = b"NES\x1A".to_vec();
+        image.extend([prg_banks, chr_banks, 0x01]);
+        image.resize(16, 0);
+        for bank in 0..prg_banks {
+            image.extend(vec![bank; 0x4000]);
+        }
+        image.extend(vec![0xCC; chr_banks as usize * 0x2000]);
+        Nrom::new(Cartridge::from_ines(&image).unwrap())
+    }
+
+    #[test]
+    fn nrom_128_is_mirrored_at_c000() {
+        let nrom = nrom(1, 1);
+        assert_eq!(nrom.cpu_peek(0x8000), Some(0));
+        assert_eq!(nrom.cpu_peek(0xC000), Some(0));
+        assert_eq!(nrom.cpu_peek(0x5000), None);
+        assert_eq!(nrom.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn nrom_256_maps_both_banks() {
+        let mut nrom = nrom(2, 1);
+        assert_eq!(nrom.cpu_peek(0xBFFF), Some(0));
+        assert_eq!(nrom.cpu_peek(0xC000), Some(1));
+        nrom.cpu_write(0xC000, 0x55);
+        assert_eq!(nrom.cpu_peek(0xC000), Some(1));
+    }
+
+    #[test]
+    fn prg_ram_and_chr() {
+        let mut chr_rom = nrom(1, 1);
+        chr_rom.cpu_write(0x6001, 0x42);
+        assert_eq!(chr_rom.cpu_peek(0x6001), Some(0x42));
+        chr_rom.ppu_write(0x0010, 0x00);
+        assert_eq!(chr_rom.ppu_peek(0x0010), 0xCC);
+
+        let mut chr_ram = nrom(1, 0);
+        chr_ram.ppu_write(0x1FFF, 0x77);
+        assert_eq!(chr_ram.ppu_peek(0x1FFF), 0x77);
+    }
+}
@@ -0,0 +1,141 @@
+//! What the Konami VRC chips share: register selects wired to different CPU
+//! address lines from board to board, and the IRQ counter of VRC4, VRC6 and
+//! VRC7.
+
+/// The CPU address lines a board connects to the chip's two register select
+/// inputs. Registers are $x000-$x003 by those inputs, whatever the address
+/// the game writes. Boards of unknown variant get several lines ORed, which
+/// works for every game since each only toggles the lines its board uses.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(super) struct Wiring {
+    low: u16,
+    high: u16,
+}
+
+impl Wiring {
+    pub(super) const fn new(low: u16, high: u16) -> Self {
+        Wiring { low, high }
+    }
+
+    pub(super) fn register(&self, address: u16) -> u16 {
+        let low = (address & self.low != 0) as u16;
+        let high = (address & self.high != 0) as u16;
+        (address & 0xF000) | (high << 1) | low
+    }
+}
+
+const CONTROL_ENABLE_AFTER_ACK: u8 = 0x01;
+const CONTROL_ENABLE: u8 = 0x02;
+const CONTROL_CYCLE_MODE: u8 = 0x04;
+
+/// The 8-bit IRQ counter, counting up from the latch to $FF. In scanline mode
+/// a prescaler divides CPU cycles by 113.667 (341/3), so the counter doesn't
+/// need the PPU at all.
+#[derive(Default)]
+pub(super) struct VrcIrq {
+    pub(super) latch: u8,
+    counter: u8,
+    prescaler: i16,
+    control: u8,
+    pending: bool,
+}
+
+impl VrcIrq {
+    pub(super) fn write_control(&mut self, value: u8) {
+        self.control = value;
+        self.pending = false;
+        if value & CONTROL_ENABLE != 0 {
+            self.counter = self.latch;
+            self.prescaler = 341;
+        }
+    }
+
+    /// Acknowledges the IRQ and moves the enable-after-acknowledge bit into
+    /// the enable bit.
+    pub(super) fn acknowledge(&mut self) {
+        self.pending = false;
+        if self.control & CONTROL_ENABLE_AFTER_ACK != 0 {
+            self.control |= CONTROL_ENABLE;
+        } else {
+            self.control &= !CONTROL_ENABLE;
+        }
+    }
+
+    pub(super) fn pending(&self) -> bool {
+        self.pending
+    }
+
+    pub(super) fn cpu_cycle(&mut self) {
+        if self.control & CONTROL_ENABLE == 0 {
+            return;
+        }
+        if self.control & CONTROL_CYCLE_MODE == 0 {
+            self.prescaler -= 3;
+            if self.prescaler > 0 {
+                return;
+            }
+            self.prescaler += 341;
+        }
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wiring_selects_registers_from_any_lines() {
+        let vrc4c = Wiring::new(0x40, 0x80);
+        assert_eq!(vrc4c.register(0xB0C0), 0xB003);
+        assert_eq!(vrc4c.register(0xB002), 0xB000);
+        let either = Wiring::new(0x02 | 0x40, 0x04 | 0x80);
+        assert_eq!(either.register(0xF004), 0xF002);
+        assert_eq!(either.register(0xF040), 0xF001);
+    }
+
+    #[test]
+    fn cycle_mode_counts_every_cpu_cycle() {
+        let mut irq = VrcIrq {
+            latch: 0xFD,
+            ..Default::default()
+        };
+        irq.write_control(CONTROL_ENABLE | CONTROL_CYCLE_MODE);
+        irq.cpu_cycle();
+        irq.cpu_cycle();
+        assert!(!irq.pending());
+        irq.cpu_cycle();
+        assert!(irq.pending());
+        // Reloaded from the latch
+        irq.acknowledge();
+        assert!(!irq.pending());
+        for _ in 0..3 {
+            irq.cpu_cycle();
+        }
+        assert!(!irq.pending(), "acknowledge without A disables the IRQ");
+    }
+
+    #[test]
+    fn scanline_mode_counts_every_341_dots() {
+        let mut irq = VrcIrq {
+            latch: 0xFE,
+            ..Default::default()
+        };
+        irq.write_control(CONTROL_ENABLE | CONTROL_ENABLE_AFTER_ACK);
+        // Lines of 114, 114 and 113 cycles
+        let mut cycles = 0;
+        while !irq.pending() {
+            irq.cpu_cycle();
+            cycles += 1;
+        }
+        assert_eq!(cycles, 114 * 2);
+        irq.acknowledge();
+        irq.cpu_cycle();
+        assert_eq!(irq.counter, 0xFE);
+    }
+}
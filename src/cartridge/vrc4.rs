@@ -0,0 +1,309 @@
+//! VRC2 and VRC4, iNES mappers 21, 22, 23 and 25.
+//!
+//! | Register    | Function                                                  |
+//! |-------------|-----------------------------------------------------------|
+//! | $8000-$8003 | 8KB PRG bank at $8000, or at $C000 in VRC4 swap mode      |
+//! | $9000-$9001 | Mirroring: vertical, horizontal, one screen lower, upper  |
+//! | $9002-$9003 | VRC4 PRG swap mode in bit 1, VRC2 mirroring again         |
+//! | $A000-$A003 | 8KB PRG bank at $A000                                     |
+//! | $B000-$E003 | 1KB CHR banks, low and high nibble in turn: $B000/$B001   |
+//! |             | for bank 0, $B002/$B003 for bank 1, ... $E002/$E003 for 7 |
+//! | $F000-$F003 | VRC4 IRQ: latch low and high nibble, control, acknowledge |
+//!
+//! The rest of PRG is fixed to the second last and last banks. VRC2 has no
+//! IRQ, a 1-bit mirroring register, and on boards without PRG-RAM a 1-bit
+//! latch at $6000-$6FFF. Only bit 0 of the latch is driven, but a mapper
+//! can't see open bus so the other bits read as 0.
+//!
+//! Which CPU address lines select $x001-$x003 differs by board. NES 2.0
+//! submappers name the board; without one every line the mapper number is
+//! used with is decoded:
+//!
+//! | Mapper | Submapper 1      | Submapper 2      | Submapper 3      |
+//! |--------|------------------|------------------|------------------|
+//! | 21     | VRC4a: A1, A2    | VRC4c: A6, A7    |                  |
+//! | 22     | VRC2a: A1, A0    |                  |                  |
+//! | 23     | VRC4f: A0, A1    | VRC4e: A2, A3    | VRC2b: A0, A1    |
+//! | 25     | VRC4b: A1, A0    | VRC4d: A3, A2    | VRC2c: A1, A0    |
+//!
+//! VRC2a also leaves out CHR A10, so its CHR banks count 2KB.
+
+use super::vrc::{VrcIrq, Wiring};
+use super::{Cartridge, Mapper, Mirroring};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+const PRG_SWAP: u8 = 0x02;
+
+pub struct Vrc4 {
+    vrc2: bool,
+    wiring: Wiring,
+    chr_shift: u8,
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_banks: [u8; 2],
+    prg_mode: u8,
+    chr_banks: [u16; 8],
+    mirroring: u8,
+    microwire: u8, // the VRC2 latch at $6000
+    irq: VrcIrq,
+}
+
+impl Vrc4 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let (vrc2, low, high) = match (cartridge.mapper, cartridge.submapper) {
+            (21, 1) => (false, 0x02, 0x04),
+            (21, 2) => (false, 0x40, 0x80),
+            (21, _) => (false, 0x42, 0x84),
+            (22, _) => (true, 0x02, 0x01),
+            (23, 1) => (false, 0x01, 0x02),
+            (23, 2) => (false, 0x04, 0x08),
+            (23, 3) => (true, 0x01, 0x02),
+            (23, _) => (false, 0x05, 0x0A),
+            (25, 1) => (false, 0x02, 0x01),
+            (25, 2) => (false, 0x08, 0x04),
+            (25, 3) => (true, 0x02, 0x01),
+            (_, _) => (false, 0x0A, 0x05),
+        };
+        Vrc4 {
+            vrc2,
+            wiring: Wiring::new(low, high),
+            chr_shift: (cartridge.mapper == 22) as u8,
+            prg_rom: cartridge.prg_rom,
+            prg_ram: vec![0; cartridge.prg_ram_size + cartridge.prg_nvram_size],
+            chr: cartridge.chr,
+            chr_is_ram: cartridge.chr_is_ram,
+            prg_banks: [0; 2],
+            prg_mode: 0,
+            chr_banks: [0; 8],
+            mirroring: 0,
+            microwire: 0,
+            irq: VrcIrq::default(),
+        }
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0x8000..=0x8003 => self.prg_banks[0] = value & 0x1F,
+            0x9000..=0x9003 if self.vrc2 => self.mirroring = value & 0x01,
+            0x9000..=0x9001 => self.mirroring = value & 0x03,
+            0x9002..=0x9003 => self.prg_mode = value,
+            0xA000..=0xA003 => self.prg_banks[1] = value & 0x1F,
+            0xB000..=0xE003 => {
+                let index =
+                    (((register as usize - 0xB000) >> 12) << 1) | ((register as usize >> 1) & 1);
+                let bank = &mut self.chr_banks[index];
+                *bank = if register & 0x0001 == 0 {
+                    (*bank & 0x1F0) | (value as u16 & 0x0F)
+                } else {
+                    (*bank & 0x00F) | ((value as u16 & 0x1F) << 4)
+                };
+            }
+            0xF000 if !self.vrc2 => self.irq.latch = (self.irq.latch & 0xF0) | (value & 0x0F),
+            0xF001 if !self.vrc2 => self.irq.latch = (self.irq.latch & 0x0F) | (value << 4),
+            0xF002 if !self.vrc2 => self.irq.write_control(value),
+            0xF003 if !self.vrc2 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> usize {
+        let banks = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+        let swap = self.prg_mode & PRG_SWAP != 0;
+        let bank = match (address >> 13) & 0x03 {
+            0 if swap => banks.saturating_sub(2),
+            0 => self.prg_banks[0] as usize,
+            1 => self.prg_banks[1] as usize,
+            2 if swap => self.prg_banks[0] as usize,
+            2 => banks.saturating_sub(2),
+            _ => banks - 1,
+        };
+        // Less than a bank mirrors within it
+        ((bank % banks) * PRG_BANK_SIZE + (address as usize & 0x1FFF)) % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let mut bank = self.chr_banks[(address >> 10) as usize & 0x07] as usize;
+        if self.vrc2 {
+            bank &= 0xFF;
+        }
+        let bank = bank >> self.chr_shift;
+        let banks = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        (bank % banks) * CHR_BANK_SIZE + (address as usize & 0x03FF)
+    }
+}
+
+impl Mapper for Vrc4 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x6FFF if self.vrc2 && self.prg_ram.is_empty() => Some(self.microwire),
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(address as usize - 0x6000) % self.prg_ram.len()])
+            }
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_rom_offset(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x6FFF if self.vrc2 && self.prg_ram.is_empty() => {
+                self.microwire = value & 0x01
+            }
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(address as usize - 0x6000) % len] = value;
+            }
+            0x8000..=0xFFFF => self.write_register(self.wiring.register(address), value),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let i = self.chr_offset(address);
+            self.chr[i] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        let mirroring = if self.vrc2 {
+            self.mirroring & 0x01
+        } else {
+            self.mirroring
+        };
+        match mirroring {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.irq.cpu_cycle();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::prg_banks;
+
+    fn vrc4(mapper: u16, submapper: u8) -> Vrc4 {
+        let mut cartridge = Cartridge::numbered(mapper, 0x40000, 0x80000);
+        cartridge.submapper = submapper;
+        Vrc4::new(cartridge)
+    }
+
+    #[test]
+    fn prg_banks_and_swap_mode() {
+        let mut vrc4 = vrc4(21, 1);
+        vrc4.cpu_write(0x8000, 4);
+        vrc4.cpu_write(0xA000, 9);
+        assert_eq!(prg_banks(&vrc4), [4, 9, 30, 31]);
+        vrc4.cpu_write(0x9004, PRG_SWAP);
+        assert_eq!(prg_banks(&vrc4), [30, 9, 4, 31]);
+    }
+
+    #[test]
+    fn chr_banks_take_two_nibbles() {
+        // VRC4c selects with A6 and A7
+        let mut vrc4 = vrc4(21, 2);
+        vrc4.cpu_write(0xD080, 0x05); // bank 5 low
+        vrc4.cpu_write(0xD0C0, 0x11); // bank 5 high
+        assert_eq!(vrc4.chr_banks[5], 0x115);
+        assert_eq!(vrc4.ppu_peek(0x1400), 0x15);
+        assert_eq!(vrc4.chr_offset(0x1400), 0x115 * 0x400);
+    }
+
+    #[test]
+    fn submappers_pick_the_address_lines() {
+        for (mapper, submapper, write) in [
+            (21, 1, 0xB006),
+            (21, 2, 0xB0C0),
+            (21, 0, 0xB0C0),
+            (23, 1, 0xB003),
+            (23, 2, 0xB00C),
+            (23, 0, 0xB00C),
+            (25, 1, 0xB003),
+            (25, 2, 0xB00C),
+            (25, 0, 0xB00F),
+        ] {
+            let mut vrc4 = vrc4(mapper, submapper);
+            vrc4.cpu_write(write, 0x01);
+            assert_eq!(vrc4.chr_banks[1], 0x010, "mapper {mapper}.{submapper}");
+        }
+    }
+
+    #[test]
+    fn vrc2a_chr_banks_count_2kb() {
+        let mut vrc2 = vrc4(22, 0);
+        assert!(vrc2.vrc2);
+        vrc2.cpu_write(0xB000, 0x06);
+        assert_eq!(vrc2.ppu_peek(0x0000), 3);
+        // A0 selects $B002 on VRC2a
+        vrc2.cpu_write(0xB001, 0x0F);
+        assert_eq!(vrc2.chr_banks[0], 0x006);
+        assert_eq!(vrc2.chr_banks[1], 0x00F);
+    }
+
+    #[test]
+    fn vrc2_mirroring_and_microwire_latch() {
+        let mut cartridge = Cartridge::numbered(23, 0x20000, 0x20000);
+        cartridge.submapper = 3;
+        cartridge.prg_ram_size = 0;
+        let mut vrc2 = Vrc4::new(cartridge);
+        vrc2.cpu_write(0x9000, 0x03);
+        assert_eq!(vrc2.mirroring(), Mirroring::Horizontal);
+        // All four registers are mirroring, there is no swap mode
+        vrc2.cpu_write(0x9002, 0x02);
+        assert_eq!(vrc2.mirroring(), Mirroring::Vertical);
+        vrc2.cpu_write(0x9003, 0x01);
+        assert_eq!(vrc2.mirroring(), Mirroring::Horizontal);
+        assert_eq!(prg_banks(&vrc2)[2], 14);
+        vrc2.cpu_write(0x6000, 0xFF);
+        assert_eq!(vrc2.cpu_peek(0x6000), Some(0x01));
+        assert_eq!(vrc2.cpu_peek(0x7000), None);
+        // No IRQ
+        vrc2.cpu_write(0xF002, 0x06);
+        vrc2.cpu_cycle();
+        vrc2.cpu_write(0xF003, 0x00);
+        assert!(!vrc2.irq());
+    }
+
+    #[test]
+    fn small_prg_stays_in_range() {
+        for size in [0x2000, 0x1000] {
+            let vrc4 = Vrc4::new(Cartridge::numbered(21, size, 0x2000));
+            assert_eq!(prg_banks(&vrc4), [0, 0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn vrc4_mirroring_and_irq() {
+        let mut vrc4 = vrc4(25, 1);
+        vrc4.cpu_write(0x9000, 0x02);
+        assert_eq!(vrc4.mirroring(), Mirroring::SingleScreenLower);
+        // VRC4b swaps the lines: $F002 is the latch high nibble, $F001 control
+        vrc4.cpu_write(0xF000, 0x0E);
+        vrc4.cpu_write(0xF002, 0x0F);
+        vrc4.cpu_write(0xF001, 0x06); // enabled, cycle mode
+        vrc4.cpu_cycle();
+        assert!(!vrc4.irq());
+        vrc4.cpu_cycle();
+        assert!(vrc4.irq());
+        vrc4.cpu_write(0xF003, 0x00);
+        assert!(!vrc4.irq());
+    }
+}
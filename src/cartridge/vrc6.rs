@@ -0,0 +1,238 @@
+//! VRC6, iNES mappers 24 (VRC6a) and 26 (VRC6b, A0 and A1 swapped).
+//!
+//! | Register    | Function                                                  |
+//! |-------------|-----------------------------------------------------------|
+//! | $8000-$8003 | 16KB PRG bank at $8000                                    |
+//! | $9000-$B002 | Expansion audio, see `audio`                              |
+//! | $B003       | PRG-RAM enable in bit 7, mirroring in bits 2-3, CHR mode  |
+//! | $C000-$C003 | 8KB PRG bank at $C000                                     |
+//! | $D000-$E003 | CHR bank registers R0-R7                                  |
+//! | $F000       | IRQ latch                                                 |
+//! | $F001       | IRQ control                                               |
+//! | $F002       | IRQ acknowledge                                           |
+//!
+//! $E000-$FFFF is fixed to the last 8KB bank. CHR mode 0 switches eight 1KB
+//! banks with R0-R7, mode 1 four 2KB banks with R0-R3, and modes 2 and 3
+//! R0-R3 as 1KB banks at $0000 and R4-R5 as 2KB banks at $1000. 2KB banks
+//! take their low bit from PPU A10, as on every board made. Nametables from
+//! CHR-ROM ($B003 bit 4) aren't supported: no game uses them.
+
+mod audio;
+
+use super::vrc::{VrcIrq, Wiring};
+use super::{Cartridge, Mapper, Mirroring};
+use audio::Audio;
+
+const CHR_BANK_SIZE: usize = 0x0400;
+const PRG_RAM_ENABLE: u8 = 0x80;
+
+pub struct Vrc6 {
+    wiring: Wiring,
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_banks: [u8; 2],
+    chr_banks: [u8; 8],
+    control: u8, // $B003
+    irq: VrcIrq,
+    audio: Audio,
+}
+
+impl Vrc6 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let wiring = if cartridge.mapper == 26 {
+            Wiring::new(0x02, 0x01)
+        } else {
+            Wiring::new(0x01, 0x02)
+        };
+        Vrc6 {
+            wiring,
+            prg_rom: cartridge.prg_rom,
+            prg_ram: vec![0; cartridge.prg_ram_size + cartridge.prg_nvram_size],
+            chr: cartridge.chr,
+            chr_is_ram: cartridge.chr_is_ram,
+            prg_banks: [0; 2],
+            chr_banks: [0; 8],
+            control: 0,
+            irq: VrcIrq::default(),
+            audio: Audio::default(),
+        }
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> usize {
+        let banks = (self.prg_rom.len() / 0x2000).max(1);
+        let bank = match address {
+            0x8000..=0xBFFF => self.prg_banks[0] as usize * 2 + ((address as usize >> 13) & 1),
+            0xC000..=0xDFFF => self.prg_banks[1] as usize,
+            _ => banks - 1,
+        };
+        // Less than a bank mirrors within it
+        ((bank % banks) * 0x2000 + (address as usize & 0x1FFF)) % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let slot = (address >> 10) as usize & 0x07;
+        let a10 = slot & 1;
+        let bank = match (self.control & 0x03, slot) {
+            (0, _) => self.chr_banks[slot] as usize,
+            (1, _) => (self.chr_banks[slot >> 1] as usize & !1) | a10,
+            (_, 0..=3) => self.chr_banks[slot] as usize,
+            (_, _) => (self.chr_banks[4 + ((slot - 4) >> 1)] as usize & !1) | a10,
+        };
+        let banks = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        (bank % banks) * CHR_BANK_SIZE + (address as usize & 0x03FF)
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && self.control & PRG_RAM_ENABLE != 0
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                Some(self.prg_ram[(address as usize - 0x6000) % self.prg_ram.len()])
+            }
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_rom_offset(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if address < 0x8000 {
+            if (0x6000..=0x7FFF).contains(&address) && self.prg_ram_enabled() {
+                let len = self.prg_ram.len();
+                self.prg_ram[(address as usize - 0x6000) % len] = value;
+            }
+            return;
+        }
+        match self.wiring.register(address) {
+            0x8000..=0x8003 => self.prg_banks[0] = value & 0x0F,
+            register @ 0x9000..=0xB002 => self.audio.write(register, value),
+            0xB003 => self.control = value,
+            0xC000..=0xC003 => self.prg_banks[1] = value & 0x1F,
+            register @ 0xD000..=0xE003 => {
+                let index = (((register as usize - 0xD000) >> 12) << 2) | (register as usize & 3);
+                self.chr_banks[index] = value;
+            }
+            0xF000 => self.irq.latch = value,
+            0xF001 => self.irq.write_control(value),
+            0xF002 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let i = self.chr_offset(address);
+            self.chr[i] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match (self.control >> 2) & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn audio(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.irq.cpu_cycle();
+        self.audio.clock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::prg_banks;
+
+    fn vrc6(mapper: u16) -> Vrc6 {
+        Vrc6::new(Cartridge::numbered(mapper, 0x40000, 0x40000))
+    }
+
+    #[test]
+    fn prg_bank_sizes() {
+        let mut vrc6 = vrc6(24);
+        vrc6.cpu_write(0x8000, 3);
+        vrc6.cpu_write(0xC000, 9);
+        assert_eq!(prg_banks(&vrc6), [6, 7, 9, 31]);
+    }
+
+    #[test]
+    fn small_prg_stays_in_range() {
+        for size in [0x2000, 0x1000] {
+            let vrc6 = Vrc6::new(Cartridge::numbered(24, size, 0x2000));
+            assert_eq!(prg_banks(&vrc6), [0, 0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn chr_modes() {
+        let mut vrc6 = vrc6(26);
+        for (i, address) in [
+            0xD000, 0xD002, 0xD001, 0xD003, 0xE000, 0xE002, 0xE001, 0xE003,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            vrc6.cpu_write(address, 0x10 + i as u8);
+        }
+        let banks = |v: &Vrc6| [0, 1, 2, 3, 4, 5, 6, 7].map(|slot| v.ppu_peek(slot * 0x400));
+        assert_eq!(
+            banks(&vrc6),
+            [0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17]
+        );
+        vrc6.cpu_write(0xB003, 0x01);
+        assert_eq!(
+            banks(&vrc6),
+            [0x10, 0x11, 0x10, 0x11, 0x12, 0x13, 0x12, 0x13]
+        );
+        vrc6.cpu_write(0xB003, 0x02);
+        assert_eq!(
+            banks(&vrc6),
+            [0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x14, 0x15]
+        );
+    }
+
+    #[test]
+    fn control_register() {
+        let mut vrc6 = vrc6(24);
+        vrc6.cpu_write(0x6000, 0x55);
+        assert_eq!(vrc6.cpu_peek(0x6000), None);
+        vrc6.cpu_write(0xB003, PRG_RAM_ENABLE | 0x04);
+        vrc6.cpu_write(0x6000, 0x55);
+        assert_eq!(vrc6.cpu_peek(0x6000), Some(0x55));
+        assert_eq!(vrc6.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn irq_and_audio_run_on_cpu_cycles() {
+        let mut vrc6 = vrc6(24);
+        vrc6.cpu_write(0xF000, 0xFF);
+        vrc6.cpu_write(0xF001, 0x06);
+        vrc6.cpu_write(0x9000, 0x8F); // constant volume 15
+        vrc6.cpu_write(0x9002, 0x80);
+        assert!(vrc6.audio() > 0.0);
+        vrc6.cpu_cycle();
+        assert!(vrc6.irq());
+        vrc6.cpu_write(0xF002, 0x00);
+        assert!(!vrc6.irq());
+    }
+}
@@ -0,0 +1,198 @@
+//! VRC6 expansion audio: two pulse channels with 16-step duty cycles and a
+//! sawtooth channel.
+//!
+//! | Pulse 1 | Pulse 2 | Sawtooth | Function                                 |
+//! |---------|---------|----------|------------------------------------------|
+//! | $9000   | $A000   | $B000    | Pulse MDDD VVVV, sawtooth --RR RRRR rate |
+//! | $9001   | $A001   | $B001    | Period low 8 bits                        |
+//! | $9002   | $A002   | $B002    | E--- PPPP: enable, period high 4 bits    |
+//!
+//! $9003 halts every channel with bit 0 and speeds them up 16 or 256 times
+//! with bits 1 and 2, by dropping low bits of the period.
+
+const PULSE_CONSTANT: u8 = 0x80;
+const ENABLE: u8 = 0x80;
+const HALT: u8 = 0x01;
+const SHIFT_4: u8 = 0x02;
+const SHIFT_8: u8 = 0x04;
+
+#[derive(Default)]
+struct Channel {
+    control: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+    accumulator: u8, // sawtooth only
+}
+
+impl Channel {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => self.control = value,
+            1 => self.period = (self.period & 0x0F00) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((value as u16 & 0x0F) << 8);
+                self.enabled = value & ENABLE != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    /// Counts the timer down, returning true when it reloads.
+    fn clock_timer(&mut self, shift: u8) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            true
+        } else {
+            self.timer -= 1;
+            false
+        }
+    }
+
+    fn clock_pulse(&mut self, shift: u8) {
+        if self.clock_timer(shift) {
+            self.step = (self.step + 1) & 0x0F;
+        }
+    }
+
+    /// The accumulator adds the rate every other step and resets on the
+    /// fourteenth.
+    fn clock_sawtooth(&mut self, shift: u8) {
+        if !self.clock_timer(shift) {
+            return;
+        }
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step & 1 == 0 {
+            self.accumulator = self.accumulator.wrapping_add(self.control & 0x3F);
+        }
+    }
+
+    fn pulse_output(&self) -> u8 {
+        let duty = (self.control >> 4) & 0x07;
+        let high = self.control & PULSE_CONSTANT != 0 || 15 - self.step <= duty;
+        if self.enabled && high {
+            self.control & 0x0F
+        } else {
+            0
+        }
+    }
+
+    fn sawtooth_output(&self) -> u8 {
+        if self.enabled {
+            self.accumulator >> 3
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Default)]
+pub(super) struct Audio {
+    pulses: [Channel; 2],
+    sawtooth: Channel,
+    frequency: u8,
+}
+
+impl Audio {
+    /// Takes the register after the board's wiring, $9000-$B003.
+    pub(super) fn write(&mut self, register: u16, value: u8) {
+        let index = register & 0x0003;
+        match register {
+            0x9003 => self.frequency = value,
+            0x9000..=0x9002 => self.pulses[0].write(index, value),
+            0xA000..=0xA002 => self.pulses[1].write(index, value),
+            0xB000..=0xB002 => self.sawtooth.write(index, value),
+            _ => {}
+        }
+    }
+
+    pub(super) fn clock(&mut self) {
+        if self.frequency & HALT != 0 {
+            return;
+        }
+        let shift = if self.frequency & SHIFT_8 != 0 {
+            8
+        } else if self.frequency & SHIFT_4 != 0 {
+            4
+        } else {
+            0
+        };
+        for pulse in &mut self.pulses {
+            pulse.clock_pulse(shift);
+        }
+        self.sawtooth.clock_sawtooth(shift);
+    }
+
+    /// The channels add up linearly, at about the level of the APU's pulses.
+    pub(super) fn output(&self) -> f32 {
+        let sum = self.pulses[0].pulse_output()
+            + self.pulses[1].pulse_output()
+            + self.sawtooth.sawtooth_output();
+        0.00752 * sum as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pulse_duty_takes_sixteenths() {
+        let mut audio = Audio::default();
+        audio.write(0x9000, 0x2F); // 3/16 duty, volume 15
+        audio.write(0x9001, 0x00);
+        audio.write(0x9002, ENABLE);
+        let mut high = 0;
+        for _ in 0..16 {
+            audio.clock();
+            if audio.pulses[0].pulse_output() == 15 {
+                high += 1;
+            }
+        }
+        assert_eq!(high, 3);
+        audio.write(0x9000, PULSE_CONSTANT | 0x07);
+        assert_eq!(audio.pulses[0].pulse_output(), 7);
+        audio.write(0x9002, 0x00);
+        assert_eq!(audio.output(), 0.0);
+    }
+
+    #[test]
+    fn sawtooth_ramps_and_resets() {
+        let mut audio = Audio::default();
+        audio.write(0xB000, 0x2A);
+        audio.write(0xB002, ENABLE);
+        let mut levels = Vec::new();
+        for _ in 0..14 {
+            audio.clock();
+            levels.push(audio.sawtooth.accumulator);
+        }
+        assert_eq!(
+            levels,
+            [0, 42, 42, 84, 84, 126, 126, 168, 168, 210, 210, 252, 252, 0]
+        );
+    }
+
+    #[test]
+    fn frequency_control_halts_and_shifts() {
+        let mut audio = Audio::default();
+        audio.write(0x9002, ENABLE | 0x01); // period $100
+        audio.write(0x9003, HALT);
+        audio.clock();
+        assert_eq!(audio.pulses[0].step, 0);
+        audio.write(0x9003, SHIFT_8);
+        audio.clock();
+        audio.clock();
+        audio.clock();
+        assert_eq!(audio.pulses[0].step, 2);
+    }
+}
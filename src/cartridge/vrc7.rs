@@ -0,0 +1,230 @@
+//! VRC7, iNES mapper 85.
+//!
+//! | Register | Function                                                     |
+//! |----------|--------------------------------------------------------------|
+//! | $8000    | 8KB PRG bank at $8000                                        |
+//! | $8010    | 8KB PRG bank at $A000                                        |
+//! | $9000    | 8KB PRG bank at $C000                                        |
+//! | $9010    | Audio register select, see `audio`                           |
+//! | $9030    | Audio register write                                         |
+//! | $A000... | 1KB CHR banks: $A000, $A010, $B000, ... $D010 for 0 to 7     |
+//! | $E000    | RS-- --MM: audio reset, PRG-RAM enable, mirroring            |
+//! | $E010    | IRQ latch                                                    |
+//! | $F000    | IRQ control                                                  |
+//! | $F010    | IRQ acknowledge                                              |
+//!
+//! $E000-$FFFF is fixed to the last 8KB bank. VRC7a boards (Lagrange Point,
+//! submapper 2) select the odd registers with A4, VRC7b boards (submapper 1)
+//! with A3. A5 tells the audio registers apart.
+
+mod audio;
+
+use super::vrc::{VrcIrq, Wiring};
+use super::{Cartridge, Mapper, Mirroring};
+use audio::Audio;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+const PRG_RAM_ENABLE: u8 = 0x40;
+const AUDIO_RESET: u8 = 0x80;
+
+pub struct Vrc7 {
+    wiring: Wiring,
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    control: u8, // $E000
+    irq: VrcIrq,
+    audio: Audio,
+}
+
+impl Vrc7 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let select = match cartridge.submapper {
+            1 => 0x08,
+            2 => 0x10,
+            _ => 0x18,
+        };
+        Vrc7 {
+            wiring: Wiring::new(select, 0x20),
+            prg_rom: cartridge.prg_rom,
+            prg_ram: vec![0; cartridge.prg_ram_size + cartridge.prg_nvram_size],
+            chr: cartridge.chr,
+            chr_is_ram: cartridge.chr_is_ram,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            control: 0,
+            irq: VrcIrq::default(),
+            audio: Audio::default(),
+        }
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> usize {
+        let banks = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+        let bank = match (address >> 13) & 0x03 {
+            3 => banks - 1,
+            slot => self.prg_banks[slot as usize] as usize,
+        };
+        // Less than a bank mirrors within it
+        ((bank % banks) * PRG_BANK_SIZE + (address as usize & 0x1FFF)) % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let bank = self.chr_banks[(address >> 10) as usize & 0x07] as usize;
+        let banks = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        (bank % banks) * CHR_BANK_SIZE + (address as usize & 0x03FF)
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && self.control & PRG_RAM_ENABLE != 0
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                Some(self.prg_ram[(address as usize - 0x6000) % self.prg_ram.len()])
+            }
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_rom_offset(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if address < 0x8000 {
+            if (0x6000..=0x7FFF).contains(&address) && self.prg_ram_enabled() {
+                let len = self.prg_ram.len();
+                self.prg_ram[(address as usize - 0x6000) % len] = value;
+            }
+            return;
+        }
+        match self.wiring.register(address) {
+            0x8000 => self.prg_banks[0] = value & 0x3F,
+            0x8001 => self.prg_banks[1] = value & 0x3F,
+            0x9000 => self.prg_banks[2] = value & 0x3F,
+            0x9001 => self.audio.select(value),
+            0x9003 => self.audio.write(value),
+            register @ (0xA000..=0xD001) if register & 0x0002 == 0 => {
+                let index = (((register as usize - 0xA000) >> 12) << 1) | (register as usize & 1);
+                self.chr_banks[index] = value;
+            }
+            0xE000 => {
+                self.control = value;
+                self.audio.set_silenced(value & AUDIO_RESET != 0);
+            }
+            0xE001 => self.irq.latch = value,
+            0xF000 => self.irq.write_control(value),
+            0xF001 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let i = self.chr_offset(address);
+            self.chr[i] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn audio(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.irq.cpu_cycle();
+        self.audio.clock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::prg_banks;
+
+    fn vrc7(submapper: u8) -> Vrc7 {
+        let mut cartridge = Cartridge::numbered(85, 0x80000, 0x40000);
+        cartridge.submapper = submapper;
+        Vrc7::new(cartridge)
+    }
+
+    #[test]
+    fn prg_and_chr_banks() {
+        for (submapper, odd) in [(1, 0x08), (2, 0x10), (0, 0x10), (0, 0x08)] {
+            let mut vrc7 = vrc7(submapper);
+            vrc7.cpu_write(0x8000, 5);
+            vrc7.cpu_write(0x8000 | odd, 6);
+            vrc7.cpu_write(0x9000, 7);
+            assert_eq!(prg_banks(&vrc7), [5, 6, 7, 63], "submapper {submapper}");
+            vrc7.cpu_write(0xC000 | odd, 0x21);
+            assert_eq!(vrc7.ppu_peek(0x1400), 0x21);
+        }
+    }
+
+    #[test]
+    fn small_prg_stays_in_range() {
+        for size in [0x2000, 0x1000] {
+            let vrc7 = Vrc7::new(Cartridge::numbered(85, size, 0x2000));
+            assert_eq!(prg_banks(&vrc7), [0, 0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn control_register() {
+        let mut vrc7 = vrc7(2);
+        vrc7.cpu_write(0x6000, 0x55);
+        assert_eq!(vrc7.cpu_peek(0x6000), None);
+        vrc7.cpu_write(0xE000, PRG_RAM_ENABLE | 0x03);
+        vrc7.cpu_write(0x6000, 0x55);
+        assert_eq!(vrc7.cpu_peek(0x6000), Some(0x55));
+        assert_eq!(vrc7.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn irq_counts_cpu_cycles() {
+        let mut vrc7 = vrc7(2);
+        vrc7.cpu_write(0xE010, 0xFE);
+        vrc7.cpu_write(0xF000, 0x06);
+        vrc7.cpu_cycle();
+        assert!(!vrc7.irq());
+        vrc7.cpu_cycle();
+        assert!(vrc7.irq());
+        vrc7.cpu_write(0xF010, 0x00);
+        assert!(!vrc7.irq());
+    }
+
+    #[test]
+    fn audio_port() {
+        let mut vrc7 = vrc7(2);
+        for (register, value) in [(0x10, 0xAC), (0x30, 0x30), (0x20, 0x18)] {
+            vrc7.cpu_write(0x9010, register);
+            vrc7.cpu_write(0x9030, value);
+        }
+        let mut peak = 0f32;
+        for _ in 0..36 * 1000 {
+            vrc7.cpu_cycle();
+            peak = peak.max(vrc7.audio().abs());
+        }
+        assert!(peak > 0.0);
+    }
+}
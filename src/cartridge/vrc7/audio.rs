@@ -0,0 +1,364 @@
+//! VRC7 expansion audio: six channels of two-operator FM synthesis, a cut
+//! down YM2413 (OPLL).
+//!
+//! The CPU writes a register number to $9010 and then a value to $9030:
+//!
+//! | Register | Function                                                   |
+//! |----------|------------------------------------------------------------|
+//! | $00-$07  | The custom instrument, patch 0                             |
+//! | $10-$15  | Channel frequency, low 8 bits of F-number                  |
+//! | $20-$25  | --SK BBBF: sustain, key on, block (octave), F-number bit 8 |
+//! | $30-$35  | IIII VVVV: instrument, volume in 3dB steps of attenuation  |
+//!
+//! This is a floating point model rather than the chip's log-sine tables.
+//! Envelopes move linearly in decibels, and tremolo, vibrato and key scaling
+//! of level and rate are left out. Instruments keep their character, but the
+//! output doesn't match the chip sample for sample.
+
+use std::f32::consts::TAU;
+
+/// The built-in instruments, 1 to 15, as dumped from the chip.
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06],
+];
+
+/// Frequency multipliers, in halves.
+const MULTIPLIERS: [u8; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+/// CPU cycles per sample: the chip takes 72 of its 3.58MHz clocks.
+const SAMPLE_PERIOD: u8 = 36;
+const SAMPLE_RATE: f32 = 49716.0;
+
+/// The envelope range, quieter than this is silence.
+const SILENCE_DB: f32 = 48.0;
+/// Seconds from 0dB to silence at decay or release rate 0.
+const DECAY_SECONDS: f32 = 79.0;
+/// Attack is this many times faster than decay at the same rate.
+const ATTACK_SPEEDUP: f32 = 14.0;
+/// The release rate while a channel's sustain bit is set.
+const SUSTAIN_RELEASE_RATE: u8 = 5;
+
+/// How far the carrier's phase moves at full modulator output, in cycles.
+const MODULATION_DEPTH: f32 = 2.0;
+/// The level of one channel at full volume, next to the APU's output.
+const CHANNEL_LEVEL: f32 = 0.05;
+
+const KEY_ON: u8 = 0x10;
+const SUSTAIN: u8 = 0x20;
+const SUSTAINED_TONE: u8 = 0x20;
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    #[default]
+    Off,
+}
+
+#[derive(Default)]
+struct Operator {
+    phase: f32,
+    stage: Stage,
+    attenuation: f32, // envelope, in dB
+}
+
+impl Operator {
+    fn key_on(&mut self) {
+        if self.stage == Stage::Off {
+            self.attenuation = SILENCE_DB;
+        }
+        self.phase = 0.0;
+        self.stage = Stage::Attack;
+    }
+
+    fn key_off(&mut self) {
+        if self.stage != Stage::Off {
+            self.stage = Stage::Release;
+        }
+    }
+
+    /// `flags` is patch byte 0 or 1, `rates` byte 4 or 5 (attack, decay) and
+    /// `levels` byte 6 or 7 (sustain level, release).
+    fn clock_envelope(&mut self, flags: u8, rates: u8, levels: u8, sustain: bool) {
+        let sustain_level = (levels >> 4) as f32 * 3.0;
+        let release_rate = if sustain {
+            SUSTAIN_RELEASE_RATE
+        } else {
+            levels & 0x0F
+        };
+        match self.stage {
+            Stage::Attack => {
+                let rate = rates >> 4;
+                if rate == 15 {
+                    self.attenuation = 0.0;
+                } else {
+                    self.attenuation -= step(rate) * ATTACK_SPEEDUP;
+                }
+                if self.attenuation <= 0.0 {
+                    self.attenuation = 0.0;
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                self.attenuation += step(rates & 0x0F);
+                if self.attenuation >= sustain_level {
+                    self.attenuation = sustain_level;
+                    self.stage = Stage::Sustain;
+                }
+            }
+            // Percussive tones keep decaying at the release rate
+            Stage::Sustain if flags & SUSTAINED_TONE == 0 => {
+                self.attenuation += step(levels & 0x0F);
+            }
+            Stage::Sustain | Stage::Off => {}
+            Stage::Release => self.attenuation += step(release_rate),
+        }
+        if self.attenuation >= SILENCE_DB {
+            self.attenuation = SILENCE_DB;
+            if self.stage != Stage::Attack {
+                self.stage = Stage::Off;
+            }
+        }
+    }
+
+    fn advance(&mut self, flags: u8, frequency: f32) {
+        let multiplier = MULTIPLIERS[flags as usize & 0x0F] as f32 / 2.0;
+        self.phase = (self.phase + frequency * multiplier).fract();
+    }
+
+    /// The output for `phase`, between -1 and 1, after `level` dB on top of
+    /// the envelope.
+    fn output(&self, phase: f32, level: f32, rectified: bool) -> f32 {
+        let attenuation = self.attenuation + level;
+        if self.stage == Stage::Off || attenuation >= SILENCE_DB {
+            return 0.0;
+        }
+        let wave = (phase * TAU).sin();
+        let wave = if rectified { wave.max(0.0) } else { wave };
+        wave * 10f32.powf(-attenuation / 20.0)
+    }
+}
+
+/// The envelope step per sample at `rate`, in dB.
+fn step(rate: u8) -> f32 {
+    if rate == 0 {
+        return 0.0;
+    }
+    let seconds = DECAY_SECONDS / (1u32 << rate) as f32;
+    SILENCE_DB / (seconds * SAMPLE_RATE)
+}
+
+#[derive(Default)]
+struct Channel {
+    frequency: u16, // 9-bit F-number
+    control: u8,    // $2x
+    instrument: u8, // $3x
+    modulator: Operator,
+    carrier: Operator,
+    feedback: [f32; 2], // the modulator's last two outputs
+    output: f32,
+}
+
+impl Channel {
+    fn write_control(&mut self, value: u8) {
+        if value & KEY_ON != 0 && self.control & KEY_ON == 0 {
+            self.modulator.key_on();
+            self.carrier.key_on();
+            self.feedback = [0.0; 2];
+        } else if value & KEY_ON == 0 {
+            self.modulator.key_off();
+            self.carrier.key_off();
+        }
+        self.control = value;
+        self.frequency = (self.frequency & 0x00FF) | ((value as u16 & 0x01) << 8);
+    }
+
+    fn clock(&mut self, patch: &[u8; 8]) {
+        let sustain = self.control & SUSTAIN != 0;
+        self.modulator
+            .clock_envelope(patch[0], patch[4], patch[6], sustain);
+        self.carrier
+            .clock_envelope(patch[1], patch[5], patch[7], sustain);
+
+        // Phase per sample of the F-number at its block, in cycles
+        let block = (self.control >> 1) & 0x07;
+        let frequency = (self.frequency as u32) << block;
+        let frequency = frequency as f32 / (1 << 19) as f32;
+        self.modulator.advance(patch[0], frequency);
+        self.carrier.advance(patch[1], frequency);
+
+        let feedback = match patch[3] & 0x07 {
+            0 => 0.0,
+            n => (self.feedback[0] + self.feedback[1]) / 2.0 * (1 << n) as f32 / 64.0,
+        };
+        let modulator = self.modulator.output(
+            self.modulator.phase + feedback,
+            (patch[2] & 0x3F) as f32 * 0.75,
+            patch[3] & 0x08 != 0,
+        );
+        self.feedback = [modulator, self.feedback[0]];
+        self.output = self.carrier.output(
+            self.carrier.phase + modulator * MODULATION_DEPTH,
+            (self.instrument & 0x0F) as f32 * 3.0,
+            patch[3] & 0x10 != 0,
+        );
+    }
+}
+
+#[derive(Default)]
+pub(super) struct Audio {
+    address: u8,
+    custom: [u8; 8],
+    channels: [Channel; 6],
+    divider: u8,
+    silenced: bool,
+}
+
+impl Audio {
+    pub(super) fn select(&mut self, address: u8) {
+        self.address = address;
+    }
+
+    pub(super) fn write(&mut self, value: u8) {
+        let channel = self.address as usize & 0x0F;
+        match self.address {
+            0x00..=0x07 => self.custom[self.address as usize] = value,
+            0x10..=0x15 => {
+                let channel = &mut self.channels[channel];
+                channel.frequency = (channel.frequency & 0x0100) | value as u16;
+            }
+            0x20..=0x25 => self.channels[channel].write_control(value),
+            0x30..=0x35 => self.channels[channel].instrument = value,
+            _ => {}
+        }
+    }
+
+    /// $E000 bit 7 holds the synthesizer in reset.
+    pub(super) fn set_silenced(&mut self, silenced: bool) {
+        if silenced && !self.silenced {
+            *self = Audio {
+                silenced,
+                ..Default::default()
+            };
+        }
+        self.silenced = silenced;
+    }
+
+    pub(super) fn clock(&mut self) {
+        if self.silenced {
+            return;
+        }
+        self.divider += 1;
+        if self.divider < SAMPLE_PERIOD {
+            return;
+        }
+        self.divider = 0;
+        for channel in &mut self.channels {
+            let patch = match channel.instrument >> 4 {
+                0 => &self.custom,
+                n => &PATCHES[n as usize - 1],
+            };
+            channel.clock(patch);
+        }
+    }
+
+    pub(super) fn output(&self) -> f32 {
+        let sum: f32 = self.channels.iter().map(|c| c.output).sum();
+        sum * CHANNEL_LEVEL
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(audio: &mut Audio, address: u8, value: u8) {
+        audio.select(address);
+        audio.write(value);
+    }
+
+    /// Sets up channel 0 with an organ-like custom instrument: instant
+    /// attack, no decay and fast release.
+    fn organ(volume: u8) -> Audio {
+        let mut audio = Audio::default();
+        let patch = [0x21, 0x21, 0x10, 0x00, 0xF0, 0xF0, 0x0F, 0x0F];
+        for (register, value) in patch.into_iter().enumerate() {
+            write(&mut audio, register as u8, value);
+        }
+        write(&mut audio, 0x10, 0xAC); // about 261Hz at block 4
+        write(&mut audio, 0x30, volume);
+        audio
+    }
+
+    /// Runs `samples` samples, returning the loudest output.
+    fn run(audio: &mut Audio, samples: u32) -> f32 {
+        let mut peak = 0f32;
+        for _ in 0..samples * SAMPLE_PERIOD as u32 {
+            audio.clock();
+            peak = peak.max(audio.output().abs());
+        }
+        peak
+    }
+
+    #[test]
+    fn key_on_plays_and_key_off_releases() {
+        let mut audio = organ(0);
+        assert_eq!(run(&mut audio, 100), 0.0);
+        write(&mut audio, 0x20, KEY_ON | 0x08);
+        assert!(run(&mut audio, 1000) > 0.01);
+        write(&mut audio, 0x20, 0x08);
+        run(&mut audio, 200);
+        assert_eq!(audio.channels[0].carrier.stage, Stage::Off);
+        assert_eq!(run(&mut audio, 100), 0.0);
+    }
+
+    #[test]
+    fn volume_attenuates_in_3db_steps() {
+        let mut loud = organ(0);
+        let mut quiet = organ(2);
+        for audio in [&mut loud, &mut quiet] {
+            write(audio, 0x20, KEY_ON | 0x08);
+        }
+        let ratio = run(&mut quiet, 2000) / run(&mut loud, 2000);
+        assert!((ratio - 0.5).abs() < 0.05, "{ratio}");
+    }
+
+    #[test]
+    fn built_in_instruments_sound() {
+        for instrument in 1..=15 {
+            let mut audio = Audio::default();
+            write(&mut audio, 0x10, 0xAC);
+            write(&mut audio, 0x30, instrument << 4);
+            write(&mut audio, 0x20, KEY_ON | 0x08);
+            assert!(run(&mut audio, 4000) > 0.001, "instrument {instrument}");
+        }
+    }
+
+    #[test]
+    fn silencing_resets_the_channels() {
+        let mut audio = organ(0);
+        write(&mut audio, 0x20, KEY_ON);
+        audio.set_silenced(true);
+        assert_eq!(audio.custom[0], 0x00);
+        assert_eq!(audio.channels[0].control, 0x00);
+        write(&mut audio, 0x20, KEY_ON);
+        audio.set_silenced(true);
+        assert_eq!(run(&mut audio, 10), 0.0);
+    }
+}
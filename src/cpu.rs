@@ -1,7 +1,48 @@
-use crate::bus::Memory;
+pub mod assembler;
+mod cycle;
+mod disasm;
+pub mod opcodes;
+
+use crate::bus::{Dma, Memory};
+use crate::error::EmuError;
 use bitflags::bitflags;
+use cycle::{Access, Pipeline};
+use opcodes::{AddressingMode, Mnemonic, OPCODES};
+
+pub use disasm::{Instruction, disassemble, disassemble_range};
+
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+// OAM DMA writes every byte through OAMDATA
+const OAM_DATA: u16 = 0x2004;
 
-const STACK_SIZE_IN_BYTES: usize = 255;
+// ANE and LXA OR the accumulator with a chip dependent constant before the AND.
+const UNSTABLE_MAGIC: u8 = 0xEE;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Operand {
+    Implied,
+    Accumulator,
+    Relative, // the offset is consumed by the branch itself
+    Address(u16),
+    Latched(u16, u8), // address and a value already read from it
+}
+
+/// How `CPU::step` treats unofficial opcodes, including the KIL/JAM group.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum IllegalOpcodePolicy {
+    /// Execute them as the 2A03 does. KIL jams the CPU until reset.
+    #[default]
+    Emulate,
+    /// Log each one, then emulate it.
+    LogAndContinue,
+    /// Refuse to run them, leaving PC on the opcode. `try_step` and
+    /// `try_tick` report `EmuError::IllegalOpcode`, `step` and `tick` jam the
+    /// CPU as KIL does.
+    Error,
+}
 
 bitflags! {
     #[repr(transparent)]
@@ -44,10 +85,21 @@ pub struct CPU<M: Memory> {
     ac: u8,
     x: u8,
     y: u8,
-    pc: u16,                          // Program Counter
-    sp: u8,                           // Stack Pointer
-    stack: [u8; STACK_SIZE_IN_BYTES], // 0x0100 - 0x01FF
+    pc: u16, // Program Counter
+    sp: u8,  // Stack Pointer, the stack lives on the bus at 0x0100 - 0x01FF
     sr: CpuFlags,
+    cycles: u64, // Total cycles executed since power on
+    nmi_line: bool,
+    nmi_pending: bool, // NMI is edge triggered, latched until serviced
+    irq_line: bool,    // IRQ is level triggered, held by its sources
+    // The I flag as seen by the last interrupt poll. CLI, SEI and PLP poll
+    // before changing I, so their effect on IRQs is delayed by one instruction.
+    irq_inhibit: bool,
+    jammed: bool,
+    illegal_opcode_policy: IllegalOpcodePolicy,
+    fault: Option<EmuError>, // raised by the current step or tick, see `try_step`
+    dma_cycles: u64,         // cycles the CPU was halted for during the current step
+    pipeline: Pipeline,      // state of a partially executed instruction, see `tick`
     pub bus: M,
 }
 
@@ -56,11 +108,20 @@ impl<M: Memory> CPU<M> {
         CPU {
             pc: 0u16,
             sp: 0xFF,
-            stack: [0; 255],
             ac: 0u8,
             x: 0u8,
             y: 0u8,
             sr: CpuFlags::UNUSED | CpuFlags::INTERRUPT_DISABLE,
+            cycles: 0,
+            nmi_line: false,
+            nmi_pending: false,
+            irq_line: false,
+            irq_inhibit: true,
+            jammed: false,
+            illegal_opcode_policy: IllegalOpcodePolicy::default(),
+            fault: None,
+            dma_cycles: 0,
+            pipeline: Pipeline::default(),
             bus,
         }
     }
@@ -83,6 +144,15 @@ impl<M: Memory> CPU<M> {
     pub fn get_p(&self) -> u8 {
         self.sr.bits()
     }
+    pub fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
+    pub fn is_jammed(&self) -> bool {
+        self.jammed
+    }
+    pub fn get_illegal_opcode_policy(&self) -> IllegalOpcodePolicy {
+        self.illegal_opcode_policy
+    }
 
     pub fn set_a(&mut self, v: u8) {
         self.ac = v;
@@ -99,8 +169,40 @@ impl<M: Memory> CPU<M> {
     pub fn set_sp(&mut self, v: u8) {
         self.sp = v;
     }
+    pub fn set_illegal_opcode_policy(&mut self, policy: IllegalOpcodePolicy) {
+        self.illegal_opcode_policy = policy;
+    }
     pub fn set_p(&mut self, v: u8) {
         self.sr = CpuFlags::from_bits_truncate(v);
+        self.irq_inhibit = self.get_flag(CpuFlags::INTERRUPT_DISABLE);
+    }
+
+    /// Drive the NMI input. An NMI is triggered on the transition to asserted
+    /// and is serviced before the next instruction.
+    pub fn nmi(&mut self, asserted: bool) {
+        if asserted && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = asserted;
+    }
+
+    /// Drive the IRQ input. The IRQ is serviced while the line stays asserted
+    /// and interrupts are enabled.
+    pub fn irq(&mut self, asserted: bool) {
+        self.irq_line = asserted;
+    }
+
+    /// Reset performs the interrupt sequence with the writes suppressed: SP is
+    /// still decremented by 3, I is set and PC is loaded from the reset vector.
+    pub fn reset(&mut self) {
+        self.sp = self.sp.wrapping_sub(3);
+        self.set_flag(CpuFlags::INTERRUPT_DISABLE, true);
+        self.irq_inhibit = true;
+        self.nmi_pending = false;
+        self.jammed = false;
+        self.pipeline = Pipeline::default();
+        self.pc = self.read_vector(RESET_VECTOR);
+        self.cycles += 7 + std::mem::take(&mut self.dma_cycles);
     }
 
     fn get_flag(&self, flag: CpuFlags) -> bool {
@@ -111,6 +213,13 @@ impl<M: Memory> CPU<M> {
         self.sr.set(flag, value);
     }
 
+    /// B is not a real flag, it is ignored when pulled and UNUSED always reads back as 1.
+    fn set_status_from_stack(&mut self, value: u8) {
+        self.sr = CpuFlags::from_bits_truncate(value);
+        self.sr.remove(CpuFlags::BREAK);
+        self.sr.insert(CpuFlags::UNUSED);
+    }
+
     fn set_zero_and_negative_flag(&mut self, value: u8) {
         self.set_flag(CpuFlags::ZERO, value == 0);
         self.set_flag(CpuFlags::NEGATIVE, (value & 0x80) != 0);
@@ -124,25 +233,83 @@ impl<M: Memory> CPU<M> {
         ((msb as u16) << 8) | lsb as u16
     }
 
+    /// Every CPU read goes through here, it is where DMA halts the CPU.
+    fn read(&mut self, address: u16) -> u8 {
+        while let Some(dma) = self.bus.take_dma() {
+            self.dma_cycles += self.run_dma(dma, address);
+        }
+        self.bus.read(address)
+    }
+
+    /// Performs `dma` with the CPU halted on a read of `address` and returns
+    /// the cycles it took. The halt cycle, the dummy cycle of the DMC and the
+    /// alignment cycle repeat that read. Devices see the repeats as a single
+    /// extra read, which is what drops controller bits and skips PPUDATA bytes
+    /// when a DMC fetch lands on a read of $4016 or $2007.
+    ///
+    /// `tick` knows the cycle of every read. `step` aligns as if the read was
+    /// the first of the instruction, which is exact for the requests `Bus`
+    /// raises, as they are all seen by the next opcode fetch.
+    fn run_dma(&mut self, dma: Dma, address: u16) -> u64 {
+        let start = self.cycles + self.dma_cycles;
+        self.bus.read(address);
+        let mut cycle = start + 1;
+        if let Dma::Dmc(_) = dma {
+            cycle += 1;
+        }
+        // DMA reads happen on even cycles, writes on odd ones
+        if cycle % 2 == 1 {
+            cycle += 1;
+        }
+        self.bus.skip_cycles(cycle - start - 1);
+        match dma {
+            Dma::Oam(page) => {
+                for low in 0..=0xFF {
+                    let value = self.bus.read(Self::get_address(low, page));
+                    self.bus.write(OAM_DATA, value);
+                }
+                cycle += 512;
+            }
+            Dma::Dmc(sample) => {
+                let value = self.bus.read(sample);
+                self.bus.dmc_sample(value);
+                cycle += 1;
+            }
+        }
+        cycle - start
+    }
+
+    #[cfg(test)]
     fn peek_stack(&self) -> u8 {
-        self.bus.read(self.sp as u16 + 0x0100u16 + 1)
+        self.bus.peek(self.sp as u16 + 0x0100u16 + 1)
     }
 
     fn push_stack(&mut self, value: u8) {
         self.bus.write(self.sp as u16 + 0x0100u16, value);
-        self.sp -= 1;
+        self.sp = self.sp.wrapping_sub(1);
     }
 
     fn pull_stack(&mut self) -> u8 {
-        self.sp += 1;
-        let val = self.bus.read(self.sp as u16 + 0x0100u16);
-        val
+        self.sp = self.sp.wrapping_add(1);
+        self.read(self.sp as u16 + 0x0100u16)
+    }
+
+    fn push_stack_u16(&mut self, value: u16) {
+        let [lsb, msb] = value.to_le_bytes();
+        self.push_stack(msb);
+        self.push_stack(lsb);
+    }
+
+    fn pull_stack_u16(&mut self) -> u16 {
+        let lsb = self.pull_stack();
+        let msb = self.pull_stack();
+        Self::get_address(lsb, msb)
     }
 
     fn addr_absolute(&mut self) -> u16 {
-        let lsb = self.bus.read(self.pc);
+        let lsb = self.read(self.pc);
         self.inc_pc();
-        let msb = self.bus.read(self.pc);
+        let msb = self.read(self.pc);
         self.inc_pc();
         Self::get_address(lsb, msb)
     }
@@ -174,61 +341,63 @@ impl<M: Memory> CPU<M> {
         )
     }
 
-    fn addr_absolute_indirect(&mut self) {
+    fn addr_absolute_indirect(&mut self) -> u16 {
         let base_addr = self.addr_absolute();
-        let lsb = self.bus.read(base_addr);
+        let lsb = self.read(base_addr);
         // When the inc crosses a page boundary we don't add 1
         let msb = if (base_addr & 0x00FF) == 0x00FF {
-            self.bus.read(base_addr & 0xFF00)
+            self.read(base_addr & 0xFF00)
         } else {
-            self.bus.read(base_addr.wrapping_add(1))
+            self.read(base_addr.wrapping_add(1))
         };
-        self.pc = Self::get_address(lsb, msb);
+        Self::get_address(lsb, msb)
     }
 
     fn addr_zero_page(&mut self) -> u16 {
-        let lsb = self.bus.read(self.pc);
+        let lsb = self.read(self.pc);
         self.inc_pc();
         Self::get_address(lsb, 0x00)
     }
 
     fn addr_zero_page_x(&mut self) -> u16 {
-        let lsb = self.bus.read(self.pc);
+        let lsb = self.read(self.pc);
         self.inc_pc();
 
         lsb.wrapping_add(self.x) as u16
     }
 
     fn addr_zero_page_y(&mut self) -> u16 {
-        let lsb = self.bus.read(self.pc);
+        let lsb = self.read(self.pc);
         self.inc_pc();
 
         lsb.wrapping_add(self.y) as u16
     }
 
     fn addr_zero_page_x_indirect(&mut self) -> u16 {
-        let zp = self.bus.read(self.pc);
+        let zp = self.read(self.pc);
         self.inc_pc();
 
+        // The pointer is read unindexed while X is added
+        self.bus.skip_cycles(1);
         let ptr = zp.wrapping_add(self.x) as u16;
 
-        let lsb = self.bus.read(ptr);
-        let msb = self.bus.read((ptr.wrapping_add(1) & 0x00FF) as u16);
+        let lsb = self.read(ptr);
+        let msb = self.read(ptr.wrapping_add(1) & 0x00FF);
 
         Self::get_address(lsb, msb)
     }
 
     fn addr_zero_page_y_indirect(&mut self) -> (u16, u64) {
-        let zp_addr = self.bus.read(self.pc) as u16;
+        let zp_addr = self.read(self.pc) as u16;
         self.inc_pc();
 
-        let lsb = self.bus.read(zp_addr);
-        let msb = self.bus.read((zp_addr.wrapping_add(1) & 0x00FF) as u16);
+        let lsb = self.read(zp_addr);
+        let msb = self.read(zp_addr.wrapping_add(1) & 0x00FF);
 
         let base_addr = Self::get_address(lsb, msb);
 
         let (new_lsb, overflow) = self.y.overflowing_add(lsb);
-        let new_msb = msb.wrapping_add(if overflow == true { 1 } else { 0 });
+        let new_msb = msb.wrapping_add(overflow as u8);
 
         let effective_addr = u16::from_le_bytes([new_lsb, new_msb]);
 
@@ -238,11 +407,9 @@ impl<M: Memory> CPU<M> {
     }
 
     fn addr_relative(&mut self) -> (u16, u64) {
-        let offset: i8 = self.bus.read(self.pc) as i8;
+        let offset: i8 = self.read(self.pc) as i8;
         self.inc_pc();
 
-        println!("Offset: {offset}");
-
         let base_addr = self.pc;
 
         let effective_addr = base_addr.wrapping_add_signed(offset as i16);
@@ -253,363 +420,640 @@ impl<M: Memory> CPU<M> {
         (effective_addr, page_crossed)
     }
 
+    fn read_vector(&mut self, vector: u16) -> u16 {
+        let lsb = self.read(vector);
+        let msb = self.read(vector.wrapping_add(1));
+        Self::get_address(lsb, msb)
+    }
+
+    /// Push PC and status (B clear) and jump through `vector`.
+    fn interrupt(&mut self, vector: u16) -> u64 {
+        self.bus.begin_interrupt(self.pc, self.cycles);
+        // Two dummy reads of PC replace the opcode and operand fetches
+        self.bus.skip_cycles(2);
+        self.push_stack_u16(self.pc);
+        self.push_stack(
+            (self.sr | CpuFlags::UNUSED)
+                .difference(CpuFlags::BREAK)
+                .bits(),
+        );
+        self.set_flag(CpuFlags::INTERRUPT_DISABLE, true);
+        self.irq_inhibit = true;
+        self.pc = self.read_vector(vector);
+        7
+    }
+
+    /// Executes a single instruction, or services a pending interrupt instead.
+    /// Returns the number of cycles taken.
+    ///
+    /// The instruction runs atomically with only its real bus accesses, the
+    /// bus is told about the cycles of the dummy ones with
+    /// `Memory::skip_cycles`. Use `tick` when the exact per-cycle bus activity
+    /// matters. An instruction left
+    /// half way by `tick` is completed cycle by cycle.
+    ///
+    /// Cycles spent halted for DMA are included, see `Memory::take_dma`.
+    ///
+    /// An unofficial opcode rejected under `IllegalOpcodePolicy::Error` does
+    /// not execute, takes no cycles and jams the CPU until reset. Bus faults
+    /// are ignored, the bus has already answered with a default value. Use
+    /// `try_step` to see both.
     pub fn step(&mut self) -> u64 {
-        let opcode = self.bus.read(self.pc);
+        let cycles = self.run_step();
+        // Nobody hears about the opcode, so stop rather than retry it forever
+        if let Some(EmuError::IllegalOpcode { .. }) = self.fault.take() {
+            self.jammed = true;
+        }
+        self.bus.take_fault();
+        cycles
+    }
+
+    /// Fallible `step`. A jammed CPU and a rejected unofficial opcode are
+    /// reported before anything executes. Faults the bus raised while the
+    /// instruction ran are reported after it completed.
+    pub fn try_step(&mut self) -> Result<u64, EmuError> {
+        if self.jammed {
+            return Err(EmuError::CpuJammed { pc: self.pc });
+        }
+        let cycles = self.run_step();
+        match self.fault.take().or_else(|| self.bus.take_fault()) {
+            Some(error) => Err(error),
+            None => Ok(cycles),
+        }
+    }
+
+    fn run_step(&mut self) -> u64 {
+        if self.pipeline.in_progress() {
+            let start = self.cycles;
+            while self.pipeline.in_progress() {
+                self.run_tick();
+            }
+            return self.cycles - start;
+        }
+
+        let cycles = if self.jammed {
+            // A jammed CPU ignores interrupts and makes no progress
+            1
+        } else if self.nmi_pending {
+            self.nmi_pending = false;
+            self.interrupt(NMI_VECTOR)
+        } else if self.irq_line && !self.irq_inhibit {
+            self.interrupt(IRQ_VECTOR)
+        } else {
+            self.execute()
+        } + std::mem::take(&mut self.dma_cycles);
+        self.cycles += cycles;
+        cycles
+    }
+
+    fn execute(&mut self) -> u64 {
+        self.bus.begin_instruction(self.pc, self.cycles);
+        let opcode = self.read(self.pc);
+        if let Err(error) = self.check_illegal_opcode(opcode, self.pc) {
+            // Rejected opcodes do not execute and leave PC on the opcode
+            self.fault = Some(error);
+            return 0;
+        }
         self.inc_pc();
-        let mut cycles = 0;
+        let op = OPCODES[opcode as usize];
+        let interrupt_disable = self.get_flag(CpuFlags::INTERRUPT_DISABLE);
 
-        match opcode {
-            0xA9 => {
-                // LDA #$nn (LDA Immediate)
-                let value = self.bus.read(self.pc);
-                self.inc_pc();
-                cycles = 2;
-                self.ac = value;
-                self.set_zero_and_negative_flag(self.ac);
+        let (operand, page_crossed) = if op.mnemonic == Mnemonic::JSR {
+            // JSR reads the high byte of its target last, see `run`
+            (Operand::Implied, 0)
+        } else {
+            self.resolve_operand(op.mode)
+        };
+        self.bus
+            .skip_cycles(Self::dummy_cycles(op.mnemonic, op.mode, page_crossed));
+        let branch_cycles = self.run(op.mnemonic, operand);
+        self.update_irq_inhibit(op.mnemonic, interrupt_disable);
+
+        let penalty = if op.page_cross_penalty {
+            page_crossed
+        } else {
+            0
+        };
+        op.cycles as u64 + penalty + branch_cycles
+    }
+
+    /// The cycles between the operand and the first access of the operation
+    /// that only make dummy accesses.
+    fn dummy_cycles(mnemonic: Mnemonic, mode: AddressingMode, page_crossed: u64) -> u64 {
+        match mode {
+            AddressingMode::Implied | AddressingMode::Accumulator => match mnemonic {
+                Mnemonic::PLA | Mnemonic::PLP | Mnemonic::RTS | Mnemonic::RTI => 2,
+                _ => 1,
+            },
+            AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => 1,
+            // Writes always take the cycle to fix up the page
+            AddressingMode::AbsoluteX | AddressingMode::AbsoluteY | AddressingMode::IndirectY
+                if page_crossed != 0 || Access::of(mnemonic) != Access::Read =>
+            {
+                1
             }
-            0xAD => {
-                // LDA #&nnnn (LDA Absolute addressing)
-                let address: u16 = self.addr_absolute();
-                let value = self.bus.read(address);
-                cycles = 4;
-                self.ac = value;
-                self.set_zero_and_negative_flag(value);
+            _ => 0,
+        }
+    }
+
+    fn check_illegal_opcode(&self, opcode: u8, pc: u16) -> Result<(), EmuError> {
+        if OPCODES[opcode as usize].official {
+            return Ok(());
+        }
+        match self.illegal_opcode_policy {
+            IllegalOpcodePolicy::Emulate => Ok(()),
+            IllegalOpcodePolicy::LogAndContinue => {
+                log!("Unofficial opcode ${opcode:02X} at ${pc:04X}");
+                Ok(())
+            }
+            IllegalOpcodePolicy::Error => Err(EmuError::IllegalOpcode { opcode, pc }),
+        }
+    }
+
+    /// Latch the I flag seen by the interrupt poll at the end of an instruction.
+    fn update_irq_inhibit(&mut self, mnemonic: Mnemonic, interrupt_disable_before: bool) {
+        self.irq_inhibit = match mnemonic {
+            Mnemonic::CLI | Mnemonic::SEI | Mnemonic::PLP => interrupt_disable_before,
+            _ => self.get_flag(CpuFlags::INTERRUPT_DISABLE),
+        };
+    }
+
+    /// Consumes the operand bytes of an instruction and computes its effective
+    /// address, along with the page cross penalty of indexed modes.
+    fn resolve_operand(&mut self, mode: AddressingMode) -> (Operand, u64) {
+        match mode {
+            AddressingMode::Implied => (Operand::Implied, 0),
+            AddressingMode::Accumulator => (Operand::Accumulator, 0),
+            AddressingMode::Relative => (Operand::Relative, 0),
+            AddressingMode::Immediate => {
+                let address = self.pc;
+                self.inc_pc();
+                (Operand::Address(address), 0)
             }
-            0xBD => {
-                // LDA $nnnn,X
-                // absolute x
+            AddressingMode::ZeroPage => (Operand::Address(self.addr_zero_page()), 0),
+            AddressingMode::ZeroPageX => (Operand::Address(self.addr_zero_page_x()), 0),
+            AddressingMode::ZeroPageY => (Operand::Address(self.addr_zero_page_y()), 0),
+            AddressingMode::Absolute => (Operand::Address(self.addr_absolute()), 0),
+            AddressingMode::AbsoluteX => {
                 let (address, p) = self.addr_absolute_x();
-                cycles += 4 + p;
-                let value = self.bus.read(address);
-                self.ac = value;
-                self.set_zero_and_negative_flag(value);
+                (Operand::Address(address), p)
             }
-            0xB9 => {
-                // absolute y
+            AddressingMode::AbsoluteY => {
                 let (address, p) = self.addr_absolute_y();
-                cycles += 4 + p;
-                let value = self.bus.read(address);
-                self.ac = value;
-                self.set_zero_and_negative_flag(value);
+                (Operand::Address(address), p)
             }
-            0xA5 => {
-                // zero-page
-                let address = self.addr_zero_page();
-                cycles = 3;
-                let value = self.bus.read(address);
-                self.ac = value;
-                self.set_zero_and_negative_flag(value);
-            }
-            0xB5 => {
-                // x-indexed zero page
-                let address = self.addr_zero_page_x();
-                cycles = 4;
-                let value = self.bus.read(address);
-                self.ac = value;
-                self.set_zero_and_negative_flag(value);
+            AddressingMode::Indirect => (Operand::Address(self.addr_absolute_indirect()), 0),
+            AddressingMode::IndirectX => (Operand::Address(self.addr_zero_page_x_indirect()), 0),
+            AddressingMode::IndirectY => {
+                let (address, p) = self.addr_zero_page_y_indirect();
+                (Operand::Address(address), p)
             }
+        }
+    }
 
-            0xA1 => {
-                // x-indexed zero page indirect
-                let address = self.addr_zero_page_x_indirect();
-                cycles = 6;
-                let value = self.bus.read(address);
-                self.ac = value;
-                self.set_zero_and_negative_flag(value);
+    fn read_operand(&mut self, operand: Operand) -> u8 {
+        match operand {
+            Operand::Accumulator => self.ac,
+            Operand::Address(address) => self.read(address),
+            Operand::Latched(_, value) => value,
+            Operand::Implied | Operand::Relative => unreachable!("no operand to read"),
+        }
+    }
+
+    fn write_operand(&mut self, operand: Operand, value: u8) {
+        match operand {
+            Operand::Accumulator => self.ac = value,
+            Operand::Address(address) | Operand::Latched(address, _) => {
+                self.bus.write(address, value)
             }
-            0xB1 => {
-                let (address, p) = self.addr_zero_page_y_indirect();
-                cycles = 5 + p;
-                self.ac = self.bus.read(address);
+            Operand::Implied | Operand::Relative => unreachable!("no operand to write"),
+        }
+    }
+
+    /// Read-modify-write, returns the value written back.
+    fn modify(&mut self, operand: Operand, f: fn(&mut Self, u8) -> u8) -> u8 {
+        let value = self.read_operand(operand);
+        if let Operand::Address(_) = operand {
+            // `step` leaves out the write of the unmodified value, `tick`
+            // latches the operand after making it
+            self.bus.skip_cycles(1);
+        }
+        let new_value = f(self, value);
+        self.write_operand(operand, new_value);
+        new_value
+    }
+
+    fn operand_address(operand: Operand) -> u16 {
+        match operand {
+            Operand::Address(address) | Operand::Latched(address, _) => address,
+            _ => unreachable!("instruction requires a memory operand"),
+        }
+    }
+
+    /// Executes `mnemonic` against an already resolved operand. Returns the
+    /// extra cycles taken by branches, every other cost comes from the opcode table.
+    fn run(&mut self, mnemonic: Mnemonic, operand: Operand) -> u64 {
+        use Mnemonic::*;
+
+        match mnemonic {
+            // Loads
+            LDA => {
+                self.ac = self.read_operand(operand);
                 self.set_zero_and_negative_flag(self.ac);
             }
-            // LDX
-            0xA2 => {
-                // immediate
-                let value = self.bus.read(self.pc);
-                self.inc_pc();
-                cycles = 2;
-                self.x = value;
+            LDX => {
+                self.x = self.read_operand(operand);
                 self.set_zero_and_negative_flag(self.x);
             }
-            0xAE => {
-                // absolute
-                let address: u16 = self.addr_absolute();
-                let value = self.bus.read(address);
-                cycles = 4;
-                self.x = value;
-                self.set_zero_and_negative_flag(value);
+            LDY => {
+                self.y = self.read_operand(operand);
+                self.set_zero_and_negative_flag(self.y);
             }
-            0xBE => {
-                // absolute indexed y
-                let (address, p) = self.addr_absolute_y();
-                cycles += 4 + p;
-                let value = self.bus.read(address);
-                self.x = value;
-                self.set_zero_and_negative_flag(value);
+            // Stores
+            STA => self.write_operand(operand, self.ac),
+            STX => self.write_operand(operand, self.x),
+            STY => self.write_operand(operand, self.y),
+            // Transfers
+            TAX => {
+                self.x = self.ac;
+                self.set_zero_and_negative_flag(self.x);
             }
-            0xA6 => {
-                // zero page
-                let address = self.addr_zero_page();
-                cycles = 3;
-                let value = self.bus.read(address);
-                self.x = value;
-                self.set_zero_and_negative_flag(value);
+            TAY => {
+                self.y = self.ac;
+                self.set_zero_and_negative_flag(self.y);
             }
-            0xB6 => {
-                // zero page indexed y
-                let address = self.addr_zero_page_y();
-                cycles = 4;
-                let value = self.bus.read(address);
-                self.x = value;
-                self.set_zero_and_negative_flag(value);
+            TSX => {
+                self.x = self.sp;
+                self.set_zero_and_negative_flag(self.x);
             }
-            // LDY (M -> Y)
-            0xA0 => {
-                // immediate
-                let value = self.bus.read(self.pc);
-                self.inc_pc();
-                cycles = 2;
-                self.y = value;
-                self.set_zero_and_negative_flag(self.y);
+            TXA => {
+                self.ac = self.x;
+                self.set_zero_and_negative_flag(self.ac);
             }
-            0xAC => {
-                // absolute
-                let address: u16 = self.addr_absolute();
-                let value = self.bus.read(address);
-                cycles = 4;
-                self.y = value;
-                self.set_zero_and_negative_flag(value);
+            TXS => self.sp = self.x,
+            TYA => {
+                self.ac = self.y;
+                self.set_zero_and_negative_flag(self.ac);
             }
-            0xBC => {
-                // absolute indexed y
-                let (address, p) = self.addr_absolute_x();
-                cycles += 4 + p;
-                let value = self.bus.read(address);
-                self.y = value;
-                self.set_zero_and_negative_flag(value);
+            // Stack
+            PHA => self.push_stack(self.ac),
+            // The B flag only exists on the stack copy, it is always set by PHP.
+            PHP => self.push_stack((self.sr | CpuFlags::BREAK | CpuFlags::UNUSED).bits()),
+            PLA => {
+                self.ac = self.pull_stack();
+                self.set_zero_and_negative_flag(self.ac);
             }
-            0xA4 => {
-                // zero page
-                let address = self.addr_zero_page();
-                cycles = 3;
-                let value = self.bus.read(address);
-                self.y = value;
-                self.set_zero_and_negative_flag(value);
+            PLP => {
+                let value = self.pull_stack();
+                self.set_status_from_stack(value);
             }
-            0xB4 => {
-                // x indexed zero page
-                let address = self.addr_zero_page_x();
-                cycles = 4;
-                let value = self.bus.read(address);
-                self.y = value;
-                self.set_zero_and_negative_flag(value);
+            // Shifts and rotates
+            ASL => {
+                self.modify(operand, |cpu, value| {
+                    let (new_value, carry) = Self::asl(value);
+                    cpu.set_flag(CpuFlags::CARRY, carry);
+                    cpu.set_zero_and_negative_flag(new_value);
+                    new_value
+                });
             }
-            // STA - A -> M
-            0x8D => {
-                let address = self.addr_absolute();
-                cycles = 4;
-                self.bus.write(address, self.ac);
-            } // absolute
-            0x9D => {
-                let (address, _) = self.addr_absolute_x();
-                cycles = 5;
-                self.bus.write(address, self.ac);
-            } // absolute-x
-            0x99 => {
-                let (address, _) = self.addr_absolute_y();
-                cycles = 5;
-                self.bus.write(address, self.ac);
-            } // absolute-y
-            0x85 => {
-                let address = self.addr_zero_page();
-                cycles = 3;
-                self.bus.write(address, self.ac);
-            } // zero page
-            0x95 => {
-                let address = self.addr_zero_page_x();
-                cycles = 4;
-                self.bus.write(address, self.ac);
-            } // x-indexed zero page
-            0x81 => {
-                let address = self.addr_zero_page_x_indirect();
-                cycles = 6;
-                self.bus.write(address, self.ac);
-            } // y-indexed zero page
-            0x91 => {
-                let (address, _) = self.addr_zero_page_y_indirect();
-                cycles = 6;
-                self.bus.write(address, self.ac);
-            } // zero page indirect y-indexed
-            // STX: X -> M
-            0x8E => {
-                let address = self.addr_absolute();
-                cycles = 3;
-                self.bus.write(address, self.x);
-            } // absolute
-            0x86 => {
-                let address = self.addr_zero_page();
-                cycles = 2;
-                self.bus.write(address, self.x);
-            } // zero page
-            0x96 => {
-                let address = self.addr_zero_page_y();
-                cycles = 2;
-                self.bus.write(address, self.x);
-            } // y-indexed zero page
-            // STY: Y -> M
-            0x8C => {
-                let address = self.addr_absolute();
-                cycles = 4;
-                self.bus.write(address, self.y);
-            } // absolute
-            0x84 => {
-                let address = self.addr_zero_page();
-                cycles = 3;
-                self.bus.write(address, self.y);
-            } // zero page
-            0x94 => {
-                let address = self.addr_zero_page_x();
-                cycles = 4;
-                self.bus.write(address, self.y);
-            } // x-indexed zero page
-            // TAX: A -> X
-            0xAA => {
-                self.x = self.ac;
+            LSR => {
+                self.modify(operand, |cpu, value| {
+                    let (new_value, carry) = Self::lsr(value);
+                    cpu.set_flag(CpuFlags::CARRY, carry);
+                    cpu.set_zero_and_negative_flag(new_value);
+                    new_value
+                });
+            }
+            ROL => {
+                self.modify(operand, Self::rol);
+            }
+            ROR => {
+                self.modify(operand, Self::ror);
+            }
+            // Arithmetic and logic
+            ADC => {
+                let value = self.read_operand(operand);
+                self.adc(value);
+            }
+            SBC => {
+                let value = self.read_operand(operand);
+                self.sbc(value);
+            }
+            AND => {
+                self.ac &= self.read_operand(operand);
+                self.set_zero_and_negative_flag(self.ac);
+            }
+            ORA => {
+                self.ac |= self.read_operand(operand);
+                self.set_zero_and_negative_flag(self.ac);
+            }
+            EOR => {
+                self.ac ^= self.read_operand(operand);
+                self.set_zero_and_negative_flag(self.ac);
+            }
+            CMP => {
+                let value = self.read_operand(operand);
+                self.compare(self.ac, value);
+            }
+            CPX => {
+                let value = self.read_operand(operand);
+                self.compare(self.x, value);
+            }
+            CPY => {
+                let value = self.read_operand(operand);
+                self.compare(self.y, value);
+            }
+            BIT => {
+                let value = self.read_operand(operand);
+                self.bit(value);
+            }
+            // Increments and decrements
+            INC => {
+                self.modify(operand, |cpu, value| {
+                    let new_value = value.wrapping_add(1);
+                    cpu.set_zero_and_negative_flag(new_value);
+                    new_value
+                });
+            }
+            DEC => {
+                self.modify(operand, |cpu, value| {
+                    let new_value = value.wrapping_sub(1);
+                    cpu.set_zero_and_negative_flag(new_value);
+                    new_value
+                });
+            }
+            INX => {
+                self.x = self.x.wrapping_add(1);
                 self.set_zero_and_negative_flag(self.x);
             }
-            // TAY: A -> Y
-            0xA8 => {
-                self.y = self.ac;
+            INY => {
+                self.y = self.y.wrapping_add(1);
                 self.set_zero_and_negative_flag(self.y);
             }
-            // TSX: S -> X
-            0xBA => {
-                self.x = self.sp;
+            DEX => {
+                self.x = self.x.wrapping_sub(1);
                 self.set_zero_and_negative_flag(self.x);
             }
-            // TXA: X -> A
-            0x8A => {
-                self.ac = self.x;
-                self.set_zero_and_negative_flag(self.ac);
+            DEY => {
+                self.y = self.y.wrapping_sub(1);
+                self.set_zero_and_negative_flag(self.y);
             }
-            // TXS: X -> S
-            0x9A => {
-                self.sp = self.x;
+            // Branches: +1 cycle if taken, +1 more if the target is on another page
+            BPL | BMI | BVC | BVS | BCC | BCS | BNE | BEQ => {
+                return self.branch(self.branch_condition(mnemonic));
             }
-            // TYA: Y -> A
-            0x98 => {
-                self.ac = self.y;
+            // Jumps and subroutines
+            JMP => self.pc = Self::operand_address(operand),
+            // JSR pushes the address of the last byte of the instruction,
+            // before reading it
+            JSR => {
+                let lsb = self.read(self.pc);
+                self.inc_pc();
+                self.bus.skip_cycles(1);
+                self.push_stack_u16(self.pc);
+                let msb = self.read(self.pc);
+                self.pc = Self::get_address(lsb, msb);
+            }
+            RTS => self.pc = self.pull_stack_u16().wrapping_add(1),
+            RTI => {
+                let value = self.pull_stack();
+                self.set_status_from_stack(value);
+                self.pc = self.pull_stack_u16();
+            }
+            // BRK: the byte after the opcode is padding and is skipped on return
+            BRK => {
+                self.inc_pc();
+                self.push_stack_u16(self.pc);
+                self.push_stack((self.sr | CpuFlags::BREAK | CpuFlags::UNUSED).bits());
+                self.set_flag(CpuFlags::INTERRUPT_DISABLE, true);
+                // An NMI arriving during BRK hijacks its vector fetch
+                let vector = if self.nmi_pending {
+                    self.nmi_pending = false;
+                    NMI_VECTOR
+                } else {
+                    IRQ_VECTOR
+                };
+                self.pc = self.read_vector(vector);
+            }
+            // Flags
+            CLC => self.set_flag(CpuFlags::CARRY, false),
+            SEC => self.set_flag(CpuFlags::CARRY, true),
+            CLI => self.set_flag(CpuFlags::INTERRUPT_DISABLE, false),
+            SEI => self.set_flag(CpuFlags::INTERRUPT_DISABLE, true),
+            CLV => self.set_flag(CpuFlags::OVERFLOW, false),
+            CLD => self.set_flag(CpuFlags::DECIMAL_MODE, false),
+            SED => self.set_flag(CpuFlags::DECIMAL_MODE, true),
+            // The unofficial variants still read their operand
+            NOP => {
+                if let Operand::Address(address) = operand {
+                    self.read(address);
+                }
+            }
+
+            // Unofficial
+            LAX => {
+                let value = self.read_operand(operand);
+                self.ac = value;
+                self.x = value;
+                self.set_zero_and_negative_flag(value);
+            }
+            SAX => self.write_operand(operand, self.ac & self.x),
+            // DCP: M - 1 -> M, A - M
+            DCP => {
+                let new_value = self.modify(operand, |_, value| value.wrapping_sub(1));
+                self.compare(self.ac, new_value);
+            }
+            // ISC: M + 1 -> M, A - M - ~C -> A
+            ISC => {
+                let new_value = self.modify(operand, |_, value| value.wrapping_add(1));
+                self.sbc(new_value);
+            }
+            // SLO: M << 1 -> M, A | M -> A
+            SLO => {
+                let new_value = self.modify(operand, |cpu, value| {
+                    let (new_value, carry) = Self::asl(value);
+                    cpu.set_flag(CpuFlags::CARRY, carry);
+                    new_value
+                });
+                self.ac |= new_value;
                 self.set_zero_and_negative_flag(self.ac);
             }
-            // PHA: A -> Stack
-            0x48 => {
-                self.push_stack(self.ac);
+            // RLA: ROL M, A & M -> A
+            RLA => {
+                let new_value = self.modify(operand, Self::rol);
+                self.ac &= new_value;
+                self.set_zero_and_negative_flag(self.ac);
             }
-            // PHP: S -> Stack
-            0x08 => self.push_stack(self.sr.bits()),
-            // PLA: Stack[SP+1] -> A
-            0x68 => {
-                let val = self.pull_stack();
-                self.ac = val;
+            // SRE: M >> 1 -> M, A ^ M -> A
+            SRE => {
+                let new_value = self.modify(operand, |cpu, value| {
+                    let (new_value, carry) = Self::lsr(value);
+                    cpu.set_flag(CpuFlags::CARRY, carry);
+                    new_value
+                });
+                self.ac ^= new_value;
                 self.set_zero_and_negative_flag(self.ac);
             }
-            // PLP: Stack[SP+1] -> SR
-            0x28 => {
-                self.sr = CpuFlags::from_bits_truncate(self.pull_stack());
+            // RRA: ROR M, A + M + C -> A
+            RRA => {
+                let new_value = self.modify(operand, Self::ror);
+                self.adc(new_value);
             }
-            // ASL A: C <- M7..M0 <- 0
-            0x0A => {
-                let value = self.ac;
-                let (new_value, carry_flag) = Self::asl(value);
+            // ANC: A & M -> A, N -> C
+            ANC => {
+                self.ac &= self.read_operand(operand);
+                self.set_zero_and_negative_flag(self.ac);
+                self.set_flag(CpuFlags::CARRY, self.ac & 0x80 != 0);
+            }
+            // ALR: (A & M) >> 1 -> A
+            ALR => {
+                let value = self.read_operand(operand);
+                let (new_value, carry) = Self::lsr(self.ac & value);
                 self.ac = new_value;
-                self.set_flag(CpuFlags::CARRY, carry_flag);
+                self.set_flag(CpuFlags::CARRY, carry);
                 self.set_zero_and_negative_flag(new_value);
             }
-            // ASL $nnnn: C <- M7..M0 <- 0
-            0x0E => {
-                let address = self.addr_absolute();
-                let value = self.bus.read(address);
-                let (new_value, carry_flag) = Self::asl(value);
-                self.bus.write(address, new_value);
-                self.set_flag(CpuFlags::CARRY, carry_flag);
-                self.set_zero_and_negative_flag(new_value);
+            // ARR: (A & M) ROR 1 -> A, bit 6 -> C, bit 6 ^ bit 5 -> V
+            ARR => {
+                let value = self.read_operand(operand);
+                let carry_in = (self.get_flag(CpuFlags::CARRY) as u8) << 7;
+                self.ac = ((self.ac & value) >> 1) | carry_in;
+                self.set_zero_and_negative_flag(self.ac);
+                self.set_flag(CpuFlags::CARRY, self.ac & 0x40 != 0);
+                self.set_flag(
+                    CpuFlags::OVERFLOW,
+                    ((self.ac >> 6) ^ (self.ac >> 5)) & 0x01 != 0,
+                );
             }
-            // ASL $nnnn, X
-            0x1E => {
-                let (address, _) = self.addr_absolute_x();
-                let value = self.bus.read(address);
-                let (new_value, carry_flag) = Self::asl(value);
-                self.bus.write(address, new_value);
-                self.set_flag(CpuFlags::CARRY, carry_flag);
-                self.set_zero_and_negative_flag(new_value);
+            // AXS: (A & X) - M -> X, without borrow in
+            AXS => {
+                let value = self.read_operand(operand);
+                let and = self.ac & self.x;
+                self.x = and.wrapping_sub(value);
+                self.set_flag(CpuFlags::CARRY, and >= value);
+                self.set_zero_and_negative_flag(self.x);
             }
-            // ASL $nn
-            0x06 => {
-                let address = self.addr_zero_page();
-                let value = self.bus.read(address);
-                let (new_value, carry_flag) = Self::asl(value);
-                self.bus.write(address, new_value);
-                self.set_flag(CpuFlags::CARRY, carry_flag);
-                self.set_zero_and_negative_flag(new_value);
+            // ANE: (A | magic) & X & M -> A, unstable
+            ANE => {
+                let value = self.read_operand(operand);
+                self.ac = (self.ac | UNSTABLE_MAGIC) & self.x & value;
+                self.set_zero_and_negative_flag(self.ac);
             }
-            // ASL $nn, X
-            0x16 => {
-                let address = self.addr_zero_page_x();
-                let value = self.bus.read(address);
-                let (new_value, carry_flag) = Self::asl(value);
-                self.bus.write(address, new_value);
-                self.set_flag(CpuFlags::CARRY, carry_flag);
-                self.set_zero_and_negative_flag(new_value);
+            // LXA: (A | magic) & M -> A, X, unstable
+            LXA => {
+                let value = self.read_operand(operand);
+                self.ac = (self.ac | UNSTABLE_MAGIC) & value;
+                self.x = self.ac;
+                self.set_zero_and_negative_flag(self.ac);
+            }
+            // SHA: A & X & (H + 1) -> M
+            SHA => self.store_and_high(operand, self.y, self.ac & self.x),
+            // SHX: X & (H + 1) -> M
+            SHX => self.store_and_high(operand, self.y, self.x),
+            // SHY: Y & (H + 1) -> M
+            SHY => self.store_and_high(operand, self.x, self.y),
+            // TAS: A & X -> S, S & (H + 1) -> M
+            TAS => {
+                self.sp = self.ac & self.x;
+                self.store_and_high(operand, self.y, self.sp);
+            }
+            // LAS: M & S -> A, X, S
+            LAS => {
+                let value = self.read_operand(operand) & self.sp;
+                self.ac = value;
+                self.x = value;
+                self.sp = value;
+                self.set_zero_and_negative_flag(value);
+            }
+            // KIL: the CPU locks up until reset
+            KIL => {
+                self.jammed = true;
+                self.pc = self.pc.wrapping_sub(1);
             }
-            // LSR A
-            0x4A => {
-                let value = self.get_a();
-                let (new_value, carry) = Self::lsr(value);
-                self.set_a(new_value);
-                self.set_flag(CpuFlags::CARRY, carry);
-                self.set_zero_and_negative_flag(new_value);
-            } // accumulator
-            // LSR $nnnn
-            0x4E => {
-                let address = self.addr_absolute();
-                let value = self.bus.read(address);
-                let (new_value, carry) = Self::lsr(value);
-                self.bus.write(address, new_value);
-                self.set_flag(CpuFlags::CARRY, carry);
-                self.set_zero_and_negative_flag(new_value);
-            } // absolute
-            // LSR $nnnn, X
-            0x5E => {
-                let (address, _) = self.addr_absolute_x();
-                let value = self.bus.read(address);
-                let (new_value, carry) = Self::lsr(value);
-                self.bus.write(address, new_value);
-                self.set_flag(CpuFlags::CARRY, carry);
-                self.set_zero_and_negative_flag(new_value);
-            } // absolute x
-            // LSR $nn
-            0x46 => {
-                let address = self.addr_zero_page();
-                let value = self.bus.read(address);
-                let (new_value, carry) = Self::lsr(value);
-                self.bus.write(address, new_value);
-                self.set_flag(CpuFlags::CARRY, carry);
-                self.set_zero_and_negative_flag(new_value);
-            } // zero page
-            // LSR $nn, X
-            0x56 => {
-                let address = self.addr_zero_page_x();
-                let value = self.bus.read(address);
-                let (new_value, carry) = Self::lsr(value);
-                self.bus.write(address, new_value);
-                self.set_flag(CpuFlags::CARRY, carry);
-                self.set_zero_and_negative_flag(new_value);
-            } // zero page x-indexed
-            other => panic!("Invalid opcode: {other}"),
         }
 
-        cycles
+        0
+    }
+
+    /// SHA, SHX, SHY and TAS store `value & (H + 1)`, H being the high byte of
+    /// the base address. On a page cross the high byte of the target address is
+    /// replaced by the stored value.
+    fn store_and_high(&mut self, operand: Operand, index: u8, value: u8) {
+        let effective = Self::operand_address(operand);
+        let base = effective.wrapping_sub(index as u16);
+        let value = value & ((base >> 8) as u8).wrapping_add(1);
+        let address = if (base & 0xFF00) != (effective & 0xFF00) {
+            Self::get_address(effective as u8, value)
+        } else {
+            effective
+        };
+        self.bus.write(address, value);
+    }
+
+    /// The 2A03 has no decimal mode, so ADC is always binary regardless of the D flag.
+    fn adc(&mut self, value: u8) {
+        let carry_in = self.get_flag(CpuFlags::CARRY) as u16;
+        let sum = self.ac as u16 + value as u16 + carry_in;
+        let result = sum as u8;
+        // Overflow when both operands share a sign and the result does not
+        let overflow = (self.ac ^ result) & (value ^ result) & 0x80 != 0;
+        self.set_flag(CpuFlags::CARRY, sum > 0xFF);
+        self.set_flag(CpuFlags::OVERFLOW, overflow);
+        self.ac = result;
+        self.set_zero_and_negative_flag(result);
+    }
+
+    fn sbc(&mut self, value: u8) {
+        self.adc(!value);
+    }
+
+    fn compare(&mut self, register: u8, value: u8) {
+        self.set_flag(CpuFlags::CARRY, register >= value);
+        self.set_zero_and_negative_flag(register.wrapping_sub(value));
+    }
+
+    fn bit(&mut self, value: u8) {
+        self.set_flag(CpuFlags::ZERO, self.ac & value == 0);
+        self.set_flag(CpuFlags::OVERFLOW, value & 0x40 != 0);
+        self.set_flag(CpuFlags::NEGATIVE, value & 0x80 != 0);
+    }
+
+    fn rol(&mut self, value: u8) -> u8 {
+        let new_value = (value << 1) | self.get_flag(CpuFlags::CARRY) as u8;
+        self.set_flag(CpuFlags::CARRY, value & 0x80 != 0);
+        self.set_zero_and_negative_flag(new_value);
+        new_value
+    }
+
+    fn ror(&mut self, value: u8) -> u8 {
+        let new_value = (value >> 1) | ((self.get_flag(CpuFlags::CARRY) as u8) << 7);
+        self.set_flag(CpuFlags::CARRY, value & 0x01 != 0);
+        self.set_zero_and_negative_flag(new_value);
+        new_value
+    }
+
+    fn branch_condition(&self, mnemonic: Mnemonic) -> bool {
+        match mnemonic {
+            Mnemonic::BPL => !self.get_flag(CpuFlags::NEGATIVE),
+            Mnemonic::BMI => self.get_flag(CpuFlags::NEGATIVE),
+            Mnemonic::BVC => !self.get_flag(CpuFlags::OVERFLOW),
+            Mnemonic::BVS => self.get_flag(CpuFlags::OVERFLOW),
+            Mnemonic::BCC => !self.get_flag(CpuFlags::CARRY),
+            Mnemonic::BCS => self.get_flag(CpuFlags::CARRY),
+            Mnemonic::BNE => !self.get_flag(CpuFlags::ZERO),
+            Mnemonic::BEQ => self.get_flag(CpuFlags::ZERO),
+            _ => unreachable!("{mnemonic:?} is not a branch"),
+        }
+    }
+
+    /// Always consumes the offset operand, returns the extra cycles taken by the branch.
+    fn branch(&mut self, condition: bool) -> u64 {
+        if !condition {
+            self.inc_pc();
+            return 0;
+        }
+        let (_, page_crossed) = self.addr_relative();
+        1 + page_crossed
     }
 
     fn asl(value: u8) -> (u8, bool) {
@@ -639,16 +1083,16 @@ mod tests {
             MockBus { mem: [0; 0x10000] }
         }
         fn load(&mut self, addr: u16, bytes: &[u8]) {
-            let mut a = addr as usize;
-            for &b in bytes {
-                self.mem[a] = b;
-                a += 1;
-            }
+            let start = addr as usize;
+            self.mem[start..start + bytes.len()].copy_from_slice(bytes);
         }
     }
 
     impl Memory for MockBus {
-        fn read(&self, addr: u16) -> u8 {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.mem[addr as usize]
+        }
+        fn peek(&self, addr: u16) -> u8 {
             self.mem[addr as usize]
         }
         fn write(&mut self, addr: u16, value: u8) {
@@ -672,7 +1116,7 @@ mod tests {
         // N V - B D I Z C
         (
             (p & 0x80) != 0,
-            (p & 0x20) != 0,
+            (p & 0x40) != 0,
             (p & 0x10) != 0,
             (p & 0x08) != 0,
             (p & 0x04) != 0,
@@ -769,8 +1213,8 @@ mod tests {
         cpu.bus.write(0x1001, 0x12);
         cpu.bus.write(0x12FD, 0x21);
         cpu.bus.write(0x12FE, 0x23);
-        cpu.addr_absolute_indirect();
-        assert_eq!(cpu.get_pc(), 0x2321);
+        let addr = cpu.addr_absolute_indirect();
+        assert_eq!(addr, 0x2321);
     }
 
     #[test]
@@ -781,8 +1225,8 @@ mod tests {
         cpu.bus.write(0x1001, 0x12);
         cpu.bus.write(0x12FF, 0x21);
         cpu.bus.write(0x1200, 0x23);
-        cpu.addr_absolute_indirect();
-        assert_eq!(cpu.get_pc(), 0x2321);
+        let addr = cpu.addr_absolute_indirect();
+        assert_eq!(addr, 0x2321);
     }
 
     #[test]
@@ -1066,7 +1510,7 @@ mod tests {
         // PHP/PLP roundtrip
         let p0 = cpu.get_p();
         let _ = run_one(&mut cpu, &[0x08]); // PHP
-                                            // overwrite P intentionally, then pull it back
+        // overwrite P intentionally, then pull it back
         cpu.set_p(0);
         let _ = run_one(&mut cpu, &[0x28]); // PLP
         assert_eq!(cpu.get_p(), p0);
@@ -1084,9 +1528,9 @@ mod tests {
         assert_eq!(cpu.get_a(), 0x00);
         let (n, _v, _b, _d, _i, z, c) = flags(cpu.get_p());
         // 1000 0000 -> 0000 0000, C=1
-        assert_eq!(n, false);
-        assert_eq!(z, true);
-        assert_eq!(c, true);
+        assert!(!n);
+        assert!(z);
+        assert!(c);
     }
 
     #[test]
@@ -1096,9 +1540,9 @@ mod tests {
         let _ = run_one(&mut cpu, &[0x0E, 0x34, 0x12]);
         assert_eq!(cpu.bus.read(0x1234), 0x00);
         let (n, _v, _b, _d, _i, z, c) = flags(cpu.get_p());
-        assert_eq!(n, false);
-        assert_eq!(z, true);
-        assert_eq!(c, true);
+        assert!(!n);
+        assert!(z);
+        assert!(c);
     }
 
     #[test]
@@ -1109,9 +1553,9 @@ mod tests {
         let _ = run_one(&mut cpu, &[0x1E, 0x34, 0x12]);
         assert_eq!(cpu.bus.read(0x1235), 0x00);
         let (n, _v, _b, _d, _i, z, c) = flags(cpu.get_p());
-        assert_eq!(n, false);
-        assert_eq!(z, true);
-        assert_eq!(c, true);
+        assert!(!n);
+        assert!(z);
+        assert!(c);
     }
 
     #[test]
@@ -1121,9 +1565,9 @@ mod tests {
         let _ = run_one(&mut cpu, &[0x06, 0x02]);
         assert_eq!(cpu.bus.read(0x0002), 0x00);
         let (n, _v, _b, _d, _i, z, c) = flags(cpu.get_p());
-        assert_eq!(n, false);
-        assert_eq!(z, true);
-        assert_eq!(c, true);
+        assert!(!n);
+        assert!(z);
+        assert!(c);
     }
 
     #[test]
@@ -1134,9 +1578,9 @@ mod tests {
         let _ = run_one(&mut cpu, &[0x16, 0x02]);
         assert_eq!(cpu.bus.read(0x0003), 0x00);
         let (n, _v, _b, _d, _i, z, c) = flags(cpu.get_p());
-        assert_eq!(n, false);
-        assert_eq!(z, true);
-        assert_eq!(c, true);
+        assert!(!n);
+        assert!(z);
+        assert!(c);
     }
     // LSR TESTS
     #[test]
@@ -1147,9 +1591,9 @@ mod tests {
         let _ = run_one(&mut cpu, &[0x4A]);
         assert_eq!(cpu.get_a(), 0x40);
         let (n, _v, _b, _d, _i, z, c) = flags(cpu.get_p());
-        assert_eq!(n, false);
-        assert_eq!(z, false);
-        assert_eq!(c, false);
+        assert!(!n);
+        assert!(!z);
+        assert!(!c);
     }
 
     #[test]
@@ -1159,9 +1603,9 @@ mod tests {
         let _ = run_one(&mut cpu, &[0x4E, 0x34, 0x12]);
         assert_eq!(cpu.bus.read(0x1234), 0x40);
         let (n, _v, _b, _d, _i, z, c) = flags(cpu.get_p());
-        assert_eq!(n, false);
-        assert_eq!(z, false);
-        assert_eq!(c, false);
+        assert!(!n);
+        assert!(!z);
+        assert!(!c);
     }
     #[test]
     fn lsr_abs_x() {
@@ -1171,9 +1615,9 @@ mod tests {
         let _ = run_one(&mut cpu, &[0x5E, 0x34, 0x12]);
         assert_eq!(cpu.bus.read(0x1235), 0x40);
         let (n, _v, _b, _d, _i, z, c) = flags(cpu.get_p());
-        assert_eq!(n, false);
-        assert_eq!(z, false);
-        assert_eq!(c, false);
+        assert!(!n);
+        assert!(!z);
+        assert!(!c);
     }
 
     #[test]
@@ -1183,9 +1627,9 @@ mod tests {
         let _ = run_one(&mut cpu, &[0x46, 0x01]);
         assert_eq!(cpu.bus.read(0x0001), 0x40);
         let (n, _v, _b, _d, _i, z, c) = flags(cpu.get_p());
-        assert_eq!(n, false);
-        assert_eq!(z, false);
-        assert_eq!(c, false);
+        assert!(!n);
+        assert!(!z);
+        assert!(!c);
     }
 
     #[test]
@@ -1196,8 +1640,808 @@ mod tests {
         let _ = run_one(&mut cpu, &[0x56, 0x0FF]);
         assert_eq!(cpu.bus.read(0x0000), 0x40);
         let (n, _v, _b, _d, _i, z, c) = flags(cpu.get_p());
-        assert_eq!(n, false);
-        assert_eq!(z, false);
-        assert_eq!(c, false);
+        assert!(!n);
+        assert!(!z);
+        assert!(!c);
+    }
+
+    // ADC / SBC
+    #[test]
+    fn adc_sets_carry_and_overflow() {
+        let mut cpu = setup_cpu();
+        cpu.set_a(0x50);
+        let cycles = run_one(&mut cpu, &[0x69, 0x50]); // ADC #$50
+        assert_eq!(cpu.get_a(), 0xA0);
+        assert_eq!(cycles, 2);
+        let (n, v, _b, _d, _i, z, c) = flags(cpu.get_p());
+        assert!(n && v && !z && !c);
+        cpu.set_a(0xFF);
+        let _ = run_one(&mut cpu, &[0x69, 0x01]);
+        assert_eq!(cpu.get_a(), 0x00);
+        let (n, v, _b, _d, _i, z, c) = flags(cpu.get_p());
+        assert!(!n && !v && z && c);
+    }
+
+    #[test]
+    fn adc_uses_carry_in() {
+        let mut cpu = setup_cpu();
+        cpu.set_p(cpu.get_p() | CpuFlags::CARRY.bits());
+        cpu.set_a(0x01);
+        cpu.bus.write(0x0010, 0x01);
+        let cycles = run_one(&mut cpu, &[0x65, 0x10]); // ADC $10
+        assert_eq!(cpu.get_a(), 0x03);
+        assert_eq!(cycles, 3);
+    }
+
+    #[test]
+    fn sbc_borrow() {
+        let mut cpu = setup_cpu();
+        cpu.set_p(cpu.get_p() | CpuFlags::CARRY.bits());
+        cpu.set_a(0x05);
+        let _ = run_one(&mut cpu, &[0xE9, 0x06]); // SBC #$06
+        assert_eq!(cpu.get_a(), 0xFF);
+        let (n, v, _b, _d, _i, _z, c) = flags(cpu.get_p());
+        assert!(n && !v && !c);
+        cpu.set_a(0x80);
+        let _ = run_one(&mut cpu, &[0xE9, 0x01]); // carry clear: 0x80 - 1 - 1
+        assert_eq!(cpu.get_a(), 0x7E);
+        let (_n, v, _b, _d, _i, _z, c) = flags(cpu.get_p());
+        assert!(v && c);
+    }
+
+    // Logical
+    #[test]
+    fn and_ora_eor() {
+        let mut cpu = setup_cpu();
+        cpu.set_a(0b1100_1100);
+        let _ = run_one(&mut cpu, &[0x29, 0b1010_1010]); // AND #
+        assert_eq!(cpu.get_a(), 0b1000_1000);
+        let _ = run_one(&mut cpu, &[0x09, 0b0000_0011]); // ORA #
+        assert_eq!(cpu.get_a(), 0b1000_1011);
+        let _ = run_one(&mut cpu, &[0x49, 0b1000_1011]); // EOR #
+        assert_eq!(cpu.get_a(), 0);
+        let (_n, _v, _b, _d, _i, z, _c) = flags(cpu.get_p());
+        assert!(z);
+    }
+
+    #[test]
+    fn and_indirect_y_page_cross_cycles() {
+        let mut cpu = setup_cpu();
+        cpu.set_a(0xFF);
+        cpu.set_y(1);
+        cpu.bus.write(0x0020, 0xFF);
+        cpu.bus.write(0x0021, 0x12);
+        cpu.bus.write(0x1300, 0x0F);
+        let cycles = run_one(&mut cpu, &[0x31, 0x20]); // AND ($20),Y
+        assert_eq!(cpu.get_a(), 0x0F);
+        assert_eq!(cycles, 6);
+    }
+
+    // Compare
+    #[test]
+    fn cmp_cpx_cpy() {
+        let mut cpu = setup_cpu();
+        cpu.set_a(0x40);
+        let _ = run_one(&mut cpu, &[0xC9, 0x40]); // CMP #$40
+        let (n, _v, _b, _d, _i, z, c) = flags(cpu.get_p());
+        assert!(!n && z && c);
+        cpu.set_x(0x10);
+        let _ = run_one(&mut cpu, &[0xE0, 0x20]); // CPX #$20
+        let (n, _v, _b, _d, _i, z, c) = flags(cpu.get_p());
+        assert!(n && !z && !c);
+        cpu.set_y(0x30);
+        cpu.bus.write(0x1234, 0x20);
+        let cycles = run_one(&mut cpu, &[0xCC, 0x34, 0x12]); // CPY $1234
+        let (n, _v, _b, _d, _i, z, c) = flags(cpu.get_p());
+        assert!(!n && !z && c);
+        assert_eq!(cycles, 4);
+    }
+
+    #[test]
+    fn bit_copies_bits_6_and_7() {
+        let mut cpu = setup_cpu();
+        cpu.set_a(0x01);
+        cpu.bus.write(0x0010, 0xC0);
+        let _ = run_one(&mut cpu, &[0x24, 0x10]); // BIT $10
+        let (n, v, _b, _d, _i, z, _c) = flags(cpu.get_p());
+        assert!(n && v && z);
+        assert_eq!(cpu.get_a(), 0x01);
+    }
+
+    // Increments / decrements
+    #[test]
+    fn inc_dec_memory() {
+        let mut cpu = setup_cpu();
+        cpu.bus.write(0x0010, 0xFF);
+        let cycles = run_one(&mut cpu, &[0xE6, 0x10]); // INC $10
+        assert_eq!(cpu.bus.read(0x0010), 0x00);
+        assert_eq!(cycles, 5);
+        cpu.set_x(1);
+        let cycles = run_one(&mut cpu, &[0xDE, 0x0F, 0x00]); // DEC $000F,X
+        assert_eq!(cpu.bus.read(0x0010), 0xFF);
+        assert_eq!(cycles, 7);
+        let (n, _v, _b, _d, _i, z, _c) = flags(cpu.get_p());
+        assert!(n && !z);
+    }
+
+    #[test]
+    fn inc_dec_registers() {
+        let mut cpu = setup_cpu();
+        let _ = run_one(&mut cpu, &[0xCA]); // DEX
+        assert_eq!(cpu.get_x(), 0xFF);
+        let _ = run_one(&mut cpu, &[0xE8]); // INX
+        assert_eq!(cpu.get_x(), 0x00);
+        let _ = run_one(&mut cpu, &[0xC8]); // INY
+        assert_eq!(cpu.get_y(), 0x01);
+        let _ = run_one(&mut cpu, &[0x88]); // DEY
+        assert_eq!(cpu.get_y(), 0x00);
+        let (_n, _v, _b, _d, _i, z, _c) = flags(cpu.get_p());
+        assert!(z);
+    }
+
+    // Rotates
+    #[test]
+    fn rol_ror_through_carry() {
+        let mut cpu = setup_cpu();
+        cpu.set_a(0x80);
+        let _ = run_one(&mut cpu, &[0x2A]); // ROL A
+        assert_eq!(cpu.get_a(), 0x00);
+        let (_n, _v, _b, _d, _i, z, c) = flags(cpu.get_p());
+        assert!(z && c);
+        let _ = run_one(&mut cpu, &[0x6A]); // ROR A, carry rotates into bit 7
+        assert_eq!(cpu.get_a(), 0x80);
+        let (n, _v, _b, _d, _i, _z, c) = flags(cpu.get_p());
+        assert!(n && !c);
+        cpu.bus.write(0x1234, 0x01);
+        let cycles = run_one(&mut cpu, &[0x6E, 0x34, 0x12]); // ROR $1234
+        assert_eq!(cpu.bus.read(0x1234), 0x00);
+        assert_eq!(cycles, 6);
+        let (_n, _v, _b, _d, _i, z, c) = flags(cpu.get_p());
+        assert!(z && c);
+    }
+
+    // Branches
+    #[test]
+    fn branch_not_taken() {
+        let mut cpu = setup_cpu();
+        let cycles = run_one(&mut cpu, &[0xF0, 0x10]); // BEQ with Z clear
+        assert_eq!(cpu.get_pc(), START + 2);
+        assert_eq!(cycles, 2);
+    }
+
+    #[test]
+    fn branch_taken_same_page() {
+        let mut cpu = setup_cpu();
+        let cycles = run_one(&mut cpu, &[0xD0, 0x10]); // BNE with Z clear
+        assert_eq!(cpu.get_pc(), START + 0x12);
+        assert_eq!(cycles, 3);
+    }
+
+    #[test]
+    fn branch_taken_page_cross() {
+        let mut cpu = setup_cpu();
+        let cycles = run_one(&mut cpu, &[0x10, 0xFC]); // BPL -4
+        assert_eq!(cpu.get_pc(), START - 2);
+        assert_eq!(cycles, 4);
+    }
+
+    // Jumps and subroutines
+    #[test]
+    fn jmp_absolute_and_indirect() {
+        let mut cpu = setup_cpu();
+        let cycles = run_one(&mut cpu, &[0x4C, 0x34, 0x12]);
+        assert_eq!(cpu.get_pc(), 0x1234);
+        assert_eq!(cycles, 3);
+        cpu.bus.write(0x02FF, 0x00);
+        cpu.bus.write(0x0200, 0x90);
+        let cycles = run_one(&mut cpu, &[0x6C, 0xFF, 0x02]); // page wrap bug
+        assert_eq!(cpu.get_pc(), 0x9000);
+        assert_eq!(cycles, 5);
+    }
+
+    #[test]
+    fn jsr_rts_roundtrip() {
+        let mut cpu = setup_cpu();
+        cpu.bus.load(0x9000, &[0x60]); // RTS
+        let cycles = run_one(&mut cpu, &[0x20, 0x00, 0x90]); // JSR $9000
+        assert_eq!(cycles, 6);
+        assert_eq!(cpu.get_pc(), 0x9000);
+        assert_eq!(cpu.get_sp(), 0xFD);
+        // return address is the last byte of the JSR
+        assert_eq!(cpu.bus.read(0x01FF), 0x80);
+        assert_eq!(cpu.bus.read(0x01FE), 0x02);
+        let cycles = cpu.step();
+        assert_eq!(cycles, 6);
+        assert_eq!(cpu.get_pc(), START + 3);
+        assert_eq!(cpu.get_sp(), 0xFF);
+    }
+
+    #[test]
+    fn brk_rti_roundtrip() {
+        let mut cpu = setup_cpu();
+        cpu.bus.write(0xFFFE, 0x00);
+        cpu.bus.write(0xFFFF, 0x90);
+        cpu.bus.load(0x9000, &[0x40]); // RTI
+        cpu.set_p(CpuFlags::UNUSED.bits() | CpuFlags::CARRY.bits());
+        let cycles = run_one(&mut cpu, &[0x00, 0xEA]); // BRK + padding
+        assert_eq!(cycles, 7);
+        assert_eq!(cpu.get_pc(), 0x9000);
+        let (_n, _v, _b, _d, i, _z, _c) = flags(cpu.get_p());
+        assert!(i);
+        // pushed status has B and UNUSED set
+        assert_eq!(cpu.bus.read(0x01FD), 0x31);
+        let cycles = cpu.step();
+        assert_eq!(cycles, 6);
+        assert_eq!(cpu.get_pc(), START + 2);
+        assert_eq!(cpu.get_p(), 0x21);
+    }
+
+    #[test]
+    fn php_sets_break_on_stack_copy_only() {
+        let mut cpu = setup_cpu();
+        let _ = run_one(&mut cpu, &[0x08]); // PHP
+        assert_eq!(cpu.peek_stack(), 0x34);
+        assert_eq!(cpu.get_p(), 0x24);
+        let _ = run_one(&mut cpu, &[0x28]); // PLP
+        assert_eq!(cpu.get_p(), 0x24);
+    }
+
+    // Flags
+    #[test]
+    fn flag_instructions() {
+        let mut cpu = setup_cpu();
+        cpu.set_p(0);
+        let _ = run_one(&mut cpu, &[0x38]); // SEC
+        let _ = run_one(&mut cpu, &[0xF8]); // SED
+        let _ = run_one(&mut cpu, &[0x78]); // SEI
+        assert_eq!(cpu.get_p(), 0x0D);
+        let _ = run_one(&mut cpu, &[0x18]); // CLC
+        let _ = run_one(&mut cpu, &[0xD8]); // CLD
+        let _ = run_one(&mut cpu, &[0x58]); // CLI
+        assert_eq!(cpu.get_p(), 0x00);
+        cpu.set_p(CpuFlags::OVERFLOW.bits());
+        let cycles = run_one(&mut cpu, &[0xB8]); // CLV
+        assert_eq!(cpu.get_p(), 0x00);
+        assert_eq!(cycles, 2);
+    }
+
+    #[test]
+    fn nop_only_advances_pc() {
+        let mut cpu = setup_cpu();
+        let cycles = run_one(&mut cpu, &[0xEA]);
+        assert_eq!(cycles, 2);
+        assert_eq!(cpu.get_pc(), START + 1);
+    }
+
+    // ------------------------
+    // Interrupts
+    // ------------------------
+    fn set_vector(cpu: &mut CPU<MockBus>, vector: u16, target: u16) {
+        let [lsb, msb] = target.to_le_bytes();
+        cpu.bus.write(vector, lsb);
+        cpu.bus.write(vector + 1, msb);
+    }
+
+    #[test]
+    fn reset_loads_vector() {
+        let mut cpu = setup_cpu();
+        set_vector(&mut cpu, RESET_VECTOR, 0xC000);
+        cpu.set_p(0x20);
+        cpu.reset();
+        assert_eq!(cpu.get_pc(), 0xC000);
+        assert_eq!(cpu.get_sp(), 0xFC);
+        assert_eq!(cpu.get_p(), 0x24);
+        assert_eq!(cpu.get_cycles(), 7);
+        // nothing is written to the stack during reset
+        assert_eq!(cpu.bus.read(0x01FF), 0x00);
+    }
+
+    #[test]
+    fn nmi_pushes_state_and_jumps() {
+        let mut cpu = setup_cpu();
+        set_vector(&mut cpu, NMI_VECTOR, 0x9000);
+        cpu.bus.load(START, &[0xEA]);
+        cpu.set_pc(START);
+        cpu.set_p(0x20 | CpuFlags::INTERRUPT_DISABLE.bits());
+        cpu.nmi(true);
+        let cycles = cpu.step();
+        assert_eq!(cycles, 7);
+        assert_eq!(cpu.get_pc(), 0x9000);
+        assert_eq!(cpu.bus.read(0x01FF), 0x80);
+        assert_eq!(cpu.bus.read(0x01FE), 0x00);
+        // B clear, UNUSED set
+        assert_eq!(cpu.bus.read(0x01FD), 0x24);
+        assert_eq!(cpu.get_sp(), 0xFC);
+    }
+
+    #[test]
+    fn nmi_is_edge_triggered() {
+        let mut cpu = setup_cpu();
+        set_vector(&mut cpu, NMI_VECTOR, 0x9000);
+        cpu.bus.load(0x9000, &[0xEA, 0xEA]);
+        cpu.set_pc(START);
+        cpu.nmi(true);
+        cpu.step();
+        assert_eq!(cpu.get_pc(), 0x9000);
+        // line still asserted, no new edge
+        cpu.nmi(true);
+        cpu.step();
+        assert_eq!(cpu.get_pc(), 0x9001);
+        cpu.nmi(false);
+        cpu.nmi(true);
+        cpu.step();
+        assert_eq!(cpu.get_pc(), 0x9000);
+    }
+
+    #[test]
+    fn irq_masked_by_interrupt_disable() {
+        let mut cpu = setup_cpu();
+        set_vector(&mut cpu, IRQ_VECTOR, 0x9000);
+        cpu.irq(true);
+        let _ = run_one(&mut cpu, &[0xEA]);
+        assert_eq!(cpu.get_pc(), START + 1);
+    }
+
+    #[test]
+    fn irq_pushes_break_clear() {
+        let mut cpu = setup_cpu();
+        set_vector(&mut cpu, IRQ_VECTOR, 0x9000);
+        cpu.set_p(0x20);
+        cpu.set_pc(START);
+        cpu.irq(true);
+        let cycles = cpu.step();
+        assert_eq!(cycles, 7);
+        assert_eq!(cpu.get_pc(), 0x9000);
+        assert_eq!(cpu.bus.read(0x01FD), 0x20);
+        let (_n, _v, _b, _d, i, _z, _c) = flags(cpu.get_p());
+        assert!(i);
+    }
+
+    #[test]
+    fn cli_delays_irq_by_one_instruction() {
+        let mut cpu = setup_cpu();
+        set_vector(&mut cpu, IRQ_VECTOR, 0x9000);
+        cpu.bus.load(START, &[0x58, 0xEA, 0xEA]); // CLI, NOP, NOP
+        cpu.set_pc(START);
+        cpu.irq(true);
+        cpu.step(); // CLI
+        cpu.step(); // NOP still runs
+        assert_eq!(cpu.get_pc(), START + 2);
+        cpu.step();
+        assert_eq!(cpu.get_pc(), 0x9000);
+    }
+
+    #[test]
+    fn sei_lets_pending_irq_through_once() {
+        let mut cpu = setup_cpu();
+        set_vector(&mut cpu, IRQ_VECTOR, 0x9000);
+        cpu.bus.load(START, &[0x78, 0xEA]); // SEI, NOP
+        cpu.set_pc(START);
+        cpu.set_p(0x20);
+        cpu.step(); // SEI polls before I is set
+        cpu.irq(true);
+        cpu.step();
+        assert_eq!(cpu.get_pc(), 0x9000);
+        // the pushed status has I set from SEI
+        assert_eq!(cpu.bus.read(0x01FD), 0x24);
+    }
+
+    #[test]
+    fn rti_reenables_irq_immediately() {
+        let mut cpu = setup_cpu();
+        set_vector(&mut cpu, IRQ_VECTOR, 0x9000);
+        cpu.bus.load(0x9000, &[0x40]); // RTI
+        cpu.set_p(0x20);
+        cpu.set_pc(START);
+        cpu.irq(true);
+        cpu.step(); // IRQ
+        cpu.step(); // RTI restores I = 0
+        assert_eq!(cpu.get_pc(), START);
+        cpu.step(); // IRQ again since the line is still held
+        assert_eq!(cpu.get_pc(), 0x9000);
+    }
+
+    #[test]
+    fn nmi_hijacks_brk() {
+        let mut cpu = setup_cpu();
+        set_vector(&mut cpu, NMI_VECTOR, 0x9000);
+        set_vector(&mut cpu, IRQ_VECTOR, 0xA000);
+        cpu.bus.load(START, &[0x00, 0x00]);
+        cpu.set_pc(START);
+        // NMI arriving after BRK has been decoded, execute() skips the poll
+        cpu.nmi(true);
+        let _ = cpu.execute();
+        assert_eq!(cpu.get_pc(), 0x9000);
+        // B is still set in the pushed status
+        assert_eq!(cpu.bus.read(0x01FD), 0x34);
+    }
+
+    // ------------------------
+    // Unofficial opcodes
+    // ------------------------
+    #[test]
+    fn lax_loads_a_and_x() {
+        let mut cpu = setup_cpu();
+        cpu.bus.write(0x0010, 0x80);
+        let cycles = run_one(&mut cpu, &[0xA7, 0x10]); // LAX $10
+        assert_eq!(cpu.get_a(), 0x80);
+        assert_eq!(cpu.get_x(), 0x80);
+        assert_eq!(cycles, 3);
+        let (n, _v, _b, _d, _i, _z, _c) = flags(cpu.get_p());
+        assert!(n);
+    }
+
+    #[test]
+    fn sax_stores_a_and_x() {
+        let mut cpu = setup_cpu();
+        cpu.set_a(0xF0);
+        cpu.set_x(0x3C);
+        let _ = run_one(&mut cpu, &[0x8F, 0x34, 0x12]); // SAX $1234
+        assert_eq!(cpu.bus.read(0x1234), 0x30);
+    }
+
+    #[test]
+    fn dcp_decrements_then_compares() {
+        let mut cpu = setup_cpu();
+        cpu.set_a(0x10);
+        cpu.bus.write(0x0010, 0x11);
+        let cycles = run_one(&mut cpu, &[0xC7, 0x10]); // DCP $10
+        assert_eq!(cpu.bus.read(0x0010), 0x10);
+        assert_eq!(cycles, 5);
+        let (_n, _v, _b, _d, _i, z, c) = flags(cpu.get_p());
+        assert!(z && c);
+    }
+
+    #[test]
+    fn isc_increments_then_subtracts() {
+        let mut cpu = setup_cpu();
+        cpu.set_p(cpu.get_p() | CpuFlags::CARRY.bits());
+        cpu.set_a(0x10);
+        cpu.set_y(0x01);
+        cpu.bus.write(0x0020, 0x00);
+        cpu.bus.write(0x0021, 0x12);
+        cpu.bus.write(0x1201, 0x04);
+        let cycles = run_one(&mut cpu, &[0xF3, 0x20]); // ISC ($20),Y
+        assert_eq!(cpu.bus.read(0x1201), 0x05);
+        assert_eq!(cpu.get_a(), 0x0B);
+        assert_eq!(cycles, 8);
+    }
+
+    #[test]
+    fn slo_rla_sre_rra() {
+        let mut cpu = setup_cpu();
+        cpu.set_a(0x01);
+        cpu.bus.write(0x0010, 0x81);
+        let _ = run_one(&mut cpu, &[0x07, 0x10]); // SLO $10
+        assert_eq!(cpu.bus.read(0x0010), 0x02);
+        assert_eq!(cpu.get_a(), 0x03);
+        let (_n, _v, _b, _d, _i, _z, c) = flags(cpu.get_p());
+        assert!(c);
+
+        cpu.bus.write(0x0010, 0x40);
+        let _ = run_one(&mut cpu, &[0x27, 0x10]); // RLA $10, carry rotates in
+        assert_eq!(cpu.bus.read(0x0010), 0x81);
+        assert_eq!(cpu.get_a(), 0x01);
+
+        cpu.bus.write(0x0010, 0x03);
+        let _ = run_one(&mut cpu, &[0x47, 0x10]); // SRE $10
+        assert_eq!(cpu.bus.read(0x0010), 0x01);
+        assert_eq!(cpu.get_a(), 0x00);
+        let (_n, _v, _b, _d, _i, z, c) = flags(cpu.get_p());
+        assert!(z && c);
+
+        cpu.set_a(0x10);
+        cpu.bus.write(0x0010, 0x02);
+        let _ = run_one(&mut cpu, &[0x67, 0x10]); // RRA $10: ROR -> 0x81, C=0
+        assert_eq!(cpu.bus.read(0x0010), 0x81);
+        assert_eq!(cpu.get_a(), 0x91);
+    }
+
+    #[test]
+    fn anc_alr_arr_axs() {
+        let mut cpu = setup_cpu();
+        cpu.set_a(0xFF);
+        let _ = run_one(&mut cpu, &[0x0B, 0x80]); // ANC #$80
+        assert_eq!(cpu.get_a(), 0x80);
+        let (n, _v, _b, _d, _i, _z, c) = flags(cpu.get_p());
+        assert!(n && c);
+
+        cpu.set_a(0xFF);
+        let _ = run_one(&mut cpu, &[0x4B, 0x03]); // ALR #$03
+        assert_eq!(cpu.get_a(), 0x01);
+        let (_n, _v, _b, _d, _i, _z, c) = flags(cpu.get_p());
+        assert!(c);
+
+        cpu.set_a(0xFF);
+        let _ = run_one(&mut cpu, &[0x6B, 0xC0]); // ARR #$C0 with C=1
+        assert_eq!(cpu.get_a(), 0xE0);
+        let (n, v, _b, _d, _i, _z, c) = flags(cpu.get_p());
+        assert!(n && !v && c);
+
+        cpu.set_a(0x0F);
+        cpu.set_x(0x07);
+        let _ = run_one(&mut cpu, &[0xCB, 0x02]); // AXS #$02
+        assert_eq!(cpu.get_x(), 0x05);
+        let (_n, _v, _b, _d, _i, _z, c) = flags(cpu.get_p());
+        assert!(c);
+    }
+
+    #[test]
+    fn las_and_tas_use_stack_pointer() {
+        let mut cpu = setup_cpu();
+        cpu.set_sp(0xF0);
+        cpu.bus.write(0x1234, 0x3C);
+        let _ = run_one(&mut cpu, &[0xBB, 0x34, 0x12]); // LAS $1234,Y
+        assert_eq!(cpu.get_a(), 0x30);
+        assert_eq!(cpu.get_x(), 0x30);
+        assert_eq!(cpu.get_sp(), 0x30);
+
+        cpu.set_a(0xFF);
+        cpu.set_x(0x0F);
+        cpu.set_y(0x00);
+        let _ = run_one(&mut cpu, &[0x9B, 0x00, 0x12]); // TAS $1200,Y
+        assert_eq!(cpu.get_sp(), 0x0F);
+        assert_eq!(cpu.bus.read(0x1200), 0x0F & 0x13);
+    }
+
+    #[test]
+    fn shx_page_cross_corrupts_high_byte() {
+        let mut cpu = setup_cpu();
+        cpu.set_x(0x02);
+        cpu.set_y(0x01);
+        let cycles = run_one(&mut cpu, &[0x9E, 0x00, 0x12]); // SHX $1200,Y
+        assert_eq!(cpu.bus.read(0x1201), 0x02 & 0x13);
+        assert_eq!(cycles, 5);
+        cpu.set_y(0x02);
+        let _ = run_one(&mut cpu, &[0x9E, 0xFF, 0x12]); // crosses to $1301
+        assert_eq!(cpu.bus.read(0x0201), 0x02);
+        assert_eq!(cpu.bus.read(0x1301), 0x00);
+    }
+
+    #[test]
+    fn unofficial_nops_skip_operands() {
+        let mut cpu = setup_cpu();
+        let cycles = run_one(&mut cpu, &[0x1A]);
+        assert_eq!((cpu.get_pc(), cycles), (START + 1, 2));
+        let cycles = run_one(&mut cpu, &[0x80, 0xFF]);
+        assert_eq!((cpu.get_pc(), cycles), (START + 2, 2));
+        let cycles = run_one(&mut cpu, &[0x44, 0xFF]);
+        assert_eq!((cpu.get_pc(), cycles), (START + 2, 3));
+        let cycles = run_one(&mut cpu, &[0x0C, 0x34, 0x12]);
+        assert_eq!((cpu.get_pc(), cycles), (START + 3, 4));
+        cpu.set_x(1);
+        let cycles = run_one(&mut cpu, &[0xFC, 0xFF, 0x12]);
+        assert_eq!((cpu.get_pc(), cycles), (START + 3, 5));
+    }
+
+    #[test]
+    fn kil_jams_until_reset() {
+        let mut cpu = setup_cpu();
+        set_vector(&mut cpu, RESET_VECTOR, 0x9000);
+        set_vector(&mut cpu, NMI_VECTOR, 0xA000);
+        let _ = run_one(&mut cpu, &[0x02]);
+        assert!(cpu.is_jammed());
+        assert_eq!(cpu.get_pc(), START);
+        cpu.nmi(true);
+        cpu.step();
+        assert_eq!(cpu.get_pc(), START);
+        cpu.reset();
+        assert!(!cpu.is_jammed());
+        assert_eq!(cpu.get_pc(), 0x9000);
+    }
+
+    #[test]
+    fn log_policy_still_executes() {
+        let mut cpu = setup_cpu();
+        cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::LogAndContinue);
+        cpu.bus.write(0x0010, 0x42);
+        let _ = run_one(&mut cpu, &[0xA7, 0x10]); // LAX $10
+        assert_eq!(cpu.get_x(), 0x42);
+    }
+
+    #[test]
+    fn error_policy_rejects_unofficial_opcodes() {
+        let mut cpu = setup_cpu();
+        cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::Error);
+        cpu.bus.write(0x0010, 0x42);
+        assert_eq!(run_one(&mut cpu, &[0xA7, 0x10]), 0);
+        assert_eq!((cpu.get_pc(), cpu.get_x()), (START, 0));
+        assert!(cpu.is_jammed());
+        assert_eq!(cpu.step(), 1);
+        assert_eq!(cpu.get_pc(), START);
+    }
+
+    #[test]
+    fn try_step_reports_illegal_opcode_without_executing() {
+        let mut cpu = setup_cpu();
+        cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::Error);
+        cpu.bus.load(START, &asm!("LAX $10"));
+        cpu.set_pc(START);
+        assert_eq!(
+            cpu.try_step(),
+            Err(EmuError::IllegalOpcode {
+                opcode: 0xA7,
+                pc: START
+            })
+        );
+        assert_eq!((cpu.get_pc(), cpu.get_cycles()), (START, 0));
+        cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::Emulate);
+        assert_eq!(cpu.try_step(), Ok(3));
+    }
+
+    #[test]
+    fn try_step_reports_jammed_cpu() {
+        let mut cpu = setup_cpu();
+        cpu.bus.load(START, &asm!("KIL"));
+        cpu.set_pc(START);
+        assert!(cpu.try_step().is_ok());
+        assert_eq!(cpu.try_step(), Err(EmuError::CpuJammed { pc: START }));
+    }
+
+    #[test]
+    fn try_tick_reports_illegal_opcode_without_executing() {
+        let mut cpu = setup_cpu();
+        cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::Error);
+        cpu.bus.load(START, &asm!("LAX $10"));
+        cpu.set_pc(START);
+        assert_eq!(
+            cpu.try_tick(),
+            Err(EmuError::IllegalOpcode {
+                opcode: 0xA7,
+                pc: START
+            })
+        );
+        assert!(cpu.at_instruction_boundary());
+        assert_eq!((cpu.get_pc(), cpu.get_cycles()), (START, 0));
+        cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::Emulate);
+        assert_eq!(cpu.try_tick(), Ok(()));
+        assert_eq!(cpu.get_pc(), START + 1);
+    }
+
+    #[test]
+    fn error_policy_jams_a_ticked_cpu() {
+        let mut cpu = setup_cpu();
+        cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::Error);
+        cpu.bus.load(START, &asm!("LAX $10"));
+        cpu.set_pc(START);
+        cpu.tick();
+        assert!(cpu.is_jammed());
+        cpu.tick();
+        assert_eq!((cpu.get_pc(), cpu.get_cycles()), (START, 1));
+    }
+
+    #[test]
+    fn error_policy_allows_official_opcodes() {
+        let mut cpu = setup_cpu();
+        cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::Error);
+        let _ = run_one(&mut cpu, &[0xA9, 0x10]);
+        assert_eq!(cpu.get_a(), 0x10);
+    }
+
+    // ------------------------
+    // Cycle counts from the opcode table
+    // ------------------------
+    #[test]
+    fn store_cycles() {
+        let mut cpu = setup_cpu();
+        assert_eq!(run_one(&mut cpu, &[0x86, 0x10]), 3); // STX $nn
+        assert_eq!(run_one(&mut cpu, &[0x96, 0x10]), 4); // STX $nn,Y
+        assert_eq!(run_one(&mut cpu, &[0x8E, 0x34, 0x12]), 4); // STX $nnnn
+        // stores always pay for the index fixup
+        assert_eq!(run_one(&mut cpu, &[0x9D, 0x34, 0x12]), 5); // STA $nnnn,X
+        assert_eq!(run_one(&mut cpu, &[0x91, 0x10]), 6); // STA ($nn),Y
+    }
+
+    #[test]
+    fn implied_and_stack_cycles() {
+        let mut cpu = setup_cpu();
+        assert_eq!(run_one(&mut cpu, &[0xAA]), 2); // TAX
+        assert_eq!(run_one(&mut cpu, &[0x9A]), 2); // TXS
+        assert_eq!(run_one(&mut cpu, &[0x48]), 3); // PHA
+        assert_eq!(run_one(&mut cpu, &[0x68]), 4); // PLA
+        assert_eq!(run_one(&mut cpu, &[0x0A]), 2); // ASL A
+        assert_eq!(run_one(&mut cpu, &[0x4E, 0x34, 0x12]), 6); // LSR $nnnn
+        assert_eq!(run_one(&mut cpu, &[0x1E, 0x34, 0x12]), 7); // ASL $nnnn,X
+    }
+
+    #[test]
+    fn page_cross_penalty_only_for_reads() {
+        let mut cpu = setup_cpu();
+        cpu.set_x(1);
+        assert_eq!(run_one(&mut cpu, &[0xBD, 0xFF, 0x12]), 5); // LDA $12FF,X
+        assert_eq!(run_one(&mut cpu, &[0xBD, 0x00, 0x12]), 4); // LDA $1200,X
+        assert_eq!(run_one(&mut cpu, &[0xDE, 0xFF, 0x12]), 7); // DEC $12FF,X
+    }
+
+    // ------------------------
+    // DMA
+    // ------------------------
+
+    /// Raises a DMC fetch from $C000 once `halt_after` reads have been made.
+    struct DmcBus {
+        mem: MockBus,
+        reads: Vec<u16>,
+        halt_after: usize,
+        sample: Option<u8>,
+    }
+
+    impl Memory for DmcBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.reads.push(addr);
+            self.mem.read(addr)
+        }
+        fn peek(&self, addr: u16) -> u8 {
+            self.mem.peek(addr)
+        }
+        fn write(&mut self, addr: u16, value: u8) {
+            self.mem.write(addr, value);
+        }
+        fn take_dma(&mut self) -> Option<Dma> {
+            (self.sample.is_none() && self.reads.len() == self.halt_after)
+                .then_some(Dma::Dmc(0xC000))
+        }
+        fn dmc_sample(&mut self, value: u8) {
+            self.sample = Some(value);
+        }
+    }
+
+    fn dmc_cpu(halt_after: usize) -> CPU<DmcBus> {
+        let mut mem = MockBus::new();
+        mem.load(START, &asm!("LDA $4016"));
+        mem.load(0xC000, &[0x5A]);
+        let mut cpu = CPU::new(DmcBus {
+            mem,
+            reads: Vec::new(),
+            halt_after,
+            sample: None,
+        });
+        cpu.set_pc(START);
+        cpu
+    }
+
+    #[test]
+    fn dmc_fetch_repeats_the_halted_read() {
+        let mut cpu = dmc_cpu(3);
+        // Halt, dummy and get, aligned as if on the opcode fetch
+        assert_eq!(cpu.step(), 4 + 3);
+        assert_eq!(cpu.bus.sample, Some(0x5A));
+        assert_eq!(
+            cpu.bus.reads,
+            [START, START + 1, START + 2, 0x4016, 0xC000, 0x4016]
+        );
+    }
+
+    #[test]
+    fn dmc_fetch_aligns_to_a_read_cycle() {
+        let mut cpu = dmc_cpu(0);
+        cpu.cycles = 1;
+        assert_eq!(cpu.step(), 4 + 4);
+        assert_eq!(cpu.bus.reads[..3], [START, 0xC000, START]);
+    }
+
+    #[test]
+    fn ticks_count_dma_cycles() {
+        let mut cpu = dmc_cpu(3);
+        while {
+            cpu.tick();
+            !cpu.at_instruction_boundary()
+        } {}
+        // The halt really is on cycle 3, so the get needs an alignment cycle
+        assert_eq!(cpu.get_cycles(), 4 + 4);
+        assert_eq!(cpu.get_a(), cpu.bus.mem.mem[0x4016]);
+    }
+
+    #[test]
+    fn ticks_halt_on_the_jmp_pointer_read() {
+        let mut cpu = dmc_cpu(4);
+        cpu.bus.mem.load(START, &asm!("JMP ($0200)"));
+        cpu.bus.mem.load(0x0200, &[0x34, 0x12]);
+        while {
+            cpu.tick();
+            !cpu.at_instruction_boundary()
+        } {}
+        assert_eq!(cpu.bus.sample, Some(0x5A));
+        assert_eq!(cpu.get_pc(), 0x1234);
+        assert_eq!(cpu.bus.reads[4..], [0x0201, 0xC000, 0x0201]);
     }
 }
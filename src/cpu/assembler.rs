@@ -0,0 +1,910 @@
+//! A small 6502 assembler.
+//!
+//! Turns source text into bytes for CPU tests, test ROM fixtures and ROM
+//! patches. It understands labels (`loop:`), constants (`name = expr`), every
+//! addressing mode in the opcode table, the `.org`, `.byte` and `.word`
+//! directives and integer expressions:
+//!
+//! ```text
+//!         .org $8000
+//! ptr = $20
+//! start:  LDA #<message
+//!         STA ptr
+//!         LDY #0
+//! loop:   LDA (ptr),Y
+//!         BEQ done
+//!         INY
+//!         BNE loop
+//! done:   RTS
+//! message: .byte "HI", 0
+//! ```
+//!
+//! Zero page addressing is chosen whenever the operand is already known to
+//! fit in one byte on the first pass, so forward references always assemble
+//! to absolute addressing. An `a:` prefix forces absolute addressing, as in
+//! ca65 (`LDA a:$10,X`). A fully parenthesized operand is indirect.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::opcodes::{AddressingMode, Mnemonic, OPCODES};
+
+/// Assembled code as one contiguous image starting at `origin`. Gaps left by
+/// `.org` are filled with zeros.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Program {
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+    pub labels: HashMap<String, u16>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AsmError {
+    /// 1-based source line
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Assembles `source`. Code starts at $0000 unless an `.org` comes first.
+pub fn assemble(source: &str) -> Result<Program, AsmError> {
+    let lines = source
+        .lines()
+        .enumerate()
+        .map(|(index, text)| {
+            parse_line(text).map_err(|message| AsmError {
+                line: index + 1,
+                message,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut assembler = Assembler::default();
+    assembler.run(&lines, Pass::Layout)?;
+    assembler.run(&lines, Pass::Emit)?;
+    Ok(Program {
+        origin: assembler.origin.unwrap_or(0),
+        bytes: assembler.bytes,
+        labels: assembler.labels,
+    })
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Number(i64),
+    Symbol(String),
+    Pc,
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Copy, Clone, Debug)]
+enum UnaryOp {
+    Neg,
+    Not,
+    Low,
+    High,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum BinaryOp {
+    Or,
+    Xor,
+    And,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// Operand syntax as written, before the zero page/absolute choice.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Syntax {
+    None,
+    Accumulator,
+    Immediate,
+    Direct,
+    DirectX,
+    DirectY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+}
+
+#[derive(Clone, Debug)]
+enum Data {
+    Text(Vec<u8>),
+    Value(Expr),
+}
+
+#[derive(Clone, Debug)]
+enum Statement {
+    Instruction {
+        mnemonic: Mnemonic,
+        syntax: Syntax,
+        operand: Option<Expr>,
+    },
+    Assign(String, Expr),
+    Org(Expr),
+    Byte(Vec<Data>),
+    Word(Vec<Expr>),
+}
+
+#[derive(Clone, Debug, Default)]
+struct Line {
+    label: Option<String>,
+    statement: Option<Statement>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Pass {
+    Layout, // assigns addresses to labels and picks opcodes
+    Emit,
+}
+
+#[derive(Default)]
+struct Assembler {
+    symbols: HashMap<String, i64>,
+    labels: HashMap<String, u16>,
+    opcodes: Vec<Option<u8>>, // chosen in the layout pass, one per line
+    pc: u32,
+    origin: Option<u16>,
+    bytes: Vec<u8>,
+}
+
+impl Assembler {
+    fn run(&mut self, lines: &[Line], pass: Pass) -> Result<(), AsmError> {
+        self.pc = 0;
+        for (index, line) in lines.iter().enumerate() {
+            self.line(index, line, pass).map_err(|message| AsmError {
+                line: index + 1,
+                message,
+            })?;
+            if self.pc > 0x10000 {
+                return Err(AsmError {
+                    line: index + 1,
+                    message: "program runs past $FFFF".to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn line(&mut self, index: usize, line: &Line, pass: Pass) -> Result<(), String> {
+        if pass == Pass::Layout {
+            self.opcodes.push(None);
+            if let Some(label) = &line.label {
+                self.define(label, self.pc as i64)?;
+                self.labels.insert(label.clone(), self.pc as u16);
+            }
+        }
+
+        match &line.statement {
+            Some(Statement::Assign(name, value)) if pass == Pass::Layout => {
+                let value = self.eval(value)?;
+                self.define(name, value)?;
+            }
+            None | Some(Statement::Assign(..)) => {}
+            Some(Statement::Org(address)) => {
+                let address = self.eval(address)?;
+                if !(0..=0xFFFF).contains(&address) {
+                    return Err(format!(".org ${address:X} is outside the address space"));
+                }
+                if address < self.pc as i64 && self.origin.is_some() {
+                    return Err(".org cannot move backwards over emitted code".to_string());
+                }
+                self.pc = address as u32;
+            }
+            Some(Statement::Byte(items)) => {
+                for item in items {
+                    match item {
+                        Data::Text(text) => {
+                            for &byte in text {
+                                self.emit(pass, byte);
+                            }
+                        }
+                        Data::Value(expr) => {
+                            let value = self.eval_in(expr, pass)?;
+                            self.emit(pass, byte(value)?);
+                        }
+                    }
+                }
+            }
+            Some(Statement::Word(items)) => {
+                for expr in items {
+                    let [lo, hi] = word(self.eval_in(expr, pass)?)?.to_le_bytes();
+                    self.emit(pass, lo);
+                    self.emit(pass, hi);
+                }
+            }
+            Some(Statement::Instruction {
+                mnemonic,
+                syntax,
+                operand,
+            }) => {
+                let opcode = match self.opcodes[index] {
+                    Some(opcode) => opcode,
+                    None => {
+                        let opcode = self.select_opcode(*mnemonic, *syntax, operand.as_ref())?;
+                        self.opcodes[index] = Some(opcode);
+                        opcode
+                    }
+                };
+                self.instruction(opcode, operand.as_ref(), pass)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn instruction(
+        &mut self,
+        opcode: u8,
+        operand: Option<&Expr>,
+        pass: Pass,
+    ) -> Result<(), String> {
+        let op = OPCODES[opcode as usize];
+        let start = self.pc;
+        // `*` is the address of the opcode, so evaluate before emitting it
+        let value = operand.map(|expr| self.eval_in(expr, pass)).transpose()?;
+        self.emit(pass, opcode);
+        let Some(value) = value else {
+            return Ok(());
+        };
+        match op.mode {
+            AddressingMode::Relative => {
+                let offset = value - (start as i64 + 2);
+                if pass == Pass::Emit && !(-128..=127).contains(&offset) {
+                    return Err(format!("branch target ${value:04X} is out of range"));
+                }
+                self.emit(pass, offset as u8);
+            }
+            mode if mode.operand_bytes() == 1 => self.emit(pass, byte(value)?),
+            _ => {
+                let [lo, hi] = word(value)?.to_le_bytes();
+                self.emit(pass, lo);
+                self.emit(pass, hi);
+            }
+        }
+        Ok(())
+    }
+
+    /// Picks the encoding for the operand syntax, preferring zero page when
+    /// the operand is already known to fit and official opcodes over
+    /// unofficial duplicates.
+    fn select_opcode(
+        &self,
+        mnemonic: Mnemonic,
+        syntax: Syntax,
+        operand: Option<&Expr>,
+    ) -> Result<u8, String> {
+        use AddressingMode::*;
+
+        let candidates: &[AddressingMode] = match syntax {
+            Syntax::None => &[Implied, Accumulator],
+            Syntax::Accumulator => &[Accumulator],
+            Syntax::Immediate => &[Immediate],
+            Syntax::Direct => &[Relative, ZeroPage, Absolute],
+            Syntax::DirectX => &[ZeroPageX, AbsoluteX],
+            Syntax::DirectY => &[ZeroPageY, AbsoluteY],
+            Syntax::Absolute => &[Absolute],
+            Syntax::AbsoluteX => &[AbsoluteX],
+            Syntax::AbsoluteY => &[AbsoluteY],
+            Syntax::Indirect => &[Indirect],
+            Syntax::IndirectX => &[IndirectX],
+            Syntax::IndirectY => &[IndirectY],
+        };
+        let available = candidates
+            .iter()
+            .filter_map(|&mode| find_opcode(mnemonic, mode).map(|opcode| (mode, opcode)))
+            .collect::<Vec<_>>();
+        let fits_zero_page = operand
+            .and_then(|expr| self.eval(expr).ok())
+            .is_some_and(|value| (0..=0xFF).contains(&value));
+
+        match available.as_slice() {
+            [] => Err(format!(
+                "{mnemonic:?} does not support this addressing mode"
+            )),
+            [(ZeroPage | ZeroPageX | ZeroPageY, _), (_, absolute), ..] if !fits_zero_page => {
+                Ok(*absolute)
+            }
+            [(_, opcode), ..] => Ok(*opcode),
+        }
+    }
+
+    fn define(&mut self, name: &str, value: i64) -> Result<(), String> {
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return Err(format!("`{name}` is defined twice"));
+        }
+        Ok(())
+    }
+
+    /// Forward references only need to resolve in the emit pass.
+    fn eval_in(&self, expr: &Expr, pass: Pass) -> Result<i64, String> {
+        match pass {
+            Pass::Layout => Ok(self.eval(expr).unwrap_or(0)),
+            Pass::Emit => self.eval(expr),
+        }
+    }
+
+    fn eval(&self, expr: &Expr) -> Result<i64, String> {
+        Ok(match expr {
+            Expr::Number(value) => *value,
+            Expr::Pc => self.pc as i64,
+            Expr::Symbol(name) => *self
+                .symbols
+                .get(name)
+                .ok_or_else(|| format!("undefined symbol `{name}`"))?,
+            Expr::Unary(op, operand) => {
+                let value = self.eval(operand)?;
+                match op {
+                    UnaryOp::Neg => -value,
+                    UnaryOp::Not => !value,
+                    UnaryOp::Low => value & 0xFF,
+                    UnaryOp::High => (value >> 8) & 0xFF,
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (self.eval(lhs)?, self.eval(rhs)?);
+                match op {
+                    BinaryOp::Or => lhs | rhs,
+                    BinaryOp::Xor => lhs ^ rhs,
+                    BinaryOp::And => lhs & rhs,
+                    BinaryOp::Shl => lhs.wrapping_shl(rhs as u32),
+                    BinaryOp::Shr => lhs.wrapping_shr(rhs as u32),
+                    BinaryOp::Add => lhs.wrapping_add(rhs),
+                    BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                    BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                    BinaryOp::Div => lhs
+                        .checked_div(rhs)
+                        .ok_or_else(|| "division by zero".to_string())?,
+                }
+            }
+        })
+    }
+
+    fn emit(&mut self, pass: Pass, byte: u8) {
+        if pass == Pass::Emit {
+            let origin = *self.origin.get_or_insert(self.pc as u16);
+            let offset = (self.pc - origin as u32) as usize;
+            if self.bytes.len() < offset {
+                self.bytes.resize(offset, 0);
+            }
+            self.bytes.push(byte);
+        }
+        self.pc += 1;
+    }
+}
+
+fn find_opcode(mnemonic: Mnemonic, mode: AddressingMode) -> Option<u8> {
+    let find = |official: bool| {
+        (0..=0xFFu8).find(|&code| {
+            let op = OPCODES[code as usize];
+            op.mnemonic == mnemonic && op.mode == mode && op.official == official
+        })
+    };
+    find(true).or_else(|| find(false))
+}
+
+fn byte(value: i64) -> Result<u8, String> {
+    if (-0x80..=0xFF).contains(&value) {
+        Ok(value as u8)
+    } else {
+        Err(format!("${value:X} does not fit in a byte"))
+    }
+}
+
+fn word(value: i64) -> Result<u16, String> {
+    if (-0x8000..=0xFFFF).contains(&value) {
+        Ok(value as u16)
+    } else {
+        Err(format!("${value:X} does not fit in a word"))
+    }
+}
+
+fn parse_line(text: &str) -> Result<Line, String> {
+    let mut rest = strip_comment(text).trim();
+    let mut line = Line::default();
+
+    let name_len = identifier_len(rest);
+    if name_len > 0 {
+        let name = &rest[..name_len];
+        let after = rest[name_len..].trim_start();
+        if let Some(after) = after.strip_prefix(':') {
+            line.label = Some(name.to_string());
+            rest = after.trim();
+        } else if let Some(value) = after.strip_prefix('=') {
+            line.statement = Some(Statement::Assign(name.to_string(), parse_expr(value)?));
+            return Ok(line);
+        }
+    }
+    if rest.is_empty() {
+        return Ok(line);
+    }
+
+    let (word, operand) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    let statement = if let Some(directive) = word.strip_prefix('.') {
+        match directive.to_ascii_lowercase().as_str() {
+            "org" => Statement::Org(parse_expr(operand)?),
+            "byte" => Statement::Byte(
+                split_top_level(operand)
+                    .into_iter()
+                    .map(parse_data)
+                    .collect::<Result<_, _>>()?,
+            ),
+            "word" => Statement::Word(
+                split_top_level(operand)
+                    .into_iter()
+                    .map(parse_expr)
+                    .collect::<Result<_, _>>()?,
+            ),
+            _ => return Err(format!("unknown directive `{word}`")),
+        }
+    } else {
+        let upper = word.to_ascii_uppercase();
+        let mnemonic = OPCODES
+            .iter()
+            .map(|op| op.mnemonic)
+            .find(|mnemonic| format!("{mnemonic:?}") == upper)
+            .ok_or_else(|| format!("unknown mnemonic `{word}`"))?;
+        let (syntax, operand) = parse_operand(operand)?;
+        Statement::Instruction {
+            mnemonic,
+            syntax,
+            operand,
+        }
+    };
+    line.statement = Some(statement);
+    Ok(line)
+}
+
+fn parse_operand(text: &str) -> Result<(Syntax, Option<Expr>), String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok((Syntax::None, None));
+    }
+    if text.eq_ignore_ascii_case("A") {
+        return Ok((Syntax::Accumulator, None));
+    }
+    if let Some(value) = text.strip_prefix('#') {
+        return Ok((Syntax::Immediate, Some(parse_expr(value)?)));
+    }
+
+    let (text, absolute) = match text.get(..2) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("a:") => (&text[2..], true),
+        _ => (text, false),
+    };
+
+    let parts = split_top_level(text);
+    let (base, index) = match parts.as_slice() {
+        [base] => (*base, None),
+        [base, index] => (*base, Some(index.to_ascii_uppercase())),
+        _ => return Err(format!("invalid operand `{text}`")),
+    };
+
+    if is_parenthesized(base) && !absolute {
+        let inner = &base[1..base.len() - 1];
+        let (syntax, pointer) = match (split_top_level(inner).as_slice(), index.as_deref()) {
+            ([pointer], None) => (Syntax::Indirect, *pointer),
+            ([pointer], Some("Y")) => (Syntax::IndirectY, *pointer),
+            ([pointer, x], None) if x.eq_ignore_ascii_case("X") => (Syntax::IndirectX, *pointer),
+            _ => return Err(format!("invalid indirect operand `{text}`")),
+        };
+        return Ok((syntax, Some(parse_expr(pointer)?)));
+    }
+
+    let syntax = match (index.as_deref(), absolute) {
+        (None, false) => Syntax::Direct,
+        (Some("X"), false) => Syntax::DirectX,
+        (Some("Y"), false) => Syntax::DirectY,
+        (None, true) => Syntax::Absolute,
+        (Some("X"), true) => Syntax::AbsoluteX,
+        (Some("Y"), true) => Syntax::AbsoluteY,
+        (Some(other), _) => return Err(format!("invalid index register `{other}`")),
+    };
+    Ok((syntax, Some(parse_expr(base)?)))
+}
+
+fn parse_data(text: &str) -> Result<Data, String> {
+    match text.strip_prefix('"') {
+        Some(rest) => rest
+            .strip_suffix('"')
+            .map(|inner| Data::Text(inner.as_bytes().to_vec()))
+            .ok_or_else(|| format!("unterminated string {text}")),
+        None => parse_expr(text).map(Data::Value),
+    }
+}
+
+/// Removes a `;` comment, ignoring semicolons inside quotes.
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    for (index, c) in text.char_indices() {
+        match (quote, c) {
+            (None, ';') => return &text[..index],
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Splits on commas outside parentheses and quotes, trimming each part.
+fn split_top_level(text: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut quote, mut start) = (0, None, 0);
+    for (index, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(open), _) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                parts.push(text[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(text[start..].trim());
+    parts
+}
+
+/// True when the opening parenthesis is closed by the last character.
+fn is_parenthesized(text: &str) -> bool {
+    if !text.starts_with('(') || !text.ends_with(')') {
+        return false;
+    }
+    let mut depth = 0;
+    for (index, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            return index == text.len() - 1;
+        }
+    }
+    false
+}
+
+fn identifier_len(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    if !bytes
+        .first()
+        .is_some_and(|b| b.is_ascii_alphabetic() || *b == b'_')
+    {
+        return 0;
+    }
+    bytes
+        .iter()
+        .position(|b| !(b.is_ascii_alphanumeric() || *b == b'_'))
+        .unwrap_or(bytes.len())
+}
+
+fn parse_expr(text: &str) -> Result<Expr, String> {
+    let mut parser = ExprParser {
+        text: text.trim().as_bytes(),
+        pos: 0,
+    };
+    if parser.text.is_empty() {
+        return Err("missing operand".to_string());
+    }
+    let expr = parser.binary(0)?;
+    parser.skip_space();
+    if parser.pos != parser.text.len() {
+        let rest = String::from_utf8_lossy(&parser.text[parser.pos..]);
+        return Err(format!("unexpected `{rest}` in expression"));
+    }
+    Ok(expr)
+}
+
+/// Binary operators from the loosest to the tightest binding.
+const PRECEDENCE: [&[(&str, BinaryOp)]; 6] = [
+    &[("|", BinaryOp::Or)],
+    &[("^", BinaryOp::Xor)],
+    &[("&", BinaryOp::And)],
+    &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[("*", BinaryOp::Mul), ("/", BinaryOp::Div)],
+];
+
+struct ExprParser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl ExprParser<'_> {
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        'operators: loop {
+            self.skip_space();
+            for &(token, op) in PRECEDENCE[level] {
+                if self.eat(token) {
+                    let rhs = self.binary(level + 1)?;
+                    lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+                    continue 'operators;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        self.skip_space();
+        let op = match self.peek() {
+            Some(b'-') => UnaryOp::Neg,
+            Some(b'~') => UnaryOp::Not,
+            Some(b'<') => UnaryOp::Low,
+            Some(b'>') => UnaryOp::High,
+            _ => return self.primary(),
+        };
+        self.pos += 1;
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let Some(c) = self.peek() else {
+            return Err("expression ends early".to_string());
+        };
+        match c {
+            b'(' => {
+                self.pos += 1;
+                let expr = self.binary(0)?;
+                self.skip_space();
+                if !self.eat(")") {
+                    return Err("missing `)`".to_string());
+                }
+                Ok(expr)
+            }
+            b'$' => self.number(16, 1),
+            b'%' => self.number(2, 1),
+            b'0'..=b'9' => self.number(10, 0),
+            b'\'' => match self.text[self.pos..] {
+                [_, c, b'\'', ..] => {
+                    self.pos += 3;
+                    Ok(Expr::Number(c as i64))
+                }
+                _ => Err("invalid character literal".to_string()),
+            },
+            b'*' => {
+                self.pos += 1;
+                Ok(Expr::Pc)
+            }
+            _ => {
+                let rest = std::str::from_utf8(&self.text[self.pos..]).unwrap_or("");
+                let len = identifier_len(rest);
+                if len == 0 {
+                    return Err(format!("unexpected `{}` in expression", c as char));
+                }
+                self.pos += len;
+                Ok(Expr::Symbol(rest[..len].to_string()))
+            }
+        }
+    }
+
+    fn number(&mut self, radix: u32, prefix: usize) -> Result<Expr, String> {
+        let start = self.pos + prefix;
+        let len = self.text[start..]
+            .iter()
+            .position(|b| !b.is_ascii_alphanumeric())
+            .unwrap_or(self.text.len() - start);
+        let digits = std::str::from_utf8(&self.text[start..start + len]).unwrap_or("");
+        self.pos = start + len;
+        i64::from_str_radix(digits, radix)
+            .map(Expr::Number)
+            .map_err(|_| format!("invalid number `{digits}`"))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        let matched = self.text[self.pos..].starts_with(token.as_bytes());
+        if matched {
+            self.pos += token.len();
+        }
+        matched
+    }
+
+    fn skip_space(&mut self) {
+        while self.peek().is_some_and(|b| b.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Memory;
+    use crate::cpu::disassemble;
+
+    fn bytes(source: &str) -> Vec<u8> {
+        assemble(source).unwrap().bytes
+    }
+
+    fn error(source: &str) -> AsmError {
+        assemble(source).unwrap_err()
+    }
+
+    struct Image {
+        mem: Vec<u8>,
+    }
+
+    impl Memory for Image {
+        fn read(&mut self, address: u16) -> u8 {
+            self.peek(address)
+        }
+
+        fn peek(&self, address: u16) -> u8 {
+            self.mem[address as usize]
+        }
+
+        fn write(&mut self, address: u16, value: u8) {
+            self.mem[address as usize] = value;
+        }
+    }
+
+    #[test]
+    fn encodes_every_addressing_mode() {
+        assert_eq!(bytes("NOP"), [0xEA]);
+        assert_eq!(bytes("ASL A"), [0x0A]);
+        assert_eq!(bytes("ASL"), [0x0A]);
+        assert_eq!(bytes("LDA #$42"), [0xA9, 0x42]);
+        assert_eq!(bytes("LDA $20"), [0xA5, 0x20]);
+        assert_eq!(bytes("LDA $20,X"), [0xB5, 0x20]);
+        assert_eq!(bytes("LDX $20,Y"), [0xB6, 0x20]);
+        assert_eq!(bytes("LDA $1234"), [0xAD, 0x34, 0x12]);
+        assert_eq!(bytes("lda $1234,x"), [0xBD, 0x34, 0x12]);
+        assert_eq!(bytes("LDA $1234,Y"), [0xB9, 0x34, 0x12]);
+        assert_eq!(bytes("LDA $20,Y"), [0xB9, 0x20, 0x00]);
+        assert_eq!(bytes("JMP ($10FF)"), [0x6C, 0xFF, 0x10]);
+        assert_eq!(bytes("LDA ($20,X)"), [0xA1, 0x20]);
+        assert_eq!(bytes("LDA ($20),Y"), [0xB1, 0x20]);
+    }
+
+    #[test]
+    fn prefers_official_encodings() {
+        assert_eq!(bytes("SBC #1"), [0xE9, 0x01]);
+        assert_eq!(bytes("NOP $10"), [0x04, 0x10]);
+        assert_eq!(bytes("LAX ($10),Y"), [0xB3, 0x10]);
+    }
+
+    #[test]
+    fn resolves_labels_in_both_directions() {
+        let program = assemble(
+            "        .org $C000
+            start:  LDX #0
+            loop:   INX
+                    BNE loop
+                    BEQ done
+                    JMP start
+            done:   RTS",
+        )
+        .unwrap();
+        assert_eq!(program.origin, 0xC000);
+        assert_eq!(
+            program.bytes,
+            [
+                0xA2, 0x00, 0xE8, 0xD0, 0xFD, 0xF0, 0x03, 0x4C, 0x00, 0xC0, 0x60
+            ]
+        );
+        assert_eq!(program.labels["done"], 0xC00A);
+    }
+
+    #[test]
+    fn forward_references_use_absolute_addressing() {
+        assert_eq!(bytes("LDA var\nvar = $10"), [0xAD, 0x10, 0x00]);
+        assert_eq!(bytes("var = $10\nLDA var"), [0xA5, 0x10]);
+    }
+
+    #[test]
+    fn forces_absolute_addressing() {
+        assert_eq!(bytes("LDA a:$10"), [0xAD, 0x10, 0x00]);
+        assert_eq!(bytes("lda A:$FF,x"), [0xBD, 0xFF, 0x00]);
+        assert_eq!(bytes("LDX a:$FF,Y"), [0xBE, 0xFF, 0x00]);
+        assert_eq!(bytes("JMP a:$10"), [0x4C, 0x10, 0x00]);
+        assert!(error("BNE a:$10").message.contains("addressing mode"));
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        assert_eq!(
+            bytes(
+                "   .org $8000
+                ptr = $20
+                    LDA #<data
+                    LDX #>data
+                    STA ptr+1
+                    LDA #(2 + 3) * 4 - 1
+                    LDA #%1010 | $F0 & $3F
+                    LDA #'A'
+                    LDA #-1
+                    JMP *
+                data:"
+            ),
+            [
+                0xA9, 0x11, 0xA2, 0x80, 0x85, 0x21, 0xA9, 19, 0xA9, 0x3A, 0xA9, 0x41, 0xA9, 0xFF,
+                0x4C, 0x0E, 0x80
+            ]
+        );
+    }
+
+    #[test]
+    fn assembles_data_directives() {
+        let program = assemble(
+            ".org $10
+            .byte 1, \"A;B\", $FF ; a comment
+            .word $1234, end
+            .org $20
+            end: .byte 0",
+        )
+        .unwrap();
+        assert_eq!(program.origin, 0x10);
+        assert_eq!(
+            &program.bytes[..9],
+            [1, b'A', b';', b'B', 0xFF, 0x34, 0x12, 0x20, 0x00]
+        );
+        assert_eq!(program.bytes.len(), 0x11);
+        assert_eq!(program.bytes[0x10], 0);
+    }
+
+    #[test]
+    fn reports_errors_with_line_numbers() {
+        assert_eq!(
+            error("NOP\nFOO #1"),
+            AsmError {
+                line: 2,
+                message: "unknown mnemonic `FOO`".to_string()
+            }
+        );
+        assert_eq!(error("JMP nowhere").message, "undefined symbol `nowhere`");
+        assert_eq!(error("a:\na:").line, 2);
+        assert_eq!(error("LDA #$100").line, 1);
+        assert_eq!(error("STX $1234,X").line, 1);
+        assert_eq!(error(".org $10\nNOP\n.org $0").line, 3);
+        assert!(
+            error("BNE far\n.byte 0\n.org $100\nfar:")
+                .message
+                .contains("out of range")
+        );
+    }
+
+    #[test]
+    fn round_trips_through_the_disassembler() {
+        for code in 0..=0xFFu8 {
+            let mut mem = vec![0; 0x10000];
+            mem[0x8000..0x8003].copy_from_slice(&[code, 0x12, 0x34]);
+            let image = Image { mem };
+            let (instruction, len) = disassemble(&image, 0x8000);
+            let source = format!(".org $8000\n{instruction}");
+            let assembled = bytes(&source);
+            let op = OPCODES[assembled[0] as usize];
+            assert_eq!(
+                (op.mnemonic, op.mode),
+                (instruction.mnemonic, instruction.mode),
+                "{source}"
+            );
+            assert_eq!(&assembled[1..], &image.mem[0x8001..0x8000 + len as usize]);
+        }
+    }
+}
@@ -0,0 +1,798 @@
+//! Cycle-stepped execution.
+//!
+//! `CPU::tick` advances the CPU by exactly one cycle and performs the single
+//! bus access the 6502 makes on that cycle, dummy reads and writes included.
+//! The per-cycle sequences follow "6502_cpu.txt" by John West and Marko Mäkelä.
+
+use super::opcodes::{AddressingMode, Mnemonic, OPCODES};
+use super::{CPU, CpuFlags, IRQ_VECTOR, NMI_VECTOR, Operand};
+use crate::bus::Memory;
+use crate::error::EmuError;
+
+/// State carried between the cycles of one instruction.
+#[derive(Copy, Clone, Debug, Default)]
+pub(super) struct Pipeline {
+    cycle: u8, // next cycle of the instruction, 0 between instructions
+    opcode: u8,
+    interrupt: bool,         // running the NMI/IRQ sequence instead of an opcode
+    address: u16,            // effective address being formed
+    base: u16,               // address before indexing, for page cross detection
+    pointer: u8,             // zero page pointer of the indirect modes
+    data: u8,                // latched operand byte
+    tail: u8,                // first cycle after the effective address is known
+    interrupt_disable: bool, // I flag when the instruction started
+}
+
+impl Pipeline {
+    pub(super) fn in_progress(&self) -> bool {
+        self.cycle != 0
+    }
+}
+
+enum Progress {
+    Busy,
+    AddressReady,
+    Done,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub(super) enum Access {
+    Read,
+    Write,
+    ReadModifyWrite,
+}
+
+impl Access {
+    pub(super) fn of(mnemonic: Mnemonic) -> Self {
+        use Mnemonic::*;
+
+        match mnemonic {
+            STA | STX | STY | SAX | SHA | SHX | SHY | TAS => Access::Write,
+            ASL | LSR | ROL | ROR | INC | DEC | SLO | RLA | SRE | RRA | DCP | ISC => {
+                Access::ReadModifyWrite
+            }
+            _ => Access::Read,
+        }
+    }
+}
+
+impl<M: Memory> CPU<M> {
+    /// Advances the CPU by a single cycle, issuing that cycle's bus access.
+    ///
+    /// Interrupts are polled when a new instruction would be fetched, the same
+    /// way `step` does. A DMA that halts the CPU on this cycle's read runs to
+    /// completion within the tick, which then counts all of its cycles.
+    ///
+    /// An unofficial opcode rejected under `IllegalOpcodePolicy::Error` is not
+    /// started, takes no cycle and jams the CPU until reset. Use `try_tick` to
+    /// see why.
+    pub fn tick(&mut self) {
+        if let Err(EmuError::IllegalOpcode { .. }) = self.try_tick() {
+            self.jammed = true;
+        }
+    }
+
+    /// Fallible `tick`. A jammed CPU still spends the cycle, and reports
+    /// `EmuError::CpuJammed`. A rejected unofficial opcode and faults the bus
+    /// raised on this cycle are reported after it.
+    pub fn try_tick(&mut self) -> Result<(), EmuError> {
+        if self.jammed {
+            self.cycles += 1;
+            return Err(EmuError::CpuJammed { pc: self.pc });
+        }
+        self.run_tick();
+        match self.fault.take().or_else(|| self.bus.take_fault()) {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    pub(super) fn run_tick(&mut self) {
+        if self.jammed {
+            self.cycles += 1;
+            return;
+        }
+
+        let progress = if !self.pipeline.in_progress() {
+            if !self.tick_fetch() {
+                // Rejected opcodes do not start and leave PC on the opcode
+                self.cycles += std::mem::take(&mut self.dma_cycles);
+                return;
+            }
+            Progress::Busy
+        } else if self.pipeline.interrupt {
+            self.tick_break(false)
+        } else {
+            self.tick_instruction()
+        };
+
+        self.cycles += 1 + std::mem::take(&mut self.dma_cycles);
+        match progress {
+            Progress::Busy => self.pipeline.cycle += 1,
+            Progress::AddressReady => {
+                self.pipeline.cycle += 1;
+                self.pipeline.tail = self.pipeline.cycle;
+            }
+            Progress::Done => {
+                if self.pipeline.interrupt {
+                    self.irq_inhibit = true;
+                } else {
+                    let mnemonic = OPCODES[self.pipeline.opcode as usize].mnemonic;
+                    self.update_irq_inhibit(mnemonic, self.pipeline.interrupt_disable);
+                }
+                self.pipeline.cycle = 0;
+            }
+        }
+    }
+
+    /// True when the next `tick` starts a new instruction or interrupt.
+    pub fn at_instruction_boundary(&self) -> bool {
+        !self.pipeline.in_progress()
+    }
+
+    /// Fetches the next opcode, or false when the illegal opcode policy
+    /// rejected it.
+    fn tick_fetch(&mut self) -> bool {
+        let interrupt = self.nmi_pending || (self.irq_line && !self.irq_inhibit);
+        if interrupt {
+            self.bus.begin_interrupt(self.pc, self.cycles);
+        } else {
+            self.bus.begin_instruction(self.pc, self.cycles);
+        }
+        // An interrupt replaces the opcode fetch with a dummy read and keeps PC
+        let opcode = self.read(self.pc);
+        if !interrupt {
+            if let Err(error) = self.check_illegal_opcode(opcode, self.pc) {
+                self.fault = Some(error);
+                return false;
+            }
+            self.inc_pc();
+        }
+        self.pipeline = Pipeline {
+            opcode,
+            interrupt,
+            interrupt_disable: self.get_flag(CpuFlags::INTERRUPT_DISABLE),
+            ..Pipeline::default()
+        };
+        true
+    }
+
+    fn tick_instruction(&mut self) -> Progress {
+        use Mnemonic::*;
+
+        let op = OPCODES[self.pipeline.opcode as usize];
+        match op.mnemonic {
+            BRK => self.tick_break(true),
+            JMP if op.mode == AddressingMode::Absolute => self.tick_jmp_absolute(),
+            JMP => self.tick_jmp_indirect(),
+            JSR => self.tick_jsr(),
+            RTS => self.tick_rts(),
+            RTI => self.tick_rti(),
+            PHA | PHP => self.tick_push(op.mnemonic),
+            PLA | PLP => self.tick_pull(op.mnemonic),
+            BPL | BMI | BVC | BVS | BCC | BCS | BNE | BEQ => self.tick_branch(op.mnemonic),
+            _ if self.pipeline.tail != 0 => self.tick_operation(op.mnemonic),
+            _ => self.tick_addressing(op.mnemonic, op.mode),
+        }
+    }
+
+    fn stack_address(&self) -> u16 {
+        0x0100 | self.sp as u16
+    }
+
+    fn fetch_operand_byte(&mut self) -> u8 {
+        let value = self.read(self.pc);
+        self.inc_pc();
+        value
+    }
+
+    /// BRK and the NMI/IRQ sequence, which only differ in cycle 1 and the B flag.
+    fn tick_break(&mut self, brk: bool) -> Progress {
+        match self.pipeline.cycle {
+            1 => {
+                self.read(self.pc);
+                if brk {
+                    self.inc_pc();
+                }
+            }
+            2 => self.push_stack((self.pc >> 8) as u8),
+            3 => self.push_stack(self.pc as u8),
+            4 => {
+                let mut status = self.sr | CpuFlags::UNUSED;
+                status.set(CpuFlags::BREAK, brk);
+                self.push_stack(status.bits());
+            }
+            5 => {
+                // An NMI arriving before the vector fetch hijacks BRK and IRQ
+                self.pipeline.address = if self.nmi_pending {
+                    self.nmi_pending = false;
+                    NMI_VECTOR
+                } else {
+                    IRQ_VECTOR
+                };
+                self.pipeline.data = self.read(self.pipeline.address);
+                self.set_flag(CpuFlags::INTERRUPT_DISABLE, true);
+            }
+            _ => {
+                let msb = self.read(self.pipeline.address.wrapping_add(1));
+                self.pc = Self::get_address(self.pipeline.data, msb);
+                return Progress::Done;
+            }
+        }
+        Progress::Busy
+    }
+
+    fn tick_jmp_absolute(&mut self) -> Progress {
+        if self.pipeline.cycle == 1 {
+            self.pipeline.data = self.fetch_operand_byte();
+            return Progress::Busy;
+        }
+        let msb = self.read(self.pc);
+        self.pc = Self::get_address(self.pipeline.data, msb);
+        Progress::Done
+    }
+
+    fn tick_jmp_indirect(&mut self) -> Progress {
+        match self.pipeline.cycle {
+            1 => self.pipeline.data = self.fetch_operand_byte(),
+            2 => {
+                let msb = self.fetch_operand_byte();
+                self.pipeline.address = Self::get_address(self.pipeline.data, msb);
+            }
+            3 => self.pipeline.data = self.read(self.pipeline.address),
+            _ => {
+                // The pointer high byte is fetched without carry into the page
+                let pointer = self.pipeline.address;
+                let msb = self.read((pointer & 0xFF00) | (pointer.wrapping_add(1) & 0x00FF));
+                self.pc = Self::get_address(self.pipeline.data, msb);
+                return Progress::Done;
+            }
+        }
+        Progress::Busy
+    }
+
+    fn tick_jsr(&mut self) -> Progress {
+        match self.pipeline.cycle {
+            1 => self.pipeline.data = self.fetch_operand_byte(),
+            2 => {
+                self.read(self.stack_address());
+            }
+            3 => self.push_stack((self.pc >> 8) as u8),
+            4 => self.push_stack(self.pc as u8),
+            _ => {
+                let msb = self.read(self.pc);
+                self.pc = Self::get_address(self.pipeline.data, msb);
+                return Progress::Done;
+            }
+        }
+        Progress::Busy
+    }
+
+    fn tick_rts(&mut self) -> Progress {
+        match self.pipeline.cycle {
+            1 => {
+                self.read(self.pc);
+            }
+            2 => {
+                self.read(self.stack_address());
+            }
+            3 => self.pipeline.data = self.pull_stack(),
+            4 => {
+                let msb = self.pull_stack();
+                self.pc = Self::get_address(self.pipeline.data, msb);
+            }
+            _ => {
+                self.read(self.pc);
+                self.inc_pc();
+                return Progress::Done;
+            }
+        }
+        Progress::Busy
+    }
+
+    fn tick_rti(&mut self) -> Progress {
+        match self.pipeline.cycle {
+            1 => {
+                self.read(self.pc);
+            }
+            2 => {
+                self.read(self.stack_address());
+            }
+            3 => {
+                let value = self.pull_stack();
+                self.set_status_from_stack(value);
+            }
+            4 => self.pipeline.data = self.pull_stack(),
+            _ => {
+                let msb = self.pull_stack();
+                self.pc = Self::get_address(self.pipeline.data, msb);
+                return Progress::Done;
+            }
+        }
+        Progress::Busy
+    }
+
+    fn tick_push(&mut self, mnemonic: Mnemonic) -> Progress {
+        if self.pipeline.cycle == 1 {
+            self.read(self.pc);
+            return Progress::Busy;
+        }
+        self.run(mnemonic, Operand::Implied);
+        Progress::Done
+    }
+
+    fn tick_pull(&mut self, mnemonic: Mnemonic) -> Progress {
+        match self.pipeline.cycle {
+            1 => {
+                self.read(self.pc);
+            }
+            2 => {
+                self.read(self.stack_address());
+            }
+            _ => {
+                self.run(mnemonic, Operand::Implied);
+                return Progress::Done;
+            }
+        }
+        Progress::Busy
+    }
+
+    fn tick_branch(&mut self, mnemonic: Mnemonic) -> Progress {
+        match self.pipeline.cycle {
+            1 => {
+                self.pipeline.data = self.fetch_operand_byte();
+                if !self.branch_condition(mnemonic) {
+                    return Progress::Done;
+                }
+            }
+            2 => {
+                self.read(self.pc);
+                let offset = self.pipeline.data as i8;
+                let target = self.pc.wrapping_add_signed(offset as i16);
+                self.pipeline.address = target;
+                // The low byte is added first, the page is fixed up on the next cycle
+                self.pc = (self.pc & 0xFF00) | (target & 0x00FF);
+                if self.pc == target {
+                    return Progress::Done;
+                }
+            }
+            _ => {
+                self.read(self.pc);
+                self.pc = self.pipeline.address;
+                return Progress::Done;
+            }
+        }
+        Progress::Busy
+    }
+
+    /// Forms the effective address, one bus access per cycle.
+    fn tick_addressing(&mut self, mnemonic: Mnemonic, mode: AddressingMode) -> Progress {
+        let cycle = self.pipeline.cycle;
+        match mode {
+            AddressingMode::Implied | AddressingMode::Accumulator => {
+                self.read(self.pc);
+                let operand = if mode == AddressingMode::Accumulator {
+                    Operand::Accumulator
+                } else {
+                    Operand::Implied
+                };
+                self.run(mnemonic, operand);
+                Progress::Done
+            }
+            AddressingMode::Immediate => {
+                let address = self.pc;
+                self.inc_pc();
+                self.run(mnemonic, Operand::Address(address));
+                Progress::Done
+            }
+            AddressingMode::ZeroPage => {
+                self.pipeline.address = self.fetch_operand_byte() as u16;
+                Progress::AddressReady
+            }
+            AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => {
+                if cycle == 1 {
+                    self.pipeline.address = self.fetch_operand_byte() as u16;
+                    return Progress::Busy;
+                }
+                let index = if mode == AddressingMode::ZeroPageX {
+                    self.x
+                } else {
+                    self.y
+                };
+                self.read(self.pipeline.address);
+                self.pipeline.address = (self.pipeline.address as u8).wrapping_add(index) as u16;
+                Progress::AddressReady
+            }
+            AddressingMode::Absolute => {
+                if cycle == 1 {
+                    self.pipeline.data = self.fetch_operand_byte();
+                    return Progress::Busy;
+                }
+                let msb = self.fetch_operand_byte();
+                self.pipeline.address = Self::get_address(self.pipeline.data, msb);
+                Progress::AddressReady
+            }
+            AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => match cycle {
+                1 => {
+                    self.pipeline.data = self.fetch_operand_byte();
+                    Progress::Busy
+                }
+                2 => {
+                    let msb = self.fetch_operand_byte();
+                    let index = if mode == AddressingMode::AbsoluteX {
+                        self.x
+                    } else {
+                        self.y
+                    };
+                    self.pipeline.base = Self::get_address(self.pipeline.data, msb);
+                    self.pipeline.address = self.pipeline.base.wrapping_add(index as u16);
+                    Progress::Busy
+                }
+                _ => self.tick_page_fixup(mnemonic),
+            },
+            AddressingMode::IndirectX => match cycle {
+                1 => {
+                    self.pipeline.pointer = self.fetch_operand_byte();
+                    Progress::Busy
+                }
+                2 => {
+                    self.read(self.pipeline.pointer as u16);
+                    self.pipeline.pointer = self.pipeline.pointer.wrapping_add(self.x);
+                    Progress::Busy
+                }
+                3 => {
+                    self.pipeline.data = self.read(self.pipeline.pointer as u16);
+                    Progress::Busy
+                }
+                _ => {
+                    let msb = self.read(self.pipeline.pointer.wrapping_add(1) as u16);
+                    self.pipeline.address = Self::get_address(self.pipeline.data, msb);
+                    Progress::AddressReady
+                }
+            },
+            AddressingMode::IndirectY => match cycle {
+                1 => {
+                    self.pipeline.pointer = self.fetch_operand_byte();
+                    Progress::Busy
+                }
+                2 => {
+                    self.pipeline.data = self.read(self.pipeline.pointer as u16);
+                    Progress::Busy
+                }
+                3 => {
+                    let msb = self.read(self.pipeline.pointer.wrapping_add(1) as u16);
+                    self.pipeline.base = Self::get_address(self.pipeline.data, msb);
+                    self.pipeline.address = self.pipeline.base.wrapping_add(self.y as u16);
+                    Progress::Busy
+                }
+                _ => self.tick_page_fixup(mnemonic),
+            },
+            AddressingMode::Indirect | AddressingMode::Relative => {
+                unreachable!("{mnemonic:?} is sequenced on its own")
+            }
+        }
+    }
+
+    /// Indexed modes first read from the address with an uncorrected page.
+    /// Reads that did not cross a page use that value, everything else treats
+    /// it as a dummy read and tries again on the fixed address.
+    fn tick_page_fixup(&mut self, mnemonic: Mnemonic) -> Progress {
+        let address = self.pipeline.address;
+        let uncorrected = (self.pipeline.base & 0xFF00) | (address & 0x00FF);
+        if Access::of(mnemonic) == Access::Read && uncorrected == address {
+            self.run(mnemonic, Operand::Address(address));
+            return Progress::Done;
+        }
+        self.read(uncorrected);
+        Progress::AddressReady
+    }
+
+    /// The cycles after the effective address is known.
+    fn tick_operation(&mut self, mnemonic: Mnemonic) -> Progress {
+        let address = self.pipeline.address;
+        match (
+            Access::of(mnemonic),
+            self.pipeline.cycle - self.pipeline.tail,
+        ) {
+            (Access::Read | Access::Write, _) => {
+                self.run(mnemonic, Operand::Address(address));
+                Progress::Done
+            }
+            (Access::ReadModifyWrite, 0) => {
+                self.pipeline.data = self.read(address);
+                Progress::Busy
+            }
+            (Access::ReadModifyWrite, 1) => {
+                // The unmodified value is written back while the ALU works
+                self.bus.write(address, self.pipeline.data);
+                Progress::Busy
+            }
+            (Access::ReadModifyWrite, _) => {
+                self.run(mnemonic, Operand::Latched(address, self.pipeline.data));
+                Progress::Done
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    enum BusCycle {
+        Read(u16, u8),
+        Write(u16, u8),
+    }
+    use BusCycle::*;
+
+    // A flat RAM that records every access
+    struct RecordingBus {
+        mem: Vec<u8>,
+        log: Vec<BusCycle>,
+    }
+
+    impl RecordingBus {
+        fn new() -> Self {
+            RecordingBus {
+                mem: vec![0; 0x10000],
+                log: Vec::new(),
+            }
+        }
+    }
+
+    impl Memory for RecordingBus {
+        fn read(&mut self, address: u16) -> u8 {
+            let value = self.mem[address as usize];
+            self.log.push(Read(address, value));
+            value
+        }
+        fn peek(&self, address: u16) -> u8 {
+            self.mem[address as usize]
+        }
+        fn write(&mut self, address: u16, value: u8) {
+            self.mem[address as usize] = value;
+            self.log.push(Write(address, value));
+        }
+    }
+
+    const START: u16 = 0x8000;
+
+    fn setup(prog: &[u8]) -> CPU<RecordingBus> {
+        let mut cpu = CPU::new(RecordingBus::new());
+        let start = START as usize;
+        cpu.bus.mem[start..start + prog.len()].copy_from_slice(prog);
+        cpu.set_pc(START);
+        cpu
+    }
+
+    /// Ticks one instruction and returns its bus activity.
+    fn tick_instruction(cpu: &mut CPU<RecordingBus>) -> Vec<BusCycle> {
+        cpu.bus.log.clear();
+        cpu.tick();
+        while !cpu.at_instruction_boundary() {
+            cpu.tick();
+        }
+        cpu.bus.log.clone()
+    }
+
+    #[test]
+    fn one_access_per_tick() {
+        let mut cpu = setup(&[0xEE, 0x34, 0x12]); // INC $1234
+        for cycle in 1..=6 {
+            cpu.tick();
+            assert_eq!(cpu.bus.log.len(), cycle);
+            assert_eq!(cpu.get_cycles(), cycle as u64);
+        }
+        assert!(cpu.at_instruction_boundary());
+    }
+
+    #[test]
+    fn absolute_x_read_page_cross() {
+        let mut cpu = setup(&[0xBD, 0xFF, 0x12]); // LDA $12FF,X
+        cpu.set_x(1);
+        cpu.bus.mem[0x1300] = 0x42;
+        let trace = tick_instruction(&mut cpu);
+        assert_eq!(
+            trace,
+            [
+                Read(0x8000, 0xBD),
+                Read(0x8001, 0xFF),
+                Read(0x8002, 0x12),
+                Read(0x1200, 0x00),
+                Read(0x1300, 0x42),
+            ]
+        );
+        assert_eq!(cpu.get_a(), 0x42);
+    }
+
+    #[test]
+    fn absolute_x_read_same_page() {
+        let mut cpu = setup(&[0xBD, 0x00, 0x12]); // LDA $1200,X
+        cpu.set_x(1);
+        let trace = tick_instruction(&mut cpu);
+        assert_eq!(trace.len(), 4);
+        assert_eq!(trace[3], Read(0x1201, 0x00));
+    }
+
+    #[test]
+    fn absolute_x_store_always_dummy_reads() {
+        let mut cpu = setup(&[0x9D, 0x00, 0x12]); // STA $1200,X
+        cpu.set_x(1);
+        cpu.set_a(0x99);
+        let trace = tick_instruction(&mut cpu);
+        assert_eq!(&trace[3..], [Read(0x1201, 0x00), Write(0x1201, 0x99)]);
+    }
+
+    #[test]
+    fn read_modify_write_writes_twice() {
+        let mut cpu = setup(&[0xE6, 0x10]); // INC $10
+        cpu.bus.mem[0x0010] = 0x41;
+        let trace = tick_instruction(&mut cpu);
+        assert_eq!(
+            &trace[2..],
+            [Read(0x0010, 0x41), Write(0x0010, 0x41), Write(0x0010, 0x42)]
+        );
+    }
+
+    #[test]
+    fn zero_page_x_dummy_reads_base() {
+        let mut cpu = setup(&[0xB5, 0xFF]); // LDA $FF,X
+        cpu.set_x(2);
+        let trace = tick_instruction(&mut cpu);
+        assert_eq!(&trace[2..], [Read(0x00FF, 0x00), Read(0x0001, 0x00)]);
+    }
+
+    #[test]
+    fn indirect_y_store() {
+        let mut cpu = setup(&[0x91, 0x20]); // STA ($20),Y
+        cpu.bus.mem[0x0020] = 0xFF;
+        cpu.bus.mem[0x0021] = 0x12;
+        cpu.set_y(1);
+        cpu.set_a(0x55);
+        let trace = tick_instruction(&mut cpu);
+        assert_eq!(
+            &trace[2..],
+            [
+                Read(0x0020, 0xFF),
+                Read(0x0021, 0x12),
+                Read(0x1200, 0x00),
+                Write(0x1300, 0x55)
+            ]
+        );
+    }
+
+    #[test]
+    fn jsr_and_rts_sequences() {
+        let mut cpu = setup(&[0x20, 0x00, 0x90]); // JSR $9000
+        cpu.bus.mem[0x9000] = 0x60; // RTS
+        let trace = tick_instruction(&mut cpu);
+        assert_eq!(
+            trace,
+            [
+                Read(0x8000, 0x20),
+                Read(0x8001, 0x00),
+                Read(0x01FF, 0x00),
+                Write(0x01FF, 0x80),
+                Write(0x01FE, 0x02),
+                Read(0x8002, 0x90),
+            ]
+        );
+        let trace = tick_instruction(&mut cpu);
+        assert_eq!(
+            trace,
+            [
+                Read(0x9000, 0x60),
+                Read(0x9001, 0x00),
+                Read(0x01FD, 0x00),
+                Read(0x01FE, 0x02),
+                Read(0x01FF, 0x80),
+                Read(0x8002, 0x90),
+            ]
+        );
+        assert_eq!(cpu.get_pc(), 0x8003);
+    }
+
+    #[test]
+    fn branch_taken_across_page() {
+        let mut cpu = setup(&[]);
+        cpu.bus.mem[0x80F0] = 0xD0; // BNE +$10
+        cpu.bus.mem[0x80F1] = 0x10;
+        cpu.set_pc(0x80F0);
+        let trace = tick_instruction(&mut cpu);
+        assert_eq!(
+            trace,
+            [
+                Read(0x80F0, 0xD0),
+                Read(0x80F1, 0x10),
+                Read(0x80F2, 0x00),
+                Read(0x8002, 0x00),
+            ]
+        );
+        assert_eq!(cpu.get_pc(), 0x8102);
+    }
+
+    #[test]
+    fn irq_sequence() {
+        let mut cpu = setup(&[0xEA]);
+        cpu.bus.mem[0xFFFE] = 0x00;
+        cpu.bus.mem[0xFFFF] = 0x90;
+        cpu.set_p(0x20);
+        cpu.irq(true);
+        let trace = tick_instruction(&mut cpu);
+        assert_eq!(
+            trace,
+            [
+                Read(0x8000, 0xEA),
+                Read(0x8000, 0xEA),
+                Write(0x01FF, 0x80),
+                Write(0x01FE, 0x00),
+                Write(0x01FD, 0x20),
+                Read(0xFFFE, 0x00),
+                Read(0xFFFF, 0x90),
+            ]
+        );
+        assert_eq!(cpu.get_pc(), 0x9000);
+        assert_eq!(cpu.get_p() & CpuFlags::INTERRUPT_DISABLE.bits(), 0x04);
+    }
+
+    #[test]
+    fn step_finishes_a_partial_instruction() {
+        let mut cpu = setup(&[0xEE, 0x34, 0x12, 0xEA]); // INC $1234, NOP
+        cpu.tick();
+        cpu.tick();
+        assert_eq!(cpu.step(), 4);
+        assert_eq!(cpu.bus.mem[0x1234], 0x01);
+        assert_eq!(cpu.step(), 2);
+    }
+
+    // xorshift, enough to scatter pointers and operands around memory
+    fn next(state: &mut u32) -> u8 {
+        *state ^= *state << 13;
+        *state ^= *state >> 17;
+        *state ^= *state << 5;
+        *state as u8
+    }
+
+    fn random_cpu(seed: u32, opcode: u8) -> CPU<RecordingBus> {
+        let mut state = seed;
+        let mut cpu = CPU::new(RecordingBus::new());
+        for byte in cpu.bus.mem.iter_mut() {
+            *byte = next(&mut state);
+        }
+        let pc = u16::from_le_bytes([next(&mut state), next(&mut state) & 0x7F]);
+        cpu.bus.mem[pc as usize] = opcode;
+        cpu.set_pc(pc);
+        cpu.set_a(next(&mut state));
+        cpu.set_x(next(&mut state));
+        cpu.set_y(next(&mut state));
+        cpu.set_sp(next(&mut state));
+        cpu.set_p(next(&mut state) | CpuFlags::UNUSED.bits());
+        cpu
+    }
+
+    #[test]
+    fn tick_matches_step_for_every_opcode() {
+        for opcode in 0..=0xFFu8 {
+            for seed in [1, 0xDEAD_BEEF, 0x1234_5678] {
+                let mut stepped = random_cpu(seed, opcode);
+                let mut ticked = random_cpu(seed, opcode);
+                let cycles = stepped.step();
+                let trace = tick_instruction(&mut ticked);
+                assert_eq!(trace.len() as u64, cycles, "opcode {opcode:#04X}");
+                assert_eq!(ticked.get_cycles(), cycles, "opcode {opcode:#04X}");
+                assert_eq!(
+                    (ticked.get_pc(), ticked.get_sp(), ticked.get_p()),
+                    (stepped.get_pc(), stepped.get_sp(), stepped.get_p()),
+                    "opcode {opcode:#04X}"
+                );
+                assert_eq!(
+                    (ticked.get_a(), ticked.get_x(), ticked.get_y()),
+                    (stepped.get_a(), stepped.get_x(), stepped.get_y()),
+                    "opcode {opcode:#04X}"
+                );
+                assert!(ticked.bus.mem == stepped.bus.mem, "opcode {opcode:#04X}");
+            }
+        }
+    }
+}
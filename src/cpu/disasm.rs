@@ -0,0 +1,199 @@
+//! 6502 disassembler.
+//!
+//! Decodes instructions from any `Memory` with the same opcode table the CPU
+//! executes, so every official and unofficial encoding is covered. Operands
+//! are rendered in the usual assembler syntax, with branch targets resolved
+//! to absolute addresses.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use super::opcodes::{AddressingMode, Mnemonic, OPCODES};
+use crate::bus::Memory;
+
+/// One decoded instruction.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Instruction {
+    pub address: u16,
+    pub opcode: u8,
+    pub mnemonic: Mnemonic,
+    pub mode: AddressingMode,
+    /// Raw operand bytes, little endian. Zero when the mode has none.
+    pub operand: u16,
+}
+
+impl Instruction {
+    pub fn is_official(&self) -> bool {
+        OPCODES[self.opcode as usize].official
+    }
+
+    /// Where a branch goes when taken.
+    pub fn branch_target(&self) -> Option<u16> {
+        (self.mode == AddressingMode::Relative).then(|| {
+            self.address
+                .wrapping_add(2)
+                .wrapping_add_signed(self.operand as u8 as i8 as i16)
+        })
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use AddressingMode::*;
+
+        write!(f, "{:?}", self.mnemonic)?;
+        let operand = self.operand;
+        match self.mode {
+            Implied => Ok(()),
+            Accumulator => write!(f, " A"),
+            Immediate => write!(f, " #${operand:02X}"),
+            ZeroPage => write!(f, " ${operand:02X}"),
+            ZeroPageX => write!(f, " ${operand:02X},X"),
+            ZeroPageY => write!(f, " ${operand:02X},Y"),
+            Absolute => write!(f, " ${operand:04X}"),
+            AbsoluteX => write!(f, " ${operand:04X},X"),
+            AbsoluteY => write!(f, " ${operand:04X},Y"),
+            Indirect => write!(f, " (${operand:04X})"),
+            IndirectX => write!(f, " (${operand:02X},X)"),
+            IndirectY => write!(f, " (${operand:02X}),Y"),
+            Relative => write!(f, " ${:04X}", self.branch_target().unwrap()),
+        }
+    }
+}
+
+/// Decodes the instruction at `addr` and returns it with its length in bytes.
+///
+/// Operand bytes past $FFFF wrap around to $0000, as the CPU fetches them.
+pub fn disassemble(mem: &impl Memory, addr: u16) -> (Instruction, u16) {
+    let opcode = mem.peek(addr);
+    let op = OPCODES[opcode as usize];
+    let operand = match op.mode.operand_bytes() {
+        0 => 0,
+        1 => mem.peek(addr.wrapping_add(1)) as u16,
+        _ => u16::from_le_bytes([
+            mem.peek(addr.wrapping_add(1)),
+            mem.peek(addr.wrapping_add(2)),
+        ]),
+    };
+    let instruction = Instruction {
+        address: addr,
+        opcode,
+        mnemonic: op.mnemonic,
+        mode: op.mode,
+        operand,
+    };
+    (instruction, op.bytes as u16)
+}
+
+/// Decodes consecutive instructions starting at the beginning of `range`,
+/// stopping with the first one that starts past its end.
+pub fn disassemble_range(mem: &impl Memory, range: RangeInclusive<u16>) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let end = *range.end() as u32;
+    let mut addr = *range.start() as u32;
+    while addr <= end {
+        let (instruction, len) = disassemble(mem, addr as u16);
+        instructions.push(instruction);
+        addr += len as u32;
+    }
+    instructions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Rom {
+        mem: Vec<u8>,
+    }
+
+    impl Rom {
+        fn at(start: u16, bytes: &[u8]) -> Self {
+            let mut mem = vec![0; 0x10000];
+            mem[start as usize..start as usize + bytes.len()].copy_from_slice(bytes);
+            Rom { mem }
+        }
+    }
+
+    impl Memory for Rom {
+        fn read(&mut self, address: u16) -> u8 {
+            self.peek(address)
+        }
+
+        fn peek(&self, address: u16) -> u8 {
+            self.mem[address as usize]
+        }
+
+        fn write(&mut self, address: u16, value: u8) {
+            self.mem[address as usize] = value;
+        }
+    }
+
+    fn text(bytes: &[u8]) -> String {
+        disassemble(&Rom::at(0x8000, bytes), 0x8000).0.to_string()
+    }
+
+    #[test]
+    fn renders_every_addressing_mode() {
+        assert_eq!(text(&[0xEA]), "NOP");
+        assert_eq!(text(&[0x0A]), "ASL A");
+        assert_eq!(text(&[0xA9, 0x42]), "LDA #$42");
+        assert_eq!(text(&[0xA5, 0x20]), "LDA $20");
+        assert_eq!(text(&[0xB5, 0x20]), "LDA $20,X");
+        assert_eq!(text(&[0xB6, 0x20]), "LDX $20,Y");
+        assert_eq!(text(&[0xAD, 0x34, 0x12]), "LDA $1234");
+        assert_eq!(text(&[0xBD, 0x34, 0x12]), "LDA $1234,X");
+        assert_eq!(text(&[0xB9, 0x34, 0x12]), "LDA $1234,Y");
+        assert_eq!(text(&[0x6C, 0xFF, 0x10]), "JMP ($10FF)");
+        assert_eq!(text(&[0xA1, 0x20]), "LDA ($20,X)");
+        assert_eq!(text(&[0xB1, 0x20]), "LDA ($20),Y");
+    }
+
+    #[test]
+    fn resolves_branch_targets() {
+        assert_eq!(text(&[0xD0, 0x10]), "BNE $8012");
+        assert_eq!(text(&[0xD0, 0xFE]), "BNE $8000");
+        assert_eq!(text(&[0x10, 0x80]), "BPL $7F82");
+    }
+
+    #[test]
+    fn renders_unofficial_opcodes() {
+        let (instruction, len) = disassemble(&Rom::at(0x8000, &[0xA7, 0x10]), 0x8000);
+        assert_eq!(instruction.to_string(), "LAX $10");
+        assert_eq!(len, 2);
+        assert!(!instruction.is_official());
+        assert_eq!(text(&[0x02]), "KIL");
+        assert_eq!(text(&[0x1C, 0x00, 0x20]), "NOP $2000,X");
+    }
+
+    #[test]
+    fn lengths_come_from_the_mode() {
+        let rom = Rom::at(0x8000, &[0x20, 0x00, 0x90]);
+        let (instruction, len) = disassemble(&rom, 0x8000);
+        assert_eq!(len, 3);
+        assert_eq!(instruction.operand, 0x9000);
+        assert!(instruction.is_official());
+    }
+
+    #[test]
+    fn range_walks_instruction_boundaries() {
+        // LDX #$00; INX; BNE loop; RTS
+        let rom = Rom::at(0xC000, &[0xA2, 0x00, 0xE8, 0xD0, 0xFD, 0x60]);
+        let listing = disassemble_range(&rom, 0xC000..=0xC005)
+            .iter()
+            .map(|i| format!("{:04X} {i}", i.address))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            listing,
+            ["C000 LDX #$00", "C002 INX", "C003 BNE $C002", "C005 RTS"]
+        );
+    }
+
+    #[test]
+    fn range_stops_at_end_of_memory() {
+        let rom = Rom::at(0xFFFD, &[0xEA, 0xEA, 0xEA]);
+        let listing = disassemble_range(&rom, 0xFFFD..=0xFFFF);
+        assert_eq!(listing.len(), 3);
+        assert_eq!(listing[2].address, 0xFFFF);
+    }
+}
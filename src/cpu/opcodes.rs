@@ -0,0 +1,481 @@
+//! Static description of all 256 opcodes of the 2A03.
+//!
+//! The table drives `CPU::step` as well as tooling that needs to know how an
+//! instruction is encoded without executing it (disassembler, tracer).
+
+use AddressingMode::*;
+use Mnemonic::*;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX, // ($nn,X)
+    IndirectY, // ($nn),Y
+    Relative,
+}
+
+impl AddressingMode {
+    /// Number of operand bytes following the opcode.
+    pub const fn operand_bytes(self) -> u8 {
+        match self {
+            Implied | Accumulator => 0,
+            Immediate | ZeroPage | ZeroPageX | ZeroPageY | IndirectX | IndirectY | Relative => 1,
+            Absolute | AbsoluteX | AbsoluteY | Indirect => 2,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Mnemonic {
+    // Official
+    ADC,
+    AND,
+    ASL,
+    BCC,
+    BCS,
+    BEQ,
+    BIT,
+    BMI,
+    BNE,
+    BPL,
+    BRK,
+    BVC,
+    BVS,
+    CLC,
+    CLD,
+    CLI,
+    CLV,
+    CMP,
+    CPX,
+    CPY,
+    DEC,
+    DEX,
+    DEY,
+    EOR,
+    INC,
+    INX,
+    INY,
+    JMP,
+    JSR,
+    LDA,
+    LDX,
+    LDY,
+    LSR,
+    NOP,
+    ORA,
+    PHA,
+    PHP,
+    PLA,
+    PLP,
+    ROL,
+    ROR,
+    RTI,
+    RTS,
+    SBC,
+    SEC,
+    SED,
+    SEI,
+    STA,
+    STX,
+    STY,
+    TAX,
+    TAY,
+    TSX,
+    TXA,
+    TXS,
+    TYA,
+    // Unofficial
+    ALR,
+    ANC,
+    ANE,
+    ARR,
+    AXS,
+    DCP,
+    ISC,
+    KIL,
+    LAS,
+    LAX,
+    LXA,
+    RLA,
+    RRA,
+    SAX,
+    SHA,
+    SHX,
+    SHY,
+    SLO,
+    SRE,
+    TAS,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Opcode {
+    pub mnemonic: Mnemonic,
+    pub mode: AddressingMode,
+    /// Instruction length including the opcode byte
+    pub bytes: u8,
+    /// Base cycle count, without page cross or branch penalties
+    pub cycles: u8,
+    /// Takes an extra cycle when indexing crosses a page boundary
+    pub page_cross_penalty: bool,
+    pub official: bool,
+}
+
+impl Opcode {
+    const fn with_page_cross_penalty(mut self) -> Self {
+        self.page_cross_penalty = true;
+        self
+    }
+}
+
+const fn official(mnemonic: Mnemonic, mode: AddressingMode, cycles: u8) -> Opcode {
+    Opcode {
+        mnemonic,
+        mode,
+        bytes: 1 + mode.operand_bytes(),
+        cycles,
+        page_cross_penalty: false,
+        official: true,
+    }
+}
+
+const fn unofficial(mnemonic: Mnemonic, mode: AddressingMode, cycles: u8) -> Opcode {
+    Opcode {
+        official: false,
+        ..official(mnemonic, mode, cycles)
+    }
+}
+
+/// Indexed by opcode.
+#[rustfmt::skip]
+pub static OPCODES: [Opcode; 256] = [
+    official(BRK, Implied, 7), // 0x00
+    official(ORA, IndirectX, 6), // 0x01
+    unofficial(KIL, Implied, 2), // 0x02
+    unofficial(SLO, IndirectX, 8), // 0x03
+    unofficial(NOP, ZeroPage, 3), // 0x04
+    official(ORA, ZeroPage, 3), // 0x05
+    official(ASL, ZeroPage, 5), // 0x06
+    unofficial(SLO, ZeroPage, 5), // 0x07
+    official(PHP, Implied, 3), // 0x08
+    official(ORA, Immediate, 2), // 0x09
+    official(ASL, Accumulator, 2), // 0x0A
+    unofficial(ANC, Immediate, 2), // 0x0B
+    unofficial(NOP, Absolute, 4), // 0x0C
+    official(ORA, Absolute, 4), // 0x0D
+    official(ASL, Absolute, 6), // 0x0E
+    unofficial(SLO, Absolute, 6), // 0x0F
+    official(BPL, Relative, 2), // 0x10
+    official(ORA, IndirectY, 5).with_page_cross_penalty(), // 0x11
+    unofficial(KIL, Implied, 2), // 0x12
+    unofficial(SLO, IndirectY, 8), // 0x13
+    unofficial(NOP, ZeroPageX, 4), // 0x14
+    official(ORA, ZeroPageX, 4), // 0x15
+    official(ASL, ZeroPageX, 6), // 0x16
+    unofficial(SLO, ZeroPageX, 6), // 0x17
+    official(CLC, Implied, 2), // 0x18
+    official(ORA, AbsoluteY, 4).with_page_cross_penalty(), // 0x19
+    unofficial(NOP, Implied, 2), // 0x1A
+    unofficial(SLO, AbsoluteY, 7), // 0x1B
+    unofficial(NOP, AbsoluteX, 4).with_page_cross_penalty(), // 0x1C
+    official(ORA, AbsoluteX, 4).with_page_cross_penalty(), // 0x1D
+    official(ASL, AbsoluteX, 7), // 0x1E
+    unofficial(SLO, AbsoluteX, 7), // 0x1F
+    official(JSR, Absolute, 6), // 0x20
+    official(AND, IndirectX, 6), // 0x21
+    unofficial(KIL, Implied, 2), // 0x22
+    unofficial(RLA, IndirectX, 8), // 0x23
+    official(BIT, ZeroPage, 3), // 0x24
+    official(AND, ZeroPage, 3), // 0x25
+    official(ROL, ZeroPage, 5), // 0x26
+    unofficial(RLA, ZeroPage, 5), // 0x27
+    official(PLP, Implied, 4), // 0x28
+    official(AND, Immediate, 2), // 0x29
+    official(ROL, Accumulator, 2), // 0x2A
+    unofficial(ANC, Immediate, 2), // 0x2B
+    official(BIT, Absolute, 4), // 0x2C
+    official(AND, Absolute, 4), // 0x2D
+    official(ROL, Absolute, 6), // 0x2E
+    unofficial(RLA, Absolute, 6), // 0x2F
+    official(BMI, Relative, 2), // 0x30
+    official(AND, IndirectY, 5).with_page_cross_penalty(), // 0x31
+    unofficial(KIL, Implied, 2), // 0x32
+    unofficial(RLA, IndirectY, 8), // 0x33
+    unofficial(NOP, ZeroPageX, 4), // 0x34
+    official(AND, ZeroPageX, 4), // 0x35
+    official(ROL, ZeroPageX, 6), // 0x36
+    unofficial(RLA, ZeroPageX, 6), // 0x37
+    official(SEC, Implied, 2), // 0x38
+    official(AND, AbsoluteY, 4).with_page_cross_penalty(), // 0x39
+    unofficial(NOP, Implied, 2), // 0x3A
+    unofficial(RLA, AbsoluteY, 7), // 0x3B
+    unofficial(NOP, AbsoluteX, 4).with_page_cross_penalty(), // 0x3C
+    official(AND, AbsoluteX, 4).with_page_cross_penalty(), // 0x3D
+    official(ROL, AbsoluteX, 7), // 0x3E
+    unofficial(RLA, AbsoluteX, 7), // 0x3F
+    official(RTI, Implied, 6), // 0x40
+    official(EOR, IndirectX, 6), // 0x41
+    unofficial(KIL, Implied, 2), // 0x42
+    unofficial(SRE, IndirectX, 8), // 0x43
+    unofficial(NOP, ZeroPage, 3), // 0x44
+    official(EOR, ZeroPage, 3), // 0x45
+    official(LSR, ZeroPage, 5), // 0x46
+    unofficial(SRE, ZeroPage, 5), // 0x47
+    official(PHA, Implied, 3), // 0x48
+    official(EOR, Immediate, 2), // 0x49
+    official(LSR, Accumulator, 2), // 0x4A
+    unofficial(ALR, Immediate, 2), // 0x4B
+    official(JMP, Absolute, 3), // 0x4C
+    official(EOR, Absolute, 4), // 0x4D
+    official(LSR, Absolute, 6), // 0x4E
+    unofficial(SRE, Absolute, 6), // 0x4F
+    official(BVC, Relative, 2), // 0x50
+    official(EOR, IndirectY, 5).with_page_cross_penalty(), // 0x51
+    unofficial(KIL, Implied, 2), // 0x52
+    unofficial(SRE, IndirectY, 8), // 0x53
+    unofficial(NOP, ZeroPageX, 4), // 0x54
+    official(EOR, ZeroPageX, 4), // 0x55
+    official(LSR, ZeroPageX, 6), // 0x56
+    unofficial(SRE, ZeroPageX, 6), // 0x57
+    official(CLI, Implied, 2), // 0x58
+    official(EOR, AbsoluteY, 4).with_page_cross_penalty(), // 0x59
+    unofficial(NOP, Implied, 2), // 0x5A
+    unofficial(SRE, AbsoluteY, 7), // 0x5B
+    unofficial(NOP, AbsoluteX, 4).with_page_cross_penalty(), // 0x5C
+    official(EOR, AbsoluteX, 4).with_page_cross_penalty(), // 0x5D
+    official(LSR, AbsoluteX, 7), // 0x5E
+    unofficial(SRE, AbsoluteX, 7), // 0x5F
+    official(RTS, Implied, 6), // 0x60
+    official(ADC, IndirectX, 6), // 0x61
+    unofficial(KIL, Implied, 2), // 0x62
+    unofficial(RRA, IndirectX, 8), // 0x63
+    unofficial(NOP, ZeroPage, 3), // 0x64
+    official(ADC, ZeroPage, 3), // 0x65
+    official(ROR, ZeroPage, 5), // 0x66
+    unofficial(RRA, ZeroPage, 5), // 0x67
+    official(PLA, Implied, 4), // 0x68
+    official(ADC, Immediate, 2), // 0x69
+    official(ROR, Accumulator, 2), // 0x6A
+    unofficial(ARR, Immediate, 2), // 0x6B
+    official(JMP, Indirect, 5), // 0x6C
+    official(ADC, Absolute, 4), // 0x6D
+    official(ROR, Absolute, 6), // 0x6E
+    unofficial(RRA, Absolute, 6), // 0x6F
+    official(BVS, Relative, 2), // 0x70
+    official(ADC, IndirectY, 5).with_page_cross_penalty(), // 0x71
+    unofficial(KIL, Implied, 2), // 0x72
+    unofficial(RRA, IndirectY, 8), // 0x73
+    unofficial(NOP, ZeroPageX, 4), // 0x74
+    official(ADC, ZeroPageX, 4), // 0x75
+    official(ROR, ZeroPageX, 6), // 0x76
+    unofficial(RRA, ZeroPageX, 6), // 0x77
+    official(SEI, Implied, 2), // 0x78
+    official(ADC, AbsoluteY, 4).with_page_cross_penalty(), // 0x79
+    unofficial(NOP, Implied, 2), // 0x7A
+    unofficial(RRA, AbsoluteY, 7), // 0x7B
+    unofficial(NOP, AbsoluteX, 4).with_page_cross_penalty(), // 0x7C
+    official(ADC, AbsoluteX, 4).with_page_cross_penalty(), // 0x7D
+    official(ROR, AbsoluteX, 7), // 0x7E
+    unofficial(RRA, AbsoluteX, 7), // 0x7F
+    unofficial(NOP, Immediate, 2), // 0x80
+    official(STA, IndirectX, 6), // 0x81
+    unofficial(NOP, Immediate, 2), // 0x82
+    unofficial(SAX, IndirectX, 6), // 0x83
+    official(STY, ZeroPage, 3), // 0x84
+    official(STA, ZeroPage, 3), // 0x85
+    official(STX, ZeroPage, 3), // 0x86
+    unofficial(SAX, ZeroPage, 3), // 0x87
+    official(DEY, Implied, 2), // 0x88
+    unofficial(NOP, Immediate, 2), // 0x89
+    official(TXA, Implied, 2), // 0x8A
+    unofficial(ANE, Immediate, 2), // 0x8B
+    official(STY, Absolute, 4), // 0x8C
+    official(STA, Absolute, 4), // 0x8D
+    official(STX, Absolute, 4), // 0x8E
+    unofficial(SAX, Absolute, 4), // 0x8F
+    official(BCC, Relative, 2), // 0x90
+    official(STA, IndirectY, 6), // 0x91
+    unofficial(KIL, Implied, 2), // 0x92
+    unofficial(SHA, IndirectY, 6), // 0x93
+    official(STY, ZeroPageX, 4), // 0x94
+    official(STA, ZeroPageX, 4), // 0x95
+    official(STX, ZeroPageY, 4), // 0x96
+    unofficial(SAX, ZeroPageY, 4), // 0x97
+    official(TYA, Implied, 2), // 0x98
+    official(STA, AbsoluteY, 5), // 0x99
+    official(TXS, Implied, 2), // 0x9A
+    unofficial(TAS, AbsoluteY, 5), // 0x9B
+    unofficial(SHY, AbsoluteX, 5), // 0x9C
+    official(STA, AbsoluteX, 5), // 0x9D
+    unofficial(SHX, AbsoluteY, 5), // 0x9E
+    unofficial(SHA, AbsoluteY, 5), // 0x9F
+    official(LDY, Immediate, 2), // 0xA0
+    official(LDA, IndirectX, 6), // 0xA1
+    official(LDX, Immediate, 2), // 0xA2
+    unofficial(LAX, IndirectX, 6), // 0xA3
+    official(LDY, ZeroPage, 3), // 0xA4
+    official(LDA, ZeroPage, 3), // 0xA5
+    official(LDX, ZeroPage, 3), // 0xA6
+    unofficial(LAX, ZeroPage, 3), // 0xA7
+    official(TAY, Implied, 2), // 0xA8
+    official(LDA, Immediate, 2), // 0xA9
+    official(TAX, Implied, 2), // 0xAA
+    unofficial(LXA, Immediate, 2), // 0xAB
+    official(LDY, Absolute, 4), // 0xAC
+    official(LDA, Absolute, 4), // 0xAD
+    official(LDX, Absolute, 4), // 0xAE
+    unofficial(LAX, Absolute, 4), // 0xAF
+    official(BCS, Relative, 2), // 0xB0
+    official(LDA, IndirectY, 5).with_page_cross_penalty(), // 0xB1
+    unofficial(KIL, Implied, 2), // 0xB2
+    unofficial(LAX, IndirectY, 5).with_page_cross_penalty(), // 0xB3
+    official(LDY, ZeroPageX, 4), // 0xB4
+    official(LDA, ZeroPageX, 4), // 0xB5
+    official(LDX, ZeroPageY, 4), // 0xB6
+    unofficial(LAX, ZeroPageY, 4), // 0xB7
+    official(CLV, Implied, 2), // 0xB8
+    official(LDA, AbsoluteY, 4).with_page_cross_penalty(), // 0xB9
+    official(TSX, Implied, 2), // 0xBA
+    unofficial(LAS, AbsoluteY, 4).with_page_cross_penalty(), // 0xBB
+    official(LDY, AbsoluteX, 4).with_page_cross_penalty(), // 0xBC
+    official(LDA, AbsoluteX, 4).with_page_cross_penalty(), // 0xBD
+    official(LDX, AbsoluteY, 4).with_page_cross_penalty(), // 0xBE
+    unofficial(LAX, AbsoluteY, 4).with_page_cross_penalty(), // 0xBF
+    official(CPY, Immediate, 2), // 0xC0
+    official(CMP, IndirectX, 6), // 0xC1
+    unofficial(NOP, Immediate, 2), // 0xC2
+    unofficial(DCP, IndirectX, 8), // 0xC3
+    official(CPY, ZeroPage, 3), // 0xC4
+    official(CMP, ZeroPage, 3), // 0xC5
+    official(DEC, ZeroPage, 5), // 0xC6
+    unofficial(DCP, ZeroPage, 5), // 0xC7
+    official(INY, Implied, 2), // 0xC8
+    official(CMP, Immediate, 2), // 0xC9
+    official(DEX, Implied, 2), // 0xCA
+    unofficial(AXS, Immediate, 2), // 0xCB
+    official(CPY, Absolute, 4), // 0xCC
+    official(CMP, Absolute, 4), // 0xCD
+    official(DEC, Absolute, 6), // 0xCE
+    unofficial(DCP, Absolute, 6), // 0xCF
+    official(BNE, Relative, 2), // 0xD0
+    official(CMP, IndirectY, 5).with_page_cross_penalty(), // 0xD1
+    unofficial(KIL, Implied, 2), // 0xD2
+    unofficial(DCP, IndirectY, 8), // 0xD3
+    unofficial(NOP, ZeroPageX, 4), // 0xD4
+    official(CMP, ZeroPageX, 4), // 0xD5
+    official(DEC, ZeroPageX, 6), // 0xD6
+    unofficial(DCP, ZeroPageX, 6), // 0xD7
+    official(CLD, Implied, 2), // 0xD8
+    official(CMP, AbsoluteY, 4).with_page_cross_penalty(), // 0xD9
+    unofficial(NOP, Implied, 2), // 0xDA
+    unofficial(DCP, AbsoluteY, 7), // 0xDB
+    unofficial(NOP, AbsoluteX, 4).with_page_cross_penalty(), // 0xDC
+    official(CMP, AbsoluteX, 4).with_page_cross_penalty(), // 0xDD
+    official(DEC, AbsoluteX, 7), // 0xDE
+    unofficial(DCP, AbsoluteX, 7), // 0xDF
+    official(CPX, Immediate, 2), // 0xE0
+    official(SBC, IndirectX, 6), // 0xE1
+    unofficial(NOP, Immediate, 2), // 0xE2
+    unofficial(ISC, IndirectX, 8), // 0xE3
+    official(CPX, ZeroPage, 3), // 0xE4
+    official(SBC, ZeroPage, 3), // 0xE5
+    official(INC, ZeroPage, 5), // 0xE6
+    unofficial(ISC, ZeroPage, 5), // 0xE7
+    official(INX, Implied, 2), // 0xE8
+    official(SBC, Immediate, 2), // 0xE9
+    official(NOP, Implied, 2), // 0xEA
+    unofficial(SBC, Immediate, 2), // 0xEB
+    official(CPX, Absolute, 4), // 0xEC
+    official(SBC, Absolute, 4), // 0xED
+    official(INC, Absolute, 6), // 0xEE
+    unofficial(ISC, Absolute, 6), // 0xEF
+    official(BEQ, Relative, 2), // 0xF0
+    official(SBC, IndirectY, 5).with_page_cross_penalty(), // 0xF1
+    unofficial(KIL, Implied, 2), // 0xF2
+    unofficial(ISC, IndirectY, 8), // 0xF3
+    unofficial(NOP, ZeroPageX, 4), // 0xF4
+    official(SBC, ZeroPageX, 4), // 0xF5
+    official(INC, ZeroPageX, 6), // 0xF6
+    unofficial(ISC, ZeroPageX, 6), // 0xF7
+    official(SED, Implied, 2), // 0xF8
+    official(SBC, AbsoluteY, 4).with_page_cross_penalty(), // 0xF9
+    unofficial(NOP, Implied, 2), // 0xFA
+    unofficial(ISC, AbsoluteY, 7), // 0xFB
+    unofficial(NOP, AbsoluteX, 4).with_page_cross_penalty(), // 0xFC
+    official(SBC, AbsoluteX, 4).with_page_cross_penalty(), // 0xFD
+    official(INC, AbsoluteX, 7), // 0xFE
+    unofficial(ISC, AbsoluteX, 7), // 0xFF
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Base cycles of every opcode, as listed in the NMOS 6502 datasheets
+    #[rustfmt::skip]
+    const REFERENCE_CYCLES: [u8; 256] = [
+        7, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6,
+        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6,
+        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        6, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6,
+        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6,
+        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
+        2, 6, 2, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5,
+        2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
+        2, 5, 2, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4,
+        2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    ];
+
+    #[test]
+    fn cycles_match_reference() {
+        for (code, op) in OPCODES.iter().enumerate() {
+            assert_eq!(op.cycles, REFERENCE_CYCLES[code], "opcode {code:#04X}");
+        }
+    }
+
+    #[test]
+    fn unofficial_opcode_count() {
+        assert_eq!(OPCODES.iter().filter(|op| !op.official).count(), 105);
+        assert_eq!(OPCODES.iter().filter(|op| op.mnemonic == KIL).count(), 12);
+    }
+
+    #[test]
+    fn bytes_follow_addressing_mode() {
+        assert_eq!(OPCODES[0xEA].bytes, 1); // NOP
+        assert_eq!(OPCODES[0xA9].bytes, 2); // LDA #$nn
+        assert_eq!(OPCODES[0x10].bytes, 2); // BPL
+        assert_eq!(OPCODES[0x6C].bytes, 3); // JMP ($nnnn)
+        assert_eq!(OPCODES[0x9E].bytes, 3); // SHX $nnnn,Y
+    }
+
+    #[test]
+    fn page_cross_penalty_only_on_indexed_reads() {
+        for op in OPCODES.iter().filter(|op| op.page_cross_penalty) {
+            assert!(
+                matches!(op.mode, AbsoluteX | AbsoluteY | IndirectY),
+                "{op:?}"
+            );
+            assert!(
+                !matches!(op.mnemonic, STA | SHA | SHX | SHY | TAS),
+                "{op:?}"
+            );
+        }
+        assert!(OPCODES[0xBD].page_cross_penalty); // LDA $nnnn,X
+        assert!(!OPCODES[0x9D].page_cross_penalty); // STA $nnnn,X
+        assert!(!OPCODES[0xDE].page_cross_penalty); // DEC $nnnn,X
+    }
+}
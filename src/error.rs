@@ -0,0 +1,77 @@
+use std::fmt;
+
+use crate::cartridge::RomError;
+
+/// Everything that can stop emulation, reported by the fallible `try_step`
+/// APIs instead of aborting the process.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum EmuError {
+    /// The CPU read an address nothing responds to.
+    UnmappedRead { address: u16 },
+    /// The CPU wrote an address nothing responds to.
+    UnmappedWrite { address: u16, value: u8 },
+    /// An unofficial opcode under `IllegalOpcodePolicy::Error`.
+    IllegalOpcode { opcode: u8, pc: u16 },
+    /// A KIL opcode halted the CPU. Only a reset recovers it.
+    CpuJammed { pc: u16 },
+    /// The cartridge image could not be loaded.
+    BadRom(RomError),
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmuError::UnmappedRead { address } => {
+                write!(f, "Read from unmapped address ${address:04X}")
+            }
+            EmuError::UnmappedWrite { address, value } => {
+                write!(
+                    f,
+                    "Write of ${value:02X} to unmapped address ${address:04X}"
+                )
+            }
+            EmuError::IllegalOpcode { opcode, pc } => {
+                write!(f, "Illegal opcode ${opcode:02X} at ${pc:04X}")
+            }
+            EmuError::CpuJammed { pc } => write!(f, "CPU jammed at ${pc:04X}"),
+            EmuError::BadRom(reason) => write!(f, "Bad ROM: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for EmuError {}
+
+impl From<RomError> for EmuError {
+    fn from(error: RomError) -> Self {
+        EmuError::BadRom(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_include_addresses() {
+        assert_eq!(
+            EmuError::UnmappedWrite {
+                address: 0x5000,
+                value: 0x0A
+            }
+            .to_string(),
+            "Write of $0A to unmapped address $5000"
+        );
+        assert_eq!(
+            EmuError::IllegalOpcode {
+                opcode: 0xA7,
+                pc: 0x8000
+            }
+            .to_string(),
+            "Illegal opcode $A7 at $8000"
+        );
+        assert_eq!(
+            EmuError::CpuJammed { pc: 0xC123 }.to_string(),
+            "CPU jammed at $C123"
+        );
+    }
+}
@@ -1,7 +1,108 @@
-struct Input {}
+use bitflags::bitflags;
+
+bitflags! {
+    /// Standard controller buttons, in the order they are shifted out.
+    #[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+    pub struct Buttons: u8 {
+        const A = 0x01;
+        const B = 0x02;
+        const SELECT = 0x04;
+        const START = 0x08;
+        const UP = 0x10;
+        const DOWN = 0x20;
+        const LEFT = 0x40;
+        const RIGHT = 0x80;
+    }
+}
+
+/// Two standard controllers on $4016 and $4017.
+///
+/// Writing 1 to bit 0 of $4016 holds the shift registers in parallel load.
+/// Once it is released every read returns the next button in D0, and 1s
+/// after all eight have been read.
+pub struct Input {
+    buttons: [Buttons; 2],
+    shift: [u8; 2],
+    strobe: bool,
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Input {
+            buttons: [Buttons::empty(); 2],
+            shift: [0; 2],
+            strobe: false,
+        }
+    }
+
+    pub fn set_buttons(&mut self, port: usize, buttons: Buttons) {
+        self.buttons[port] = buttons;
+        if self.strobe {
+            self.shift[port] = buttons.bits();
+        }
+    }
+
+    /// CPU write of $4016.
+    pub fn write_strobe(&mut self, value: u8) {
+        self.strobe = value & 0x01 != 0;
+        if self.strobe {
+            self.shift = self.buttons.map(|buttons| buttons.bits());
+        }
+    }
+
+    /// CPU read of $4016 (port 0) or $4017 (port 1), shifting the next button in.
+    pub fn read(&mut self, port: usize) -> u8 {
+        let value = self.peek(port);
+        if !self.strobe {
+            self.shift[port] = (self.shift[port] >> 1) | 0x80;
+        }
+        value
+    }
+
+    pub fn peek(&self, port: usize) -> u8 {
+        if self.strobe {
+            self.buttons[port].bits() & 0x01
+        } else {
+            self.shift[port] & 0x01
+        }
+    }
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    #[test]
+    fn construct_input() {
+        let mut input = Input::new();
+        assert_eq!(input.read(0), 0);
+    }
+
+    #[test]
+    fn reads_shift_out_buttons_then_ones() {
+        let mut input = Input::new();
+        input.set_buttons(0, Buttons::A | Buttons::START | Buttons::RIGHT);
+        input.write_strobe(1);
+        input.write_strobe(0);
+        let bits = (0..10).map(|_| input.read(0)).collect::<Vec<_>>();
+        assert_eq!(bits, [1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+        // The second controller was loaded too, with nothing pressed
+        assert_eq!(input.read(1), 0);
+    }
+
     #[test]
-    fn construct_input() {}
+    fn strobe_high_keeps_reporting_a() {
+        let mut input = Input::new();
+        input.set_buttons(1, Buttons::A);
+        input.write_strobe(1);
+        assert_eq!(input.read(1), 1);
+        assert_eq!(input.read(1), 1);
+        assert_eq!(input.peek(1), 1);
+    }
 }
@@ -1,7 +1,28 @@
+#[macro_export]
+macro_rules! log{
+    ($($args:tt)*) => {
+        let log_message = format_args!($($args)*);
+        println!("[NES] {}", log_message);
+    };
+}
+
+/// Assembles 6502 source lines into bytes, panicking on errors. Meant for
+/// tests and fixtures, e.g. `asm!("LDA #$42", "STA $10")`.
+#[macro_export]
+macro_rules! asm {
+    ($($line:literal),+ $(,)?) => {
+        match $crate::cpu::assembler::assemble(concat!($($line, "\n"),+)) {
+            Ok(program) => program.bytes,
+            Err(error) => panic!("{}", error),
+        }
+    };
+}
+
 pub mod apu;
 pub mod bus;
 pub mod cartridge;
 pub mod cpu;
+pub mod error;
 pub mod input;
 pub mod memory;
 pub mod nes;
@@ -1,10 +1,4 @@
-#[macro_export]
-macro_rules! log{
-    ($($args:tt)*) => {
-        let log_message = format_args!($($args)*);
-        println!("[NES] {}", log_message);
-    };
-}
+use nes_emu::log;
 
 fn main() {
     log!("Starting NES emu...");
@@ -1,4 +1,4 @@
-struct Memory {}
+pub struct Memory {}
 
 #[cfg(test)]
 mod tests {
@@ -1,13 +1,46 @@
 use crate::bus::Bus;
 use crate::cpu::CPU;
+use crate::error::EmuError;
 
 pub struct NES {
     pub cpu: CPU<Bus>,
 }
 
+impl NES {
+    pub fn new(bus: Bus) -> Self {
+        NES { cpu: CPU::new(bus) }
+    }
+
+    /// Runs one CPU instruction, see `CPU::step`.
+    pub fn step(&mut self) -> u64 {
+        let cycles = self.cpu.step();
+        self.clock(cycles);
+        cycles
+    }
+
+    /// Runs one CPU instruction, reporting what went wrong instead of
+    /// ignoring it. See `CPU::try_step`.
+    pub fn try_step(&mut self) -> Result<u64, EmuError> {
+        // A fault can end a step that still spent cycles
+        let start = self.cpu.get_cycles();
+        let result = self.cpu.try_step();
+        self.clock(self.cpu.get_cycles() - start);
+        result
+    }
+
+    /// Catches the rest of the system up with the CPU.
+    fn clock(&mut self, cycles: u64) {
+        self.cpu.bus.clock(cycles);
+        self.cpu.nmi(self.cpu.bus.ppu.nmi_line());
+        self.cpu.irq(self.cpu.bus.irq_line());
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::bus::Bus;
+    use crate::bus::{Bus, Memory};
+    use crate::cartridge::Cartridge;
+    use crate::input::Buttons;
 
     use super::*;
     #[test]
@@ -15,4 +48,91 @@ mod tests {
         let cpu = CPU::new(Bus::new());
         let _ = NES { cpu };
     }
+
+    #[test]
+    fn try_step_surfaces_unmapped_access() {
+        let mut nes = NES::new(Bus::new());
+        nes.cpu.bus.ram[..6].copy_from_slice(&asm!("LDA $6000", "LDA $6000"));
+        nes.cpu.set_pc(0x0000);
+        assert_eq!(nes.try_step(), Ok(4), "open bus is legal by default");
+        nes.cpu.bus.set_strict_access(true);
+        assert_eq!(
+            nes.try_step(),
+            Err(EmuError::UnmappedRead { address: 0x6000 })
+        );
+        // The instruction still completed, loading the last operand byte left
+        // on the data bus, and the emulator can carry on
+        assert_eq!(nes.cpu.get_pc(), 0x0006);
+        assert_eq!(nes.cpu.get_a(), 0x60);
+        nes.cpu.bus.ram[6..7].copy_from_slice(&asm!("NOP"));
+        assert_eq!(nes.try_step(), Ok(2));
+    }
+
+    #[test]
+    fn oam_dma_copies_a_page_and_halts_the_cpu() {
+        let mut nes = NES::new(Bus::new());
+        for (i, byte) in nes.cpu.bus.ram[0x200..0x300].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let program = asm!(
+            ".org $0400",
+            "LDA #$02",
+            "STA $4014",
+            "LDA $10",
+            "STA $4014",
+            "NOP"
+        );
+        nes.cpu.bus.ram[0x400..0x400 + program.len()].copy_from_slice(&program);
+        nes.cpu.set_pc(0x0400);
+        assert_eq!(nes.step(), 2);
+        assert_eq!(nes.step(), 4);
+        // Halted on cycle 6, the first read of the copy waits for cycle 8
+        assert_eq!(nes.step(), 514 + 3);
+        assert_eq!(nes.cpu.bus.ppu.oam[..4], [0x00, 0x01, 0x02, 0x03]);
+        assert_eq!(nes.cpu.bus.ppu.oam[0xFF], 0xFF);
+        assert_eq!(nes.step(), 4);
+        // Halted on cycle 527, the copy starts reading right away
+        assert_eq!(nes.step(), 513 + 2);
+    }
+
+    #[test]
+    fn dmc_fetch_during_a_controller_read_drops_a_bit() {
+        let mut nes = NES::new(Bus::new());
+        nes.cpu.bus.ram[..3].copy_from_slice(&asm!("LDA $4016"));
+        nes.cpu.set_pc(0x0000);
+        nes.cpu.bus.input.set_buttons(0, Buttons::A);
+        nes.cpu.bus.write(0x4016, 1);
+        nes.cpu.bus.write(0x4016, 0);
+        for _ in 0..3 {
+            nes.cpu.tick();
+        }
+        // Enabling the DMC with an empty buffer requests its first byte
+        nes.cpu.bus.write(0x4015, 0x10);
+        nes.cpu.tick();
+        // The halted read shifted A out, the real one sees B
+        assert_eq!(nes.cpu.get_a() & 0x01, 0);
+        assert!(nes.cpu.at_instruction_boundary());
+    }
+
+    #[test]
+    fn runs_an_nrom_cartridge() {
+        let mut image = b"NES\x1A\x01\x01".to_vec();
+        image.resize(16, 0);
+        let program = asm!(".org $C000", "reset: LDA #$42", "STA $6000", "JMP *");
+        image.extend(&program);
+        image.resize(16 + 0x3FFC, 0);
+        image.extend([0x00, 0xC0, 0x00, 0x00]);
+        image.resize(16 + 0x4000 + 0x2000, 0);
+
+        let mapper = Cartridge::from_ines(&image).unwrap().into_mapper().unwrap();
+        let mut nes = NES::new(Bus::with_mapper(mapper));
+        nes.cpu.reset();
+        // Mirrored, the vector is read at $FFFC
+        assert_eq!(nes.cpu.get_pc(), 0xC000);
+        for _ in 0..3 {
+            nes.step();
+        }
+        assert_eq!(nes.cpu.bus.peek(0x6000), 0x42);
+        assert_eq!(nes.cpu.get_pc(), 0xC005);
+    }
 }
@@ -1,7 +1,652 @@
-struct PPU {}
+use crate::cartridge::{Mapper, Mirroring};
+
+const PPUCTRL: u16 = 0;
+const PPUMASK: u16 = 1;
+const PPUSTATUS: u16 = 2;
+const OAMADDR: u16 = 3;
+const OAMDATA: u16 = 4;
+const PPUSCROLL: u16 = 5;
+const PPUADDR: u16 = 6;
+const PPUDATA: u16 = 7;
+
+const VBLANK: u8 = 0x80;
+const CTRL_INCREMENT_32: u8 = 0x04;
+const CTRL_SPRITE_TABLE: u8 = 0x08;
+const CTRL_BG_TABLE: u8 = 0x10;
+const CTRL_SPRITE_16: u8 = 0x20;
+const CTRL_NMI_ENABLE: u8 = 0x80;
+const MASK_RENDERING: u8 = 0x18; // background or sprites
+
+const DOTS: u16 = 341;
+const VBLANK_LINE: u16 = 241;
+const PRE_RENDER_LINE: u16 = 261;
+
+/// The register interface of the 2C02 and the memory behind it.
+///
+/// `v`, `t`, `x` and `w` are the internal scroll registers as described on the
+/// nesdev wiki ("PPU scrolling").
+///
+/// The pattern tables at $0000-$1FFF are on the cartridge. Accesses that may
+/// reach them take the mapper, `None` leaves the PPU without a cartridge.
+/// Outside rendering the PPU keeps `v` on its address bus, so the mapper also
+/// sees every change of `v`.
+///
+/// `tick` runs the PPU one dot at a time. It keeps the frame timing and, while
+/// rendering is enabled, makes the background and sprite fetches of a real
+/// 2C02 in the right order, so boards that watch the PPU bus see the same
+/// addresses as on hardware. Pixels are not produced yet.
+pub struct PPU {
+    ctrl: u8,
+    mask: u8,
+    status: u8,
+    oam_addr: u8,
+    pub oam: [u8; 256],
+    v: u16,
+    t: u16,
+    x: u8,
+    w: bool,
+    read_buffer: u8,          // PPUDATA reads return the previous value
+    io_latch: u8,             // the PPU's own open bus, read back from write only registers
+    pub mirroring: Mirroring, // kept in sync with the mapper by the bus
+    pub vram: [u8; 0x1000],   // room for four screen nametables
+    pub palette: [u8; 32],
+    scanline: u16, // 0-239 visible, 241-260 vblank, 261 pre-render
+    dot: u16,
+    odd_frame: bool,
+    tile: u8,                  // latched by the nametable fetch
+    sprite_patterns: [u16; 8], // pattern addresses for the next line's sprites
+}
+
+impl PPU {
+    pub fn new() -> Self {
+        PPU {
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            oam: [0; 256],
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            read_buffer: 0,
+            io_latch: 0,
+            mirroring: Mirroring::Horizontal,
+            vram: [0; 0x1000],
+            palette: [0; 32],
+            scanline: 0,
+            dot: 0,
+            odd_frame: false,
+            tile: 0,
+            sprite_patterns: [0; 8],
+        }
+    }
+
+    pub fn get_ctrl(&self) -> u8 {
+        self.ctrl
+    }
+    pub fn get_mask(&self) -> u8 {
+        self.mask
+    }
+    pub fn get_status(&self) -> u8 {
+        self.status
+    }
+    pub fn get_vram_address(&self) -> u16 {
+        self.v
+    }
+    pub fn get_fine_x(&self) -> u8 {
+        self.x
+    }
+    pub fn get_scanline(&self) -> u16 {
+        self.scanline
+    }
+    pub fn get_dot(&self) -> u16 {
+        self.dot
+    }
+
+    pub fn set_vblank(&mut self, vblank: bool) {
+        if vblank {
+            self.status |= VBLANK;
+        } else {
+            self.status &= !VBLANK;
+        }
+    }
+
+    /// The /NMI output, asserted during vblank when PPUCTRL enables it.
+    pub fn nmi_line(&self) -> bool {
+        self.ctrl & CTRL_NMI_ENABLE != 0 && self.status & VBLANK != 0
+    }
+
+    /// Runs one dot. The timing follows the nesdev wiki's "PPU rendering"
+    /// frame diagram, including the dot skipped on odd frames.
+    pub fn tick(&mut self, mapper: Option<&mut (dyn Mapper + 'static)>) {
+        match (self.scanline, self.dot) {
+            (VBLANK_LINE, 1) => self.status |= VBLANK,
+            (PRE_RENDER_LINE, 1) => self.status &= !VBLANK,
+            _ => {}
+        }
+        let rendering = self.mask & MASK_RENDERING != 0;
+        if rendering && (self.scanline < 240 || self.scanline == PRE_RENDER_LINE) {
+            self.render_fetches(mapper);
+        }
+
+        self.dot += 1;
+        let skip = rendering && self.odd_frame && self.scanline == PRE_RENDER_LINE;
+        if self.dot == DOTS || (skip && self.dot == DOTS - 1) {
+            self.dot = 0;
+            if self.scanline == PRE_RENDER_LINE {
+                self.scanline = 0;
+                self.odd_frame = !self.odd_frame;
+            } else {
+                self.scanline += 1;
+            }
+        }
+    }
+
+    /// The memory accesses of the current dot on a rendering line. Each fetch
+    /// takes two dots; it is made on the first.
+    fn render_fetches(&mut self, mut mapper: Option<&mut (dyn Mapper + 'static)>) {
+        let dot = self.dot;
+        let nametable = 0x2000 | (self.v & 0x0FFF);
+        match dot {
+            1..=256 | 321..=336 => {
+                let fine_y = self.v >> 12;
+                let pattern = if self.ctrl & CTRL_BG_TABLE != 0 {
+                    0x1000
+                } else {
+                    0x0000
+                };
+                match (dot - 1) % 8 {
+                    0 => self.tile = self.fetch(nametable, mapper),
+                    2 => {
+                        let v = self.v;
+                        let attribute =
+                            0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+                        self.fetch(attribute, mapper);
+                    }
+                    4 => {
+                        self.fetch(pattern | (self.tile as u16) << 4 | fine_y, mapper);
+                    }
+                    6 => {
+                        self.fetch(pattern | (self.tile as u16) << 4 | fine_y | 8, mapper);
+                    }
+                    7 => self.increment_coarse_x(),
+                    _ => {}
+                }
+                if dot == 256 {
+                    self.increment_y();
+                }
+            }
+            257..=320 => {
+                if dot == 257 {
+                    self.v = (self.v & !0x041F) | (self.t & 0x041F);
+                    self.evaluate_sprites();
+                }
+                // Two garbage nametable fetches, then the sprite's pattern
+                let pattern = self.sprite_patterns[(dot as usize - 257) / 8];
+                match (dot - 257) % 8 {
+                    0 | 2 => {
+                        self.fetch(nametable, mapper.as_deref_mut());
+                    }
+                    4 => {
+                        self.fetch(pattern, mapper.as_deref_mut());
+                    }
+                    6 => {
+                        self.fetch(pattern | 8, mapper.as_deref_mut());
+                    }
+                    _ => {}
+                }
+                if self.scanline == PRE_RENDER_LINE && (280..=304).contains(&dot) {
+                    self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+                }
+            }
+            337 | 339 => {
+                self.fetch(nametable, mapper);
+            }
+            _ => {}
+        }
+    }
+
+    /// A rendering fetch, which the mapper sees like any other PPU access.
+    fn fetch(&mut self, address: u16, mut mapper: Option<&mut (dyn Mapper + 'static)>) -> u8 {
+        if let Some(mapper) = mapper.as_deref_mut() {
+            mapper.ppu_address(address);
+        }
+        self.read_memory(address, mapper)
+    }
+
+    /// Finds the first eight sprites on the next line. Empty slots fetch tile
+    /// $FF, as the real evaluation leaves $FF in secondary OAM.
+    fn evaluate_sprites(&mut self) {
+        let height = if self.ctrl & CTRL_SPRITE_16 != 0 {
+            16
+        } else {
+            8
+        };
+        let mut slots = [(0xFF, 0, 0); 8];
+        if self.scanline != PRE_RENDER_LINE {
+            let visible = self.oam.chunks_exact(4).filter_map(|sprite| {
+                let row = self.scanline.wrapping_sub(sprite[0] as u16);
+                (row < height).then_some((sprite[1], row, sprite[2]))
+            });
+            for (slot, sprite) in slots.iter_mut().zip(visible) {
+                *slot = sprite;
+            }
+        }
+        for (i, (tile, row, attributes)) in slots.into_iter().enumerate() {
+            self.sprite_patterns[i] = self.sprite_pattern(tile, row, attributes, height);
+        }
+    }
+
+    fn sprite_pattern(&self, tile: u8, row: u16, attributes: u8, height: u16) -> u16 {
+        let row = if attributes & 0x80 != 0 {
+            (height - 1).wrapping_sub(row) % height
+        } else {
+            row % height
+        };
+        let tile = tile as u16;
+        if height == 16 {
+            // Bit 0 of the tile picks the table, the bottom half is the next tile
+            ((tile & 0x01) << 12) | ((tile & 0xFE) << 4) | ((row & 0x08) << 1) | (row & 0x07)
+        } else {
+            let table = if self.ctrl & CTRL_SPRITE_TABLE != 0 {
+                0x1000
+            } else {
+                0x0000
+            };
+            table | (tile << 4) | row
+        }
+    }
+
+    fn increment_coarse_x(&mut self) {
+        if self.v & 0x001F == 31 {
+            self.v = (self.v & !0x001F) ^ 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let coarse_y = match (self.v & 0x03E0) >> 5 {
+            29 => {
+                self.v ^= 0x0800;
+                0
+            }
+            31 => 0,
+            y => y + 1,
+        };
+        self.v = (self.v & !0x03E0) | (coarse_y << 5);
+    }
+
+    /// CPU read of $2000-$2007, with the side effects of the access.
+    pub fn read_register(
+        &mut self,
+        address: u16,
+        mut mapper: Option<&mut (dyn Mapper + 'static)>,
+    ) -> u8 {
+        let value = match address & 0x0007 {
+            PPUSTATUS => {
+                let value = (self.status & 0xE0) | (self.io_latch & 0x1F);
+                self.status &= !VBLANK;
+                self.w = false;
+                value
+            }
+            OAMDATA => self.oam[self.oam_addr as usize],
+            PPUDATA => {
+                let address = self.v & 0x3FFF;
+                let value = if address >= 0x3F00 {
+                    // Palette reads are immediate, the buffer gets the nametable below
+                    self.read_buffer = self.peek_memory(address - 0x1000, None);
+                    (self.peek_memory(address, None) & 0x3F) | (self.io_latch & 0xC0)
+                } else {
+                    let value = self.read_buffer;
+                    self.read_buffer = self.read_memory(address, mapper.as_deref_mut());
+                    value
+                };
+                self.increment_v(mapper);
+                value
+            }
+            _ => return self.io_latch,
+        };
+        self.io_latch = value;
+        value
+    }
+
+    /// What `read_register` would return, without changing anything.
+    pub fn peek_register(&self, address: u16) -> u8 {
+        match address & 0x0007 {
+            PPUSTATUS => (self.status & 0xE0) | (self.io_latch & 0x1F),
+            OAMDATA => self.oam[self.oam_addr as usize],
+            PPUDATA => {
+                let address = self.v & 0x3FFF;
+                if address >= 0x3F00 {
+                    (self.peek_memory(address, None) & 0x3F) | (self.io_latch & 0xC0)
+                } else {
+                    self.read_buffer
+                }
+            }
+            _ => self.io_latch,
+        }
+    }
+
+    /// CPU write of $2000-$2007.
+    pub fn write_register(
+        &mut self,
+        address: u16,
+        value: u8,
+        mut mapper: Option<&mut (dyn Mapper + 'static)>,
+    ) {
+        self.io_latch = value;
+        match address & 0x0007 {
+            PPUCTRL => {
+                self.ctrl = value;
+                self.t = (self.t & 0xF3FF) | ((value as u16 & 0x03) << 10);
+            }
+            PPUMASK => self.mask = value,
+            PPUSTATUS => {}
+            OAMADDR => self.oam_addr = value,
+            OAMDATA => {
+                self.oam[self.oam_addr as usize] = value;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            }
+            PPUSCROLL => {
+                if !self.w {
+                    self.t = (self.t & !0x001F) | (value as u16 >> 3);
+                    self.x = value & 0x07;
+                } else {
+                    self.t = (self.t & 0x8C1F)
+                        | ((value as u16 & 0x07) << 12)
+                        | ((value as u16 & 0xF8) << 2);
+                }
+                self.w = !self.w;
+            }
+            PPUADDR => {
+                if !self.w {
+                    self.t = (self.t & 0x00FF) | ((value as u16 & 0x3F) << 8);
+                } else {
+                    self.t = (self.t & 0xFF00) | value as u16;
+                    self.set_v(self.t, mapper);
+                }
+                self.w = !self.w;
+            }
+            PPUDATA => {
+                self.write_memory(self.v & 0x3FFF, value, mapper.as_deref_mut());
+                self.increment_v(mapper);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn increment_v(&mut self, mapper: Option<&mut (dyn Mapper + 'static)>) {
+        let step = if self.ctrl & CTRL_INCREMENT_32 != 0 {
+            32
+        } else {
+            1
+        };
+        self.set_v(self.v.wrapping_add(step) & 0x7FFF, mapper);
+    }
+
+    fn set_v(&mut self, v: u16, mapper: Option<&mut (dyn Mapper + 'static)>) {
+        self.v = v;
+        if let Some(mapper) = mapper {
+            mapper.ppu_address(v & 0x3FFF);
+        }
+    }
+
+    /// Reads the PPU address space, $0000-$3FFF. Without a cartridge the
+    /// pattern tables read back the low address byte the PPU left on its
+    /// multiplexed data bus.
+    pub fn peek_memory(&self, address: u16, mapper: Option<&dyn Mapper>) -> u8 {
+        match address & 0x3FFF {
+            address @ 0x0000..=0x1FFF => mapper.map_or(address as u8, |m| m.ppu_peek(address)),
+            address @ 0x2000..=0x3EFF => mapper
+                .and_then(|m| m.nametable_peek(0x2000 | (address & 0x0FFF)))
+                .unwrap_or(self.vram[self.nametable_index(address)]),
+            address => self.palette[palette_index(address)],
+        }
+    }
+
+    /// Like `peek_memory`, letting the mapper see the read.
+    fn read_memory(&self, address: u16, mapper: Option<&mut (dyn Mapper + 'static)>) -> u8 {
+        match (address & 0x3FFF, mapper) {
+            (address @ 0x0000..=0x1FFF, Some(mapper)) => mapper.ppu_read(address),
+            (address @ 0x2000..=0x3EFF, Some(mapper)) => mapper
+                .nametable_read(0x2000 | (address & 0x0FFF))
+                .unwrap_or(self.vram[self.nametable_index(address)]),
+            (address, _) => self.peek_memory(address, None),
+        }
+    }
+
+    pub fn write_memory(
+        &mut self,
+        address: u16,
+        value: u8,
+        mapper: Option<&mut (dyn Mapper + 'static)>,
+    ) {
+        match address & 0x3FFF {
+            address @ 0x0000..=0x1FFF => {
+                if let Some(mapper) = mapper {
+                    mapper.ppu_write(address, value);
+                }
+            }
+            address @ 0x2000..=0x3EFF => {
+                if !mapper.is_some_and(|m| m.nametable_write(0x2000 | (address & 0x0FFF), value)) {
+                    let index = self.nametable_index(address);
+                    self.vram[index] = value;
+                }
+            }
+            address => self.palette[palette_index(address)] = value,
+        }
+    }
+
+    fn nametable_index(&self, address: u16) -> usize {
+        let table = (address as usize >> 10) & 0x03;
+        let page = match self.mirroring {
+            Mirroring::Horizontal => table >> 1,
+            Mirroring::Vertical => table & 0x01,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => table,
+            Mirroring::Custom(pages) => pages[table] as usize & 0x03,
+        };
+        page * 0x400 + (address as usize & 0x03FF)
+    }
+}
+
+impl Default for PPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// $3F10/$3F14/$3F18/$3F1C mirror the backdrop entries below them.
+fn palette_index(address: u16) -> usize {
+    let index = address as usize & 0x1F;
+    if index & 0x13 == 0x10 {
+        index & 0x0F
+    } else {
+        index
+    }
+}
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    fn set_address(ppu: &mut PPU, address: u16) {
+        ppu.write_register(PPUADDR, (address >> 8) as u8, None);
+        ppu.write_register(PPUADDR, address as u8, None);
+    }
+
+    #[test]
+    fn construct_ppu() {
+        let ppu = PPU::new();
+        assert_eq!(ppu.get_status(), 0);
+    }
+
+    #[test]
+    fn status_read_clears_vblank_and_latch() {
+        let mut ppu = PPU::new();
+        ppu.set_vblank(true);
+        ppu.write_register(PPUADDR, 0x21, None);
+        assert_eq!(ppu.peek_register(PPUSTATUS) & VBLANK, VBLANK);
+        assert_eq!(ppu.read_register(PPUSTATUS, None) & VBLANK, VBLANK);
+        assert_eq!(ppu.read_register(PPUSTATUS, None) & VBLANK, 0);
+        // The latch was reset, so this is a first write again
+        set_address(&mut ppu, 0x2400);
+        assert_eq!(ppu.get_vram_address(), 0x2400);
+    }
+
+    #[test]
+    fn status_low_bits_come_from_the_io_latch() {
+        let mut ppu = PPU::new();
+        ppu.write_register(PPUMASK, 0x1F, None);
+        assert_eq!(ppu.read_register(PPUSTATUS, None), 0x1F);
+        assert_eq!(ppu.read_register(PPUCTRL, None), 0x1F);
+    }
+
+    #[test]
+    fn data_reads_are_buffered_and_advance_v() {
+        let mut ppu = PPU::new();
+        ppu.vram[0x000] = 0x11;
+        ppu.vram[0x001] = 0x22;
+        set_address(&mut ppu, 0x2000);
+        assert_eq!(ppu.peek_register(PPUDATA), 0x00);
+        assert_eq!(ppu.get_vram_address(), 0x2000);
+        assert_eq!(ppu.read_register(PPUDATA, None), 0x00);
+        assert_eq!(ppu.read_register(PPUDATA, None), 0x11);
+        assert_eq!(ppu.read_register(PPUDATA, None), 0x22);
+        assert_eq!(ppu.get_vram_address(), 0x2003);
+    }
+
+    #[test]
+    fn palette_reads_are_immediate() {
+        let mut ppu = PPU::new();
+        ppu.palette[0x01] = 0x2A;
+        ppu.vram[0x701] = 0x55; // $2F01, under $3F01 with horizontal mirroring
+        set_address(&mut ppu, 0x3F01);
+        assert_eq!(ppu.read_register(PPUDATA, None), 0x2A);
+        set_address(&mut ppu, 0x0000);
+        assert_eq!(ppu.read_register(PPUDATA, None), 0x55);
+    }
+
+    #[test]
+    fn data_writes_increment_by_32_when_selected() {
+        let mut ppu = PPU::new();
+        ppu.write_register(PPUCTRL, CTRL_INCREMENT_32, None);
+        set_address(&mut ppu, 0x2000);
+        ppu.write_register(PPUDATA, 0x01, None);
+        ppu.write_register(PPUDATA, 0x02, None);
+        assert_eq!((ppu.vram[0x000], ppu.vram[0x020]), (0x01, 0x02));
+        assert_eq!(ppu.get_vram_address(), 0x2040);
+    }
+
+    #[test]
+    fn scroll_writes_fill_t_and_fine_x() {
+        let mut ppu = PPU::new();
+        ppu.write_register(PPUCTRL, 0x01, None);
+        ppu.write_register(PPUSCROLL, 0x7D, None); // coarse X 15, fine X 5
+        ppu.write_register(PPUSCROLL, 0x5E, None); // coarse Y 11, fine Y 6
+        assert_eq!(ppu.get_fine_x(), 5);
+        // fine Y, nametable, coarse Y, coarse X
+        assert_eq!(ppu.t, (6 << 12) | (1 << 10) | (11 << 5) | 15);
+    }
+
+    #[test]
+    fn nametables_follow_mirroring() {
+        let mut ppu = PPU::new();
+        ppu.mirroring = Mirroring::Vertical;
+        ppu.write_memory(0x2000, 0x01, None);
+        assert_eq!(ppu.peek_memory(0x2800, None), 0x01);
+        assert_eq!(ppu.peek_memory(0x2400, None), 0x00);
+        ppu.mirroring = Mirroring::Horizontal;
+        assert_eq!(ppu.peek_memory(0x2400, None), 0x01);
+        assert_eq!(ppu.peek_memory(0x3000, None), 0x01); // $3000-$3EFF mirrors $2000
+    }
+
+    #[test]
+    fn palette_backdrop_mirrors() {
+        let mut ppu = PPU::new();
+        ppu.write_memory(0x3F10, 0x0F, None);
+        assert_eq!(ppu.peek_memory(0x3F00, None), 0x0F);
+        assert_eq!(ppu.peek_memory(0x3F30, None), 0x0F);
+    }
+
+    #[test]
+    fn oam_data_writes_increment_address() {
+        let mut ppu = PPU::new();
+        ppu.write_register(OAMADDR, 0xFF, None);
+        ppu.write_register(OAMDATA, 0x12, None);
+        ppu.write_register(OAMDATA, 0x34, None);
+        assert_eq!((ppu.oam[0xFF], ppu.oam[0x00]), (0x12, 0x34));
+        assert_eq!(ppu.read_register(OAMDATA, None), ppu.oam[0x01]);
+    }
+
+    #[test]
+    fn nmi_needs_vblank_and_enable() {
+        let mut ppu = PPU::new();
+        ppu.set_vblank(true);
+        assert!(!ppu.nmi_line());
+        ppu.write_register(PPUCTRL, CTRL_NMI_ENABLE, None);
+        assert!(ppu.nmi_line());
+    }
+
+    /// Dots until the PPU is at `scanline` and `dot`.
+    fn run_to(ppu: &mut PPU, scanline: u16, dot: u16) -> u32 {
+        let mut dots = 0;
+        while (ppu.get_scanline(), ppu.get_dot()) != (scanline, dot) {
+            ppu.tick(None);
+            dots += 1;
+        }
+        dots
+    }
+
+    #[test]
+    fn vblank_spans_lines_241_to_261() {
+        let mut ppu = PPU::new();
+        ppu.write_register(PPUCTRL, CTRL_NMI_ENABLE, None);
+        run_to(&mut ppu, 241, 1);
+        assert!(!ppu.nmi_line());
+        ppu.tick(None);
+        assert!(ppu.nmi_line());
+        run_to(&mut ppu, 261, 2);
+        assert_eq!(ppu.get_status() & VBLANK, 0);
+    }
+
+    #[test]
+    fn odd_frames_skip_a_dot_while_rendering() {
+        let mut ppu = PPU::new();
+        assert_eq!(run_to(&mut ppu, 0, 1), 1);
+        // Not rendering: every frame is 262 lines of 341 dots
+        assert_eq!(run_to(&mut ppu, 0, 0), 262 * 341 - 1);
+        assert_eq!(run_to(&mut ppu, 1, 0) + run_to(&mut ppu, 0, 0), 262 * 341);
+        ppu.write_register(PPUMASK, 0x08, None);
+        assert_eq!(run_to(&mut ppu, 1, 0) + run_to(&mut ppu, 0, 0), 262 * 341);
+        assert_eq!(
+            run_to(&mut ppu, 1, 0) + run_to(&mut ppu, 0, 0),
+            262 * 341 - 1
+        );
+    }
+
     #[test]
-    fn construct_ppu() {}
+    fn rendering_copies_t_to_v() {
+        let mut ppu = PPU::new();
+        ppu.write_register(PPUCTRL, 0x01, None);
+        ppu.write_register(PPUSCROLL, 0x7D, None);
+        ppu.write_register(PPUSCROLL, 0x5E, None);
+        ppu.write_register(PPUMASK, 0x08, None);
+        run_to(&mut ppu, 261, 305);
+        assert_eq!(ppu.get_vram_address(), ppu.t);
+        // Two tiles fetched at the end of the pre-render line
+        run_to(&mut ppu, 0, 0);
+        assert_eq!(ppu.get_vram_address(), ppu.t + 2);
+        // The next line starts at the same X, one pixel down
+        run_to(&mut ppu, 1, 0);
+        assert_eq!(ppu.get_vram_address(), ppu.t + 0x1000 + 2);
+    }
 }
@@ -0,0 +1,194 @@
+//! nestest CPU conformance run.
+//!
+//! Runs nestest.nes in automation mode (PC = $C000) and compares a
+//! Nintendulator style trace line against the reference log before every
+//! instruction. The ROM and log are not redistributable, so they are read from
+//! `tests/fixtures/nestest.nes` and `tests/fixtures/nestest.log`. The test is
+//! ignored by default, run it with `cargo test --test nestest -- --ignored`;
+//! it fails when the fixtures are missing.
+
+use std::collections::VecDeque;
+use std::fs;
+
+use nes_emu::bus::Memory;
+use nes_emu::cpu::opcodes::{AddressingMode, Mnemonic};
+use nes_emu::cpu::{CPU, Instruction, disassemble};
+
+const ROM_PATH: &str = "tests/fixtures/nestest.nes";
+const LOG_PATH: &str = "tests/fixtures/nestest.log";
+const CONTEXT_LINES: usize = 5;
+
+/// Just enough of the NES memory map to run nestest: mirrored RAM and a
+/// 16KB NROM PRG bank mirrored at $8000 and $C000. The PPU and APU registers
+/// read back as $FF, as they do in the reference log.
+struct NestestBus {
+    ram: [u8; 0x800],
+    prg: Vec<u8>,
+}
+
+impl NestestBus {
+    fn from_ines(rom: &[u8]) -> Self {
+        assert_eq!(&rom[0..4], b"NES\x1A", "not an iNES file");
+        let prg_size = rom[4] as usize * 0x4000;
+        let trainer = if rom[6] & 0x04 != 0 { 512 } else { 0 };
+        let start = 16 + trainer;
+        NestestBus {
+            ram: [0; 0x800],
+            prg: rom[start..start + prg_size].to_vec(),
+        }
+    }
+}
+
+impl Memory for NestestBus {
+    fn read(&mut self, address: u16) -> u8 {
+        self.peek(address)
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.ram[(address & 0x07FF) as usize],
+            0x8000..=0xFFFF => self.prg[(address as usize - 0x8000) % self.prg.len()],
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address < 0x2000 {
+            self.ram[(address & 0x07FF) as usize] = value;
+        }
+    }
+}
+
+fn peek_u16_zero_page(bus: &NestestBus, pointer: u8) -> u16 {
+    u16::from_le_bytes([
+        bus.peek(pointer as u16),
+        bus.peek(pointer.wrapping_add(1) as u16),
+    ])
+}
+
+/// Disassembly column of the trace: the crate's own disassembly, followed by
+/// the memory annotations Nintendulator adds.
+fn disassembly_column(cpu: &CPU<NestestBus>, instruction: &Instruction) -> String {
+    use AddressingMode::*;
+
+    let bus = &cpu.bus;
+    let operand = instruction.operand;
+    let lo = operand as u8;
+    let index = |mode| {
+        if mode == ZeroPageX || mode == AbsoluteX {
+            cpu.get_x()
+        } else {
+            cpu.get_y()
+        }
+    };
+    let annotation = match instruction.mode {
+        Implied | Accumulator | Immediate | Relative => None,
+        ZeroPage => Some(format!("= {:02X}", bus.peek(lo as u16))),
+        ZeroPageX | ZeroPageY => {
+            let address = lo.wrapping_add(index(instruction.mode));
+            Some(format!(
+                "@ {address:02X} = {:02X}",
+                bus.peek(address as u16)
+            ))
+        }
+        Absolute => match instruction.mnemonic {
+            Mnemonic::JMP | Mnemonic::JSR => None,
+            _ => Some(format!("= {:02X}", bus.peek(operand))),
+        },
+        AbsoluteX | AbsoluteY => {
+            let address = operand.wrapping_add(index(instruction.mode) as u16);
+            Some(format!("@ {address:04X} = {:02X}", bus.peek(address)))
+        }
+        Indirect => {
+            let wrapped = (operand & 0xFF00) | (operand.wrapping_add(1) & 0x00FF);
+            let target = u16::from_le_bytes([bus.peek(operand), bus.peek(wrapped)]);
+            Some(format!("= {target:04X}"))
+        }
+        IndirectX => {
+            let pointer = lo.wrapping_add(cpu.get_x());
+            let address = peek_u16_zero_page(bus, pointer);
+            Some(format!(
+                "@ {pointer:02X} = {address:04X} = {:02X}",
+                bus.peek(address)
+            ))
+        }
+        IndirectY => {
+            let base = peek_u16_zero_page(bus, lo);
+            let address = base.wrapping_add(cpu.get_y() as u16);
+            Some(format!(
+                "= {base:04X} @ {address:04X} = {:02X}",
+                bus.peek(address)
+            ))
+        }
+    };
+
+    let mut text = instruction.to_string();
+    // Nintendulator names ISC differently
+    if instruction.mnemonic == Mnemonic::ISC {
+        text.replace_range(..3, "ISB");
+    }
+    match annotation {
+        Some(annotation) => format!("{text} {annotation}"),
+        None => text,
+    }
+}
+
+fn trace_line(cpu: &CPU<NestestBus>) -> String {
+    let pc = cpu.get_pc();
+    let (instruction, len) = disassemble(&cpu.bus, pc);
+    let bytes = (0..len)
+        .map(|i| format!("{:02X}", cpu.bus.peek(pc.wrapping_add(i))))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let unofficial = if instruction.is_official() { ' ' } else { '*' };
+
+    // Three PPU dots per CPU cycle, 341 dots per scanline
+    let dots = cpu.get_cycles() * 3;
+    let scanline = (dots / 341) % 262;
+    let dot = dots % 341;
+
+    format!(
+        "{pc:04X}  {bytes:<8} {unofficial}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{scanline:>3},{dot:>3} CYC:{}",
+        disassembly_column(cpu, &instruction),
+        cpu.get_a(),
+        cpu.get_x(),
+        cpu.get_y(),
+        cpu.get_p(),
+        cpu.get_sp(),
+        cpu.get_cycles(),
+    )
+}
+
+#[test]
+#[ignore = "needs tests/fixtures/nestest.nes and nestest.log"]
+fn nestest_matches_golden_log() {
+    let rom = fs::read(ROM_PATH).unwrap_or_else(|error| panic!("{ROM_PATH}: {error}"));
+    let log = fs::read_to_string(LOG_PATH).unwrap_or_else(|error| panic!("{LOG_PATH}: {error}"));
+
+    let mut cpu = CPU::new(NestestBus::from_ines(&rom));
+    // Power up state from the log: 7 cycles of reset, then automation mode at $C000
+    cpu.reset();
+    cpu.set_pc(0xC000);
+    cpu.set_sp(0xFD);
+
+    let mut history: VecDeque<String> = VecDeque::with_capacity(CONTEXT_LINES);
+    for (number, expected) in log.lines().enumerate() {
+        let actual = trace_line(&cpu);
+        if actual != expected.trim_end() {
+            let context = history.iter().cloned().collect::<Vec<_>>().join("\n");
+            panic!(
+                "nestest diverged at line {}\n{context}\nexpected: {expected}\n  actual: {actual}",
+                number + 1
+            );
+        }
+        if history.len() == CONTEXT_LINES {
+            history.pop_front();
+        }
+        history.push_back(actual);
+        cpu.step();
+    }
+
+    // nestest reports failures in $02 (official) and $03 (unofficial opcodes)
+    assert_eq!(cpu.bus.peek(0x0002), 0x00, "official opcode error code");
+    assert_eq!(cpu.bus.peek(0x0003), 0x00, "unofficial opcode error code");
+}
@@ -0,0 +1,261 @@
+//! SingleStepTests CPU conformance run.
+//!
+//! Runs the per-opcode JSON vectors from Tom Harte's SingleStepTests
+//! (`65x02/nes6502/v1`, the 2A03 set without decimal mode) through `CPU::tick`
+//! on a flat 64KB bus that records every access. Each vector is checked for
+//! registers, memory and the exact cycle by cycle bus trace.
+//!
+//! The vectors are large and not vendored. They are read from the directory
+//! in `SINGLE_STEP_TESTS`, or `tests/fixtures/nes6502`. The test is ignored
+//! by default and fails when the vectors are missing. Run it with
+//! `cargo test --release --test single_step -- --ignored --nocapture` to see
+//! the summary table.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use nes_emu::bus::Memory;
+use nes_emu::cpu::CPU;
+use nes_emu::cpu::opcodes::{Mnemonic, OPCODES};
+
+const DEFAULT_DIR: &str = "tests/fixtures/nes6502";
+
+#[derive(Deserialize)]
+struct TestCase {
+    name: String,
+    initial: State,
+    #[serde(rename = "final")]
+    expected: State,
+    cycles: Vec<(u16, u8, String)>,
+}
+
+#[derive(Deserialize)]
+struct State {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum BusCycle {
+    Read(u16, u8),
+    Write(u16, u8),
+}
+
+impl BusCycle {
+    fn from_json((address, value, kind): &(u16, u8, String)) -> Self {
+        match kind.as_str() {
+            "read" => BusCycle::Read(*address, *value),
+            "write" => BusCycle::Write(*address, *value),
+            other => panic!("unknown bus cycle kind {other:?}"),
+        }
+    }
+
+    fn address(&self) -> u16 {
+        match *self {
+            BusCycle::Read(address, _) | BusCycle::Write(address, _) => address,
+        }
+    }
+}
+
+/// Flat 64KB of RAM that logs every read and write.
+struct RecordingBus {
+    mem: Vec<u8>,
+    log: Vec<BusCycle>,
+}
+
+impl RecordingBus {
+    fn new() -> Self {
+        RecordingBus {
+            mem: vec![0; 0x10000],
+            log: Vec::new(),
+        }
+    }
+
+    /// Zeroes everything a test case touched so the bus can be reused.
+    fn clear(&mut self, case: &TestCase) {
+        let touched = case.initial.ram.iter().chain(&case.expected.ram);
+        for &(address, _) in touched {
+            self.mem[address as usize] = 0;
+        }
+        for cycle in self.log.drain(..) {
+            self.mem[cycle.address() as usize] = 0;
+        }
+    }
+}
+
+impl Memory for RecordingBus {
+    fn read(&mut self, address: u16) -> u8 {
+        let value = self.mem[address as usize];
+        self.log.push(BusCycle::Read(address, value));
+        value
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.mem[address as usize]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.log.push(BusCycle::Write(address, value));
+        self.mem[address as usize] = value;
+    }
+}
+
+/// Runs one vector and describes the first difference, if any.
+fn run_case(bus: RecordingBus, case: &TestCase) -> (RecordingBus, Result<(), String>) {
+    let mut cpu = CPU::new(bus);
+    let start = &case.initial;
+    for &(address, value) in &start.ram {
+        cpu.bus.mem[address as usize] = value;
+    }
+    cpu.set_pc(start.pc);
+    cpu.set_sp(start.s);
+    cpu.set_a(start.a);
+    cpu.set_x(start.x);
+    cpu.set_y(start.y);
+    cpu.set_p(start.p);
+
+    cpu.tick();
+    while !cpu.at_instruction_boundary() {
+        cpu.tick();
+    }
+
+    let result = check(&cpu, case);
+    (cpu.bus, result)
+}
+
+fn check(cpu: &CPU<RecordingBus>, case: &TestCase) -> Result<(), String> {
+    let end = &case.expected;
+    let registers = [
+        ("PC", cpu.get_pc(), end.pc),
+        ("SP", cpu.get_sp() as u16, end.s as u16),
+        ("A", cpu.get_a() as u16, end.a as u16),
+        ("X", cpu.get_x() as u16, end.x as u16),
+        ("Y", cpu.get_y() as u16, end.y as u16),
+        ("P", cpu.get_p() as u16, end.p as u16),
+    ];
+    for (register, actual, expected) in registers {
+        if actual != expected {
+            return Err(format!(
+                "{}: {register} is ${actual:02X}, expected ${expected:02X}",
+                case.name
+            ));
+        }
+    }
+
+    for &(address, expected) in &end.ram {
+        let actual = cpu.bus.mem[address as usize];
+        if actual != expected {
+            return Err(format!(
+                "{}: ${address:04X} is ${actual:02X}, expected ${expected:02X}",
+                case.name
+            ));
+        }
+    }
+
+    let expected = case
+        .cycles
+        .iter()
+        .map(BusCycle::from_json)
+        .collect::<Vec<_>>();
+    let actual = &cpu.bus.log;
+    if *actual != expected {
+        return Err(format!(
+            "{}: bus trace {actual:?}, expected {expected:?}",
+            case.name
+        ));
+    }
+    Ok(())
+}
+
+struct OpcodeReport {
+    opcode: u8,
+    passed: usize,
+    total: usize,
+    first_failure: Option<String>,
+}
+
+fn test_dir() -> PathBuf {
+    let dir = env::var_os("SINGLE_STEP_TESTS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_DIR));
+    assert!(
+        dir.is_dir(),
+        "{} is missing: set SINGLE_STEP_TESTS or populate {DEFAULT_DIR}",
+        dir.display()
+    );
+    dir
+}
+
+fn run_opcode(dir: &Path, opcode: u8) -> Option<OpcodeReport> {
+    let path = dir.join(format!("{opcode:02x}.json"));
+    let json = fs::read_to_string(&path).ok()?;
+    let cases: Vec<TestCase> = serde_json::from_str(&json)
+        .unwrap_or_else(|e| panic!("failed to parse {}: {e}", path.display()));
+
+    let mut report = OpcodeReport {
+        opcode,
+        passed: 0,
+        total: cases.len(),
+        first_failure: None,
+    };
+    let mut bus = RecordingBus::new();
+    for case in &cases {
+        let (mut returned, result) = run_case(bus, case);
+        match result {
+            Ok(()) => report.passed += 1,
+            Err(failure) => {
+                report.first_failure.get_or_insert(failure);
+            }
+        }
+        returned.clear(case);
+        bus = returned;
+    }
+    Some(report)
+}
+
+fn print_summary(reports: &[OpcodeReport]) {
+    println!("opcode  mnemonic  mode         passed");
+    for report in reports {
+        let op = OPCODES[report.opcode as usize];
+        let mnemonic = format!("{:?}", op.mnemonic);
+        let mode = format!("{:?}", op.mode);
+        println!(
+            "${:02X}     {mnemonic:<8}  {mode:<11}  {}/{}",
+            report.opcode, report.passed, report.total
+        );
+    }
+}
+
+#[test]
+#[ignore = "needs the SingleStepTests vectors, see SINGLE_STEP_TESTS"]
+fn single_step_tests() {
+    let dir = test_dir();
+
+    // KIL halts the CPU, and the vectors record the bus activity of a real
+    // jammed 6502, which this emulator does not model.
+    let reports = (0..=0xFFu8)
+        .filter(|&opcode| OPCODES[opcode as usize].mnemonic != Mnemonic::KIL)
+        .filter_map(|opcode| run_opcode(&dir, opcode))
+        .collect::<Vec<_>>();
+    assert!(!reports.is_empty(), "no vectors found in {}", dir.display());
+    print_summary(&reports);
+
+    let failures = reports
+        .iter()
+        .filter_map(|report| report.first_failure.as_deref())
+        .collect::<Vec<_>>();
+    assert!(
+        failures.is_empty(),
+        "{} opcodes failed, first failure of each:\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}